@@ -0,0 +1,171 @@
+use crate::config::{AbTestConfig, AbTestKey, AbTestVariant};
+use hyper::header::{COOKIE, HeaderMap, HeaderValue};
+use rand::Rng;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Variant a request is sent to and the cookie making the choice sticky, `None` when the client
+/// already holds it.
+pub struct AbTestAssignment<'a> {
+    pub variant: &'a AbTestVariant,
+    pub set_cookie: Option<HeaderValue>,
+}
+
+/// Keeps the variant named by the client's cookie, a cookie naming a removed variant falls back to
+/// a fresh assignment. Clients without one are assigned by the hash of their key so the same user
+/// lands on the same variant on every instance, or at random without a key.
+pub fn assign<'a>(config: &'a AbTestConfig, headers: &HeaderMap) -> AbTestAssignment<'a> {
+    if let Some(variant) = cookie(headers, &config.cookie)
+        .and_then(|name| config.variants.iter().find(|variant| variant.name == name))
+    {
+        return AbTestAssignment {
+            variant,
+            set_cookie: None,
+        };
+    }
+
+    let total_weight = config
+        .variants
+        .iter()
+        .map(|variant| u64::from(variant.weight))
+        .sum::<u64>();
+    let key = config.key.as_ref().and_then(|key| match key {
+        AbTestKey::Header(name) => headers.get(name).and_then(|value| value.to_str().ok()),
+        AbTestKey::Cookie(name) => cookie(headers, name),
+    });
+    let bucket = match key {
+        Some(key) => key_hash(key) % total_weight,
+        None => rand::rng().random_range(0..total_weight),
+    };
+    let variant = pick(&config.variants, bucket);
+
+    let mut cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax",
+        config.cookie, variant.name
+    );
+    if let Some(max_age) = config.cookie_max_age {
+        cookie.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+    }
+    AbTestAssignment {
+        variant,
+        // Names are validated as cookie tokens on load
+        set_cookie: HeaderValue::from_str(&cookie).ok(),
+    }
+}
+
+fn key_hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Variant whose cumulative weight range holds `bucket`.
+fn pick(variants: &[AbTestVariant], mut bucket: u64) -> &AbTestVariant {
+    for variant in variants {
+        let weight = u64::from(variant.weight);
+        if bucket < weight {
+            return variant;
+        }
+        bucket -= weight;
+    }
+    &variants[variants.len() - 1]
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find_map(|(cookie, value)| (cookie == name).then_some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    fn config() -> AbTestConfig {
+        AbTestConfig {
+            variants: ["control", "blue", "green"]
+                .into_iter()
+                .map(|name| AbTestVariant {
+                    name: String::from(name),
+                    service: format!("{name}-service"),
+                    weight: 1,
+                })
+                .collect(),
+            key: Some(AbTestKey::Header(String::from("x-user-id"))),
+            cookie: String::from("portiq_ab"),
+            cookie_max_age: Some(Duration::from_secs(3600)),
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_same_key_lands_on_same_variant() {
+        let config = config();
+        let mut assigned = HashSet::new();
+        for user in 0..100 {
+            let user = format!("user-{user}");
+            let first = assign(&config, &headers(&[("x-user-id", &user)]));
+            for _ in 0..5 {
+                let again = assign(&config, &headers(&[("x-user-id", &user)]));
+                assert_eq!(again.variant.name, first.variant.name);
+            }
+            assert_eq!(
+                first.set_cookie.unwrap(),
+                format!(
+                    "portiq_ab={}; Path=/; HttpOnly; SameSite=Lax; Max-Age=3600",
+                    first.variant.name
+                )
+            );
+            assigned.insert(first.variant.name.clone());
+        }
+        // Keys spread over every variant
+        assert_eq!(assigned.len(), 3);
+    }
+
+    #[test]
+    fn test_cookie_wins_over_key_unless_stale() {
+        let config = config();
+        let by_key = assign(&config, &headers(&[("x-user-id", "user-1")]));
+        let other = config
+            .variants
+            .iter()
+            .find(|variant| variant.name != by_key.variant.name)
+            .unwrap();
+
+        let cookie = format!("theme=dark; portiq_ab={}", other.name);
+        let sticky = assign(
+            &config,
+            &headers(&[("x-user-id", "user-1"), ("cookie", &cookie)]),
+        );
+        assert_eq!(sticky.variant.name, other.name);
+        assert!(sticky.set_cookie.is_none());
+
+        let stale = assign(
+            &config,
+            &headers(&[("x-user-id", "user-1"), ("cookie", "portiq_ab=removed")]),
+        );
+        assert_eq!(stale.variant.name, by_key.variant.name);
+        assert!(stale.set_cookie.is_some());
+    }
+
+    #[test]
+    fn test_weights_bound_the_buckets() {
+        let mut config = config();
+        config.variants[0].weight = 2;
+        let names = (0..4)
+            .map(|bucket| pick(&config.variants, bucket).name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["control", "control", "blue", "green"]);
+    }
+}
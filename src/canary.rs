@@ -0,0 +1,199 @@
+use crate::config::{CanaryConfig, CanaryRollbackConfig};
+use rand::Rng;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Instant, SystemTime};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CanarySnapshot {
+    pub service: String,
+    pub weight: u8,
+    #[serde(with = "humantime_serde")]
+    pub rolled_back_at: Option<SystemTime>,
+}
+
+struct ErrorWindow {
+    start: Instant,
+    requests: u32,
+    errors: u32,
+}
+
+impl ErrorWindow {
+    fn reset(&mut self, now: Instant) {
+        self.start = now;
+        self.requests = 0;
+        self.errors = 0;
+    }
+}
+
+/// Live weight of a route's canary with the error rate of its current window.
+pub struct Canary {
+    /// Weight of the config it was created from, a reload changing it overrides the live weight
+    configured_weight: u8,
+    weight: AtomicU8,
+    window: Mutex<ErrorWindow>,
+    rolled_back_at: Mutex<Option<SystemTime>>,
+}
+
+impl Canary {
+    fn new(configured_weight: u8) -> Self {
+        Canary {
+            configured_weight,
+            weight: AtomicU8::new(configured_weight),
+            window: Mutex::new(ErrorWindow {
+                start: Instant::now(),
+                requests: 0,
+                errors: 0,
+            }),
+            rolled_back_at: Mutex::new(None),
+        }
+    }
+
+    /// Whether a request goes to the canary service.
+    pub fn pick(&self) -> bool {
+        let weight = self.weight.load(Ordering::Relaxed);
+        weight > 0 && rand::rng().random_range(0..100) < weight
+    }
+
+    pub fn set_weight(&self, weight: u8) {
+        self.weight.store(weight, Ordering::Relaxed);
+        self.window.lock().unwrap().reset(Instant::now());
+    }
+
+    /// Records a response of the canary service, rolling the canary back once its error rate
+    /// over a full enough window reaches the threshold.
+    pub fn record(&self, rollback: &CanaryRollbackConfig, failed: bool, now: Instant) {
+        let mut window = self.window.lock().unwrap();
+        // Responses to requests sent before a rollback may still arrive
+        if self.weight.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        if now.duration_since(window.start) >= rollback.window {
+            window.reset(now);
+        }
+        window.requests += 1;
+        if failed {
+            window.errors += 1;
+        }
+        if window.requests >= rollback.min_requests
+            && f64::from(window.errors) >= rollback.error_threshold * f64::from(window.requests)
+        {
+            tracing::warn!(
+                "Rolling back canary after {} failed of {} responses",
+                window.errors,
+                window.requests
+            );
+            self.weight.store(0, Ordering::Relaxed);
+            window.reset(now);
+            *self.rolled_back_at.lock().unwrap() = Some(SystemTime::now());
+        }
+    }
+
+    pub fn snapshot(&self, service: &str) -> CanarySnapshot {
+        CanarySnapshot {
+            service: service.to_string(),
+            weight: self.weight.load(Ordering::Relaxed),
+            rolled_back_at: *self.rolled_back_at.lock().unwrap(),
+        }
+    }
+}
+
+/// Process wide canaries of named routes, their live weight survives config reloads.
+pub struct Canaries {
+    routes: RwLock<HashMap<String, Arc<Canary>>>,
+}
+
+impl Canaries {
+    pub fn new() -> Self {
+        Canaries {
+            routes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn canary_for(&self, route: &str, config: &CanaryConfig) -> Arc<Canary> {
+        if let Some(canary) = self.routes.read().unwrap().get(route)
+            && canary.configured_weight == config.weight
+        {
+            return canary.clone();
+        }
+        let mut routes = self.routes.write().unwrap();
+        match routes.get(route) {
+            Some(canary) if canary.configured_weight == config.weight => canary.clone(),
+            _ => {
+                let canary = Arc::new(Canary::new(config.weight));
+                routes.insert(route.to_string(), canary.clone());
+                canary
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn rollback() -> CanaryRollbackConfig {
+        CanaryRollbackConfig {
+            error_threshold: 0.5,
+            window: Duration::from_secs(60),
+            min_requests: 4,
+        }
+    }
+
+    #[test]
+    fn test_erroring_canary_is_rolled_back() {
+        let canary = Canary::new(20);
+        let start = Instant::now();
+        for failed in [true, false, true] {
+            canary.record(&rollback(), failed, start);
+        }
+        assert_eq!(canary.snapshot("v2").weight, 20);
+        canary.record(&rollback(), true, start);
+
+        let snapshot = canary.snapshot("v2");
+        assert_eq!(snapshot.weight, 0);
+        assert!(snapshot.rolled_back_at.is_some());
+        assert!((0..100).all(|_| !canary.pick()));
+    }
+
+    #[test]
+    fn test_errors_of_expired_window_are_forgotten() {
+        let canary = Canary::new(20);
+        let start = Instant::now();
+        for _ in 0..3 {
+            canary.record(&rollback(), true, start);
+        }
+        for _ in 0..3 {
+            canary.record(&rollback(), false, start + Duration::from_secs(60));
+        }
+        canary.record(&rollback(), true, start + Duration::from_secs(61));
+        assert_eq!(canary.snapshot("v2").weight, 20);
+    }
+
+    #[test]
+    fn test_live_weight_survives_unchanged_config() {
+        let canaries = Canaries::new();
+        let config = CanaryConfig {
+            service: String::from("v2"),
+            weight: 10,
+            rollback: None,
+        };
+        canaries.canary_for("orders", &config).set_weight(50);
+        assert_eq!(
+            canaries.canary_for("orders", &config).snapshot("v2").weight,
+            50
+        );
+
+        let config = CanaryConfig {
+            weight: 5,
+            ..config
+        };
+        assert_eq!(
+            canaries.canary_for("orders", &config).snapshot("v2").weight,
+            5
+        );
+    }
+}
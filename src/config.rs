@@ -1,14 +1,32 @@
-use crate::gateway_runtime::GatewayRuntime;
-use crate::{CONFIG_FILE_PATH, SharedGatewayState};
+use crate::lifecycle::LifecycleEvent;
+use crate::load_balancer::{MAX_REDUCED_WEIGHT_SUM, reduced_weights};
+use crate::middleware::constants::{
+    ADD_PREFIX_MIDDLEWARE, API_KEY_MIDDLEWARE, BASIC_AUTH_MIDDLEWARE, CACHE_MIDDLEWARE,
+    CIRCUIT_BREAKER_MIDDLEWARE, CONCURRENCY_LIMIT_MIDDLEWARE, CONTENT_TYPE_MIDDLEWARE,
+    CORS_MIDDLEWARE, HEADERS_MIDDLEWARE, IP_FILTER_MIDDLEWARE, JWT_MIDDLEWARE, MIRROR_MIDDLEWARE,
+    QUERY_REWRITE_MIDDLEWARE, RATE_LIMIT_MIDDLEWARE, REQUEST_DECOMPRESS_MIDDLEWARE,
+    RETRY_MIDDLEWARE, REWRITE_MIDDLEWARE, STATUS_REMAP_MIDDLEWARE, WHEN_MIDDLEWARE,
+};
+use crate::middleware::{jwt_algorithms, parse_password_hash};
+use crate::router::{UpstreamTemplate, parse_method, parse_path_regex};
+use crate::server::load_certified_key;
+use crate::utils::load_ca_certificates;
+use crate::{CONFIG_FILE_PATH, LIFECYCLE, MIDDLEWARE_REGISTRY, SharedGatewayState};
 use config::{Config, File};
-use serde::{Deserialize, Serialize};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::http::uri::PathAndQuery;
+use hyper::{Method, StatusCode};
+use ipnet::IpNet;
+use regex::Regex;
+use rustls_pki_types::ServerName;
+use serde::{Deserialize, Serialize, Serializer};
 use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct GatewayConfig {
     #[serde(default = "default_config_version")]
     pub version: u8,
@@ -20,18 +38,168 @@ pub struct GatewayConfig {
     pub access_log: AccessLog,
     pub tls: Option<Vec<TLSConfig>>,
     pub listeners: Vec<Listener>,
+    /// Upper bound for the full handling of a request including middlewares
+    #[serde(default, with = "humantime_serde")]
+    pub request_timeout: Option<Duration>,
+    /// Connection settings shared by every upstream HTTP client
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
     #[serde(default)]
     pub http: HttpConfig,
     #[serde(default)]
     pub tcp: TcpConfig,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UpstreamConfig {
+    #[serde(default)]
+    pub dns: UpstreamDnsConfig,
+    /// Deadline for establishing an upstream connection including the TLS handshake
+    #[serde(default = "default_upstream_connect_timeout", with = "humantime_serde")]
+    pub connect_timeout: Duration,
+    /// Deadline for an upstream request including its response body, services may override it
+    #[serde(
+        default = "default_upstream_request_timeout",
+        alias = "timeout",
+        with = "humantime_serde"
+    )]
+    pub request_timeout: Duration,
+    /// Socket options of upstream connections, only `nodelay` and `keepalive` apply
+    #[serde(default)]
+    pub tcp: TcpOptions,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            dns: UpstreamDnsConfig::default(),
+            connect_timeout: default_upstream_connect_timeout(),
+            request_timeout: default_upstream_request_timeout(),
+            tcp: TcpOptions::default(),
+        }
+    }
+}
+
+/// Socket options, the system defaults are kept for the omitted ones
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TcpOptions {
+    /// Disable Nagle's algorithm, trading throughput for latency
+    pub nodelay: Option<bool>,
+    /// Idle time before the first keepalive probe, keepalive is off when omitted
+    #[serde(default, with = "humantime_serde")]
+    pub keepalive: Option<Duration>,
+    /// `SO_REUSEADDR` of a listening socket, on by default on Unix
+    pub reuse_address: Option<bool>,
+    /// `SO_REUSEPORT` of a listening socket, Unix only
+    pub reuse_port: Option<bool>,
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+}
+
+impl TcpOptions {
+    fn validate(&self) -> Result<(), String> {
+        if self.keepalive.is_some_and(|keepalive| keepalive.is_zero()) {
+            return Err(String::from("keepalive must be greater than 0"));
+        }
+        if self.recv_buffer_size == Some(0) || self.send_buffer_size == Some(0) {
+            return Err(String::from(
+                "recv_buffer_size and send_buffer_size must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Options the platform can't apply, they are ignored.
+    pub fn unsupported(&self) -> Vec<&'static str> {
+        let mut unsupported = Vec::new();
+        if self.reuse_port.is_some() && !REUSE_PORT_SUPPORTED {
+            unsupported.push("reuse_port");
+        }
+        unsupported
+    }
+}
+
+/// Platforms where tokio exposes `SO_REUSEPORT`
+pub const REUSE_PORT_SUPPORTED: bool = cfg!(all(
+    unix,
+    not(any(
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "cygwin"
+    ))
+));
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct UpstreamDnsConfig {
+    #[serde(default)]
+    pub family: IpFamily,
+    /// Static host to address overrides, consulted before the system resolver
+    #[serde(default)]
+    pub hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    /// Resolver order, as the system returns it
+    #[default]
+    Any,
+    PreferIpv4,
+    PreferIpv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
 impl GatewayConfig {
+    pub fn get_listener(&self, name: &str) -> Option<&Listener> {
+        self.listeners.iter().find(|listener| listener.name == name)
+    }
+
     fn validate(&self) -> Result<(), String> {
         if self.version != 1 {
             return Err(String::from("version value must be 1"));
         }
 
+        if self
+            .request_timeout
+            .is_some_and(|timeout| timeout.is_zero())
+        {
+            return Err(String::from("request_timeout must be greater than 0"));
+        }
+
+        if self.upstream.connect_timeout.is_zero() || self.upstream.request_timeout.is_zero() {
+            return Err(String::from(
+                "upstream.connect_timeout and upstream.request_timeout must be greater than 0",
+            ));
+        }
+
+        self.upstream
+            .tcp
+            .validate()
+            .map_err(|err| format!("Invalid upstream.tcp: {err}"))?;
+        let upstream_tcp = &self.upstream.tcp;
+        for (option, set) in [
+            ("reuse_address", upstream_tcp.reuse_address.is_some()),
+            ("reuse_port", upstream_tcp.reuse_port.is_some()),
+            ("recv_buffer_size", upstream_tcp.recv_buffer_size.is_some()),
+            ("send_buffer_size", upstream_tcp.send_buffer_size.is_some()),
+        ] {
+            if set {
+                tracing::warn!("upstream.tcp.{option} is ignored, it only applies to listeners");
+            }
+        }
+
+        let admin_listeners = self.admin_api.all_listeners();
+        let mut admin_addrs = HashSet::with_capacity(admin_listeners.len());
+        for listener in &admin_listeners {
+            if !admin_addrs.insert(listener.addr) {
+                return Err(format!(
+                    "Duplicate admin API listener address {}",
+                    listener.addr
+                ));
+            }
+        }
+
         // Check if a default tls config is provided (if at all)
         if let Some(tls_config) = &self.tls {
             let count = tls_config.iter().filter(|cfg| cfg.default).count();
@@ -42,12 +210,110 @@ impl GatewayConfig {
             }
         }
 
+        // Unreadable or mismatched certificates are reported now rather than at the first
+        // handshake
+        let admin_tls = admin_listeners
+            .iter()
+            .filter_map(|listener| listener.tls.as_ref())
+            .map(|tls| (&tls.cert_file, &tls.key_file));
+        let tls = self
+            .tls
+            .iter()
+            .flatten()
+            .map(|tls| (&tls.cert_file, &tls.key_file));
+        for (cert_file, key_file) in tls.chain(admin_tls) {
+            load_certified_key(cert_file, key_file)
+                .map_err(|err| format!("Invalid TLS certificate: {err}"))?;
+        }
+
         let mut seen_listeners = HashSet::with_capacity(self.listeners.len());
         for listener in &self.listeners {
             if !seen_listeners.insert(&listener.name) {
                 return Err(format!("Duplicate listener name {}", listener.name));
             }
 
+            if listener.max_uri_length == 0 {
+                return Err(format!(
+                    "max_uri_length must be greater than 0 for listener {}",
+                    listener.name
+                ));
+            }
+
+            listener.tcp.validate().map_err(|err| {
+                format!("Invalid tcp options for listener {}: {err}", listener.name)
+            })?;
+            for option in listener.tcp.unsupported() {
+                tracing::warn!(
+                    "tcp.{option} of listener {} is not supported on this platform, ignoring it",
+                    listener.name
+                );
+            }
+
+            if listener
+                .wait_for_healthy_upstreams
+                .is_some_and(|max_wait| max_wait.is_zero())
+            {
+                return Err(format!(
+                    "wait_for_healthy_upstreams must be greater than 0 for listener {}",
+                    listener.name
+                ));
+            }
+
+            if listener.acceptors.enabled {
+                if listener.acceptors.count == Some(0) {
+                    return Err(format!(
+                        "acceptors.count must be greater than 0 for listener {}",
+                        listener.name
+                    ));
+                }
+                if listener.tcp.reuse_port == Some(false) {
+                    return Err(format!(
+                        "acceptors require tcp.reuse_port for listener {}",
+                        listener.name
+                    ));
+                }
+                if !REUSE_PORT_SUPPORTED {
+                    tracing::warn!(
+                        "acceptors of listener {} need SO_REUSEPORT, which is not supported on this platform, using a single acceptor",
+                        listener.name
+                    );
+                }
+            }
+
+            if listener.max_concurrent_handshakes == 0 {
+                return Err(format!(
+                    "max_concurrent_handshakes must be greater than 0 for listener {}",
+                    listener.name
+                ));
+            }
+
+            if listener
+                .request_body_timeout
+                .is_some_and(|timeout| timeout.is_zero())
+            {
+                return Err(format!(
+                    "request_body_timeout must be greater than 0 for listener {}",
+                    listener.name
+                ));
+            }
+
+            if listener
+                .request_timeout
+                .is_some_and(|timeout| timeout.is_zero())
+            {
+                return Err(format!(
+                    "request_timeout must be greater than 0 for listener {}",
+                    listener.name
+                ));
+            }
+
+            if listener.max_request_body_size == Some(0) {
+                return Err(format!(
+                    "max_request_body_size must be greater than 0 for listener {}",
+                    listener.name
+                ));
+            }
+
             if let Protocol::Https = listener.protocol
                 && self.tls.is_none()
             {
@@ -58,32 +324,258 @@ impl GatewayConfig {
             }
         }
 
+        for name in &self.http.strip_response_headers.headers {
+            if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(format!("Invalid strip_response_headers header name {name}"));
+            }
+        }
+
+        if !StatusCode::from_u16(self.http.paused_route_status)
+            .is_ok_and(|status| status.is_client_error() || status.is_server_error())
+        {
+            return Err(format!(
+                "paused_route_status must be a 4xx or 5xx status, found {}",
+                self.http.paused_route_status
+            ));
+        }
+
+        if self.http.served_by.enabled
+            && HeaderName::from_bytes(self.http.served_by.header.as_bytes()).is_err()
+        {
+            return Err(format!(
+                "Invalid served_by header name {}",
+                self.http.served_by.header
+            ));
+        }
+
+        if HeaderName::from_bytes(self.http.request_id.upstream_header.as_bytes()).is_err() {
+            return Err(format!(
+                "Invalid request_id upstream_header name {}",
+                self.http.request_id.upstream_header
+            ));
+        }
+
+        if let Some(upstream_override) = &self.http.upstream_override {
+            if HeaderName::from_bytes(upstream_override.header.as_bytes()).is_err() {
+                return Err(format!(
+                    "Invalid upstream_override header {}",
+                    upstream_override.header
+                ));
+            }
+            if upstream_override.trusted_ips.is_empty() {
+                return Err(String::from("upstream_override needs trusted_ips"));
+            }
+            for range in &upstream_override.trusted_ips {
+                if parse_ip_range(range).is_none() {
+                    return Err(format!("Invalid upstream_override trusted ip {range}"));
+                }
+            }
+        }
+
+        if let Some(echo) = &self.http.debug_echo {
+            if !echo.path.starts_with('/') {
+                return Err(format!("debug_echo path {} must start with '/'", echo.path));
+            }
+            if let Some(listener) = echo
+                .listeners
+                .iter()
+                .find(|listener| !seen_listeners.contains(listener))
+            {
+                return Err(format!("Undefined listener {listener} for debug_echo"));
+            }
+        }
+
+        for (name, middleware) in &self.http.middlewares {
+            if !MIDDLEWARE_REGISTRY.contains(middleware.name()) {
+                return Err(format!(
+                    "Middleware {name} uses unsupported type {}",
+                    middleware.name()
+                ));
+            }
+            middleware.validate().map_err(|err| {
+                format!(
+                    "Invalid config for middleware {name} of type {}: {err}",
+                    middleware.name()
+                )
+            })?;
+        }
+
+        for middleware in &self.http.global_middlewares {
+            if !self.http.middlewares.contains_key(middleware) {
+                return Err(format!("Middleware {} is not defined", middleware));
+            }
+        }
+
         let mut seen_services = HashSet::with_capacity(self.http.services.len());
-        for key in self.http.services.keys() {
+        for (key, service) in &self.http.services {
             if seen_services.contains(key) {
                 return Err(format!("Duplicate service name {}", key));
             }
             seen_services.insert(key);
+
+            if [service.connect_timeout, service.request_timeout]
+                .iter()
+                .any(|timeout| timeout.is_some_and(|timeout| timeout.is_zero()))
+            {
+                return Err(format!(
+                    "connect_timeout and request_timeout must be greater than 0 for service {key}"
+                ));
+            }
+
+            if service.max_requests_per_connection == Some(0) {
+                return Err(format!(
+                    "max_requests_per_connection must be greater than 0 for service {key}"
+                ));
+            }
+
+            if let Some(redirects) = &service.redirects
+                && redirects.rewrite_location
+                && redirects.mode != RedirectMode::PassThrough
+            {
+                return Err(format!(
+                    "redirects.rewrite_location requires mode pass_through for service {key}"
+                ));
+            }
+
+            if let Some(proxy) = &service.proxy {
+                let scheme = proxy.url.split_once("://").map(|(scheme, _)| scheme);
+                if !matches!(scheme, Some("http" | "https" | "socks5" | "socks5h")) {
+                    return Err(format!(
+                        "Unsupported proxy url {} for service {key}, expected http, https, socks5 or socks5h",
+                        proxy.url
+                    ));
+                }
+            }
+
+            if let Some(keep_alive) = &service.http2_keep_alive
+                && (keep_alive.interval.is_zero() || keep_alive.timeout.is_zero())
+            {
+                return Err(format!(
+                    "http2_keep_alive interval and timeout must be greater than 0 for service {key}"
+                ));
+            }
+
+            if let Some(health_check) = &service.health_check {
+                health_check
+                    .validate()
+                    .map_err(|err| format!("{err} for service {key}"))?;
+            }
+
+            if let Some(passive_health) = &service.passive_health
+                && (passive_health.consecutive_failures == 0
+                    || passive_health.ejection_duration.is_zero()
+                    || passive_health.max_ejection_percent > 100)
+            {
+                return Err(format!(
+                    "passive_health needs consecutive_failures and ejection_duration greater than 0 and max_ejection_percent up to 100 for service {key}"
+                ));
+            }
+
+            for upstream in &service.upstreams {
+                upstream
+                    .validate_tls_sni()
+                    .map_err(|err| format!("{err} for service {key}"))?;
+            }
+            validate_upstream_names(&service.upstreams)
+                .map_err(|err| format!("{err} for service {key}"))?;
+            // Checked here as well so `--check` reports it, not only the start
+            if let Some(ca_file) = service.tls.as_ref().and_then(|tls| tls.ca_file.as_ref()) {
+                load_ca_certificates(ca_file).map_err(|err| format!("{err} for service {key}"))?;
+            }
+            service
+                .strategy
+                .validate(&service.upstreams)
+                .map_err(|err| format!("{err} for service {key}"))?;
         }
 
-        for route in &self.http.routes {
+        let mut seen_route_names = HashSet::new();
+        for (index, route) in self.http.routes.iter().enumerate() {
+            let service_name = route.service_name(index);
+            if let Some(name) = &route.name {
+                if name.is_empty() || name.contains('/') {
+                    return Err(format!(
+                        "Route name {name:?} must be non-empty and must not contain '/'"
+                    ));
+                }
+                if !seen_route_names.insert(name) {
+                    return Err(format!("Duplicate route name {name}"));
+                }
+            }
             if route.hosts.is_none() && route.path.is_none() {
                 return Err(format!(
-                    "At least one of hosts or path is required for matching route against service {}",
-                    route.service
+                    "At least one of hosts or path is required for matching route against service {service_name}",
+                ));
+            }
+
+            if let Some(Err(err)) = route.path.as_deref().and_then(parse_path_regex) {
+                return Err(format!(
+                    "Invalid path regex for route {service_name}: {err}"
                 ));
             }
 
+            if let Some(methods) = &route.methods {
+                if methods.is_empty() {
+                    return Err(format!(
+                        "methods must not be empty for route {service_name}, omit it to allow any"
+                    ));
+                }
+                if let Some(method) = methods.iter().find(|method| parse_method(method).is_none()) {
+                    return Err(format!(
+                        "Invalid method {method:?} for route {service_name}"
+                    ));
+                }
+            }
+
+            if let Some(health_check) = &route.health_check {
+                health_check
+                    .validate()
+                    .map_err(|err| format!("{err} for route {service_name}"))?;
+            }
+
+            match (&route.service, &route.upstreams, &route.upstream_template) {
+                (_, None, _) if route.health_check.is_some() => {
+                    return Err(format!(
+                        "health_check needs inline upstreams for route {service_name}"
+                    ));
+                }
+                (Some(service), None, None) => {
+                    if !seen_services.contains(service) {
+                        return Err(format!("Undefined service {service}"));
+                    }
+                }
+                (None, Some(upstreams), None) => {
+                    if upstreams.is_empty() {
+                        return Err(format!(
+                            "Inline upstreams must not be empty for route {service_name}"
+                        ));
+                    }
+                    for upstream in upstreams {
+                        upstream
+                            .validate_tls_sni()
+                            .map_err(|err| format!("{err} for route {service_name}"))?;
+                    }
+                    validate_upstream_names(upstreams)
+                        .map_err(|err| format!("{err} for route {service_name}"))?;
+                }
+
+                (None, None, Some(template)) => {
+                    validate_upstream_template(template, route.hosts.as_deref()).map_err(
+                        |err| format!("Invalid upstream_template for route {service_name}: {err}"),
+                    )?;
+                }
+                _ => {
+                    return Err(format!(
+                        "Exactly one of service, upstreams or upstream_template must be set for route {service_name}"
+                    ));
+                }
+            }
+
             for listener in &route.listeners {
                 if !seen_listeners.contains(listener) {
                     return Err(format!("Undefined listener {}", listener));
                 }
             }
 
-            if !seen_services.contains(&route.service) {
-                return Err(format!("Undefined service {}", route.service));
-            }
-
             if let Some(route_middlewares) = &route.middlewares {
                 for middleware in route_middlewares {
                     if !self.http.middlewares.contains_key(middleware) {
@@ -91,6 +583,118 @@ impl GatewayConfig {
                     }
                 }
             }
+
+            if route
+                .request_body_timeout
+                .is_some_and(|timeout| timeout.is_zero())
+                || route.max_request_body_size == Some(0)
+            {
+                return Err(format!(
+                    "request_body_timeout and max_request_body_size must be greater than 0 for route {service_name}"
+                ));
+            }
+
+            if let Some(canary) = &route.canary {
+                if route.name.is_none() || route.upstream_template.is_some() {
+                    return Err(format!(
+                        "Canary needs a named route without upstream_template for route {service_name}"
+                    ));
+                }
+                if !seen_services.contains(&canary.service) {
+                    return Err(format!("Undefined canary service {}", canary.service));
+                }
+                if canary.weight > 100 {
+                    return Err(format!(
+                        "Canary weight must be at most 100 for route {service_name}"
+                    ));
+                }
+                if let Some(rollback) = &canary.rollback
+                    && (!(rollback.error_threshold > 0.0 && rollback.error_threshold <= 1.0)
+                        || rollback.window.is_zero()
+                        || rollback.min_requests == 0)
+                {
+                    return Err(format!(
+                        "Canary rollback needs an error_threshold in (0, 1], a window and min_requests greater than 0 for route {service_name}"
+                    ));
+                }
+            }
+
+            if let Some(ab_test) = &route.ab_test {
+                if route.canary.is_some() || route.upstream_template.is_some() {
+                    return Err(format!(
+                        "ab_test cannot be combined with canary or upstream_template for route {service_name}"
+                    ));
+                }
+                if ab_test.variants.is_empty() {
+                    return Err(format!(
+                        "ab_test needs at least one variant for route {service_name}"
+                    ));
+                }
+                if !is_cookie_token(&ab_test.cookie) {
+                    return Err(format!(
+                        "Invalid ab_test cookie name {} for route {service_name}",
+                        ab_test.cookie
+                    ));
+                }
+                let mut names = HashSet::new();
+                for variant in &ab_test.variants {
+                    if !seen_services.contains(&variant.service) {
+                        return Err(format!("Undefined ab_test service {}", variant.service));
+                    }
+                    if variant.weight == 0 || !is_cookie_token(&variant.name) {
+                        return Err(format!(
+                            "ab_test variant {} needs a cookie-safe name and a weight greater than 0 for route {service_name}",
+                            variant.name
+                        ));
+                    }
+                    if !names.insert(variant.name.as_str()) {
+                        return Err(format!(
+                            "Duplicate ab_test variant {} for route {service_name}",
+                            variant.name
+                        ));
+                    }
+                }
+            }
+
+            if let Some(timeout) = &route.timeout {
+                if timeout.duration.is_zero() {
+                    return Err(format!(
+                        "Timeout must be greater than 0 for route {service_name}"
+                    ));
+                }
+                if let Some(response) = &timeout.response {
+                    response.validate().map_err(|err| {
+                        format!("Invalid timeout response for route {service_name}: {err}")
+                    })?;
+                }
+            }
+
+            if let Some(skip_middlewares) = &route.skip_middlewares {
+                for middleware in skip_middlewares {
+                    if !self.http.global_middlewares.contains(middleware) {
+                        return Err(format!(
+                            "Skipped middleware {} is not a global middleware",
+                            middleware
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (key, service) in &self.tcp.services {
+            if service
+                .upstreams
+                .iter()
+                .any(|upstream| upstream.tls_sni.is_some())
+            {
+                return Err(format!(
+                    "tls_sni is only supported for HTTP upstreams, found in tcp service {key}"
+                ));
+            }
+            service
+                .strategy
+                .validate(&service.upstreams)
+                .map_err(|err| format!("{err} for tcp service {key}"))?;
         }
 
         Ok(())
@@ -100,12 +704,93 @@ impl GatewayConfig {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct AdminAPIConfig {
     pub addr: SocketAddr,
+    #[serde(default = "default_drain_delay", with = "humantime_serde")]
+    pub drain_delay: Duration,
+    /// Answer new requests with 503 once draining starts, even on open connections
+    #[serde(default)]
+    pub reject_requests_while_draining: bool,
+
+    /// Serve only reads, mutating endpoints such as reload and drain answer 403
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// Serve the admin API over HTTPS, plaintext when omitted
+    pub tls: Option<AdminTlsConfig>,
+
+    /// Further listeners exposing a subset of the endpoints, e.g. metrics for the cluster network
+    /// while the full API stays on loopback
+    #[serde(default)]
+    pub listeners: Vec<AdminListenerConfig>,
+}
+
+impl AdminAPIConfig {
+    /// The main listener serving every endpoint followed by the configured extra ones.
+    pub fn all_listeners(&self) -> Vec<AdminListenerConfig> {
+        let main = AdminListenerConfig {
+            addr: self.addr,
+            read_only: self.read_only,
+            tls: self.tls.clone(),
+            endpoints: None,
+        };
+        std::iter::once(main)
+            .chain(self.listeners.iter().cloned())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AdminListenerConfig {
+    pub addr: SocketAddr,
+    #[serde(default)]
+    pub read_only: bool,
+    pub tls: Option<AdminTlsConfig>,
+    /// Endpoints served on this listener, every endpoint when omitted
+    pub endpoints: Option<Vec<AdminEndpoint>>,
+}
+
+impl AdminListenerConfig {
+    pub fn exposes(&self, endpoint: AdminEndpoint) -> bool {
+        self.endpoints
+            .as_ref()
+            .is_none_or(|endpoints| endpoints.contains(&endpoint))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminEndpoint {
+    /// `/`, version and running config
+    Context,
+    Config,
+    Reload,
+    Ready,
+    Drain,
+    /// `/metrics/bytes`
+    Metrics,
+    Upstreams,
+    Circuits,
+    Canary,
+    /// `/routes/{name}/errors`
+    RouteErrors,
+    /// `/routes/{name}/pause` and `/routes/{name}/unpause`
+    RoutePause,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AdminTlsConfig {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
 }
 
 impl Default for AdminAPIConfig {
     fn default() -> Self {
         AdminAPIConfig {
             addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 5678),
+            drain_delay: default_drain_delay(),
+            reject_requests_while_draining: false,
+            read_only: false,
+            tls: None,
+            listeners: Vec::new(),
         }
     }
 }
@@ -125,52 +810,695 @@ pub struct Listener {
     pub addr: SocketAddr,
     #[serde(default)]
     pub protocol: Protocol,
+    #[serde(default = "default_max_uri_length")]
+    pub max_uri_length: usize,
+    #[serde(default = "default_max_concurrent_handshakes")]
+    pub max_concurrent_handshakes: usize,
+    /// Time allowed for the client to send the full request body, routes may override it
+    #[serde(default, with = "humantime_serde")]
+    pub request_body_timeout: Option<Duration>,
+    /// Default timeout of routes without their own, capped by the global `request_timeout`
+    #[serde(default, with = "humantime_serde")]
+    pub request_timeout: Option<Duration>,
+    /// Largest request body accepted, routes may override it
+    pub max_request_body_size: Option<usize>,
+    /// Resolve dot segments and collapse duplicate slashes before routing and forwarding
+    #[serde(default)]
+    pub normalize_path: bool,
+    /// What to do with requests hiding traversal behind encoded dots, `;` or backslashes
+    #[serde(default)]
+    pub path_traversal: PathTraversalAction,
+    /// Which malformed request paths are answered with 400 before routing
+    #[serde(default)]
+    pub path_validation: PathValidation,
+    /// Socket options of the listening socket and accepted connections
+    #[serde(default)]
+    pub tcp: TcpOptions,
+    #[serde(default)]
+    pub acceptors: AcceptorsConfig,
+    /// Delay accepting at startup until every route has an upstream that passed its health check,
+    /// at most this long
+    #[serde(default, with = "humantime_serde")]
+    pub wait_for_healthy_upstreams: Option<Duration>,
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct HttpConfig {
+/// Parallel accept loops, each on its own `SO_REUSEPORT` socket bound to the listener address
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AcceptorsConfig {
     #[serde(default)]
-    pub middlewares: HashMap<String, MiddlewareConfig>,
-    pub services: HashMap<String, HttpServiceConfig>,
-    pub routes: Vec<RouteConfig>,
+    pub enabled: bool,
+    /// Number of sockets and accept loops, the CPU count when omitted
+    pub count: Option<usize>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct HttpServiceConfig {
-    pub upstreams: Vec<Upstream>,
+impl AcceptorsConfig {
+    /// Accept loops to run, a single one when disabled or unsupported by the platform.
+    pub fn effective_count(&self) -> usize {
+        if !self.enabled || !REUSE_PORT_SUPPORTED {
+            return 1;
+        }
+        self.count.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|count| count.get())
+                .unwrap_or(1)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathTraversalAction {
+    /// Forward the path as received
+    #[default]
+    Allow,
+    /// Answer 400
+    Reject,
+    /// Resolve the traversal before routing, 400 when it hides an encoded separator
+    Normalize,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathValidation {
+    /// Control characters, literal or percent-encoded, are rejected
+    #[default]
+    Lenient,
+    /// Malformed percent-encoding and escapes decoding to invalid UTF-8 are rejected as well
+    Strict,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HttpConfig {
+    #[serde(default)]
+    pub middlewares: HashMap<String, MiddlewareConfig>,
+    /// Middlewares applied to every route ahead of the route's own middlewares
+    #[serde(default)]
+    pub global_middlewares: Vec<String>,
+    pub services: HashMap<String, HttpServiceConfig>,
+    pub routes: Vec<RouteConfig>,
+    #[serde(default)]
+    pub served_by: ServedByConfig,
+    #[serde(default)]
+    pub request_id: RequestIdConfig,
+    /// Endpoint answering with the request as an upstream would receive it, for debugging
+    pub debug_echo: Option<DebugEchoConfig>,
+    #[serde(default)]
+    pub metering: MeteringConfig,
+    #[serde(default)]
+    pub strip_response_headers: StripResponseHeadersConfig,
+    /// Report the time spent in each middleware and upstream as `Server-Timing`, for debugging
+    #[serde(default)]
+    pub server_timing: bool,
+    /// Request bodies up to this size are buffered so retries and mirrors can replay them,
+    /// larger ones stream upstream without either
+    #[serde(default = "default_request_body_buffer_threshold")]
+    pub request_body_buffer_threshold: usize,
+    /// Status answered by routes paused through the admin API
+    #[serde(default = "default_paused_route_status")]
+    pub paused_route_status: u16,
+    /// Lets trusted clients pick the upstream of a request, for testing
+    pub upstream_override: Option<UpstreamOverrideConfig>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            middlewares: HashMap::new(),
+            global_middlewares: Vec::new(),
+            services: HashMap::new(),
+            routes: Vec::new(),
+            served_by: ServedByConfig::default(),
+            request_id: RequestIdConfig::default(),
+            debug_echo: None,
+            metering: MeteringConfig::default(),
+            strip_response_headers: StripResponseHeadersConfig::default(),
+            server_timing: false,
+            request_body_buffer_threshold: default_request_body_buffer_threshold(),
+            paused_route_status: default_paused_route_status(),
+            upstream_override: None,
+        }
+    }
+}
+
+/// Response headers known to leak backend implementation details.
+pub const DEFAULT_STRIPPED_RESPONSE_HEADERS: [&str; 4] = [
+    "x-powered-by",
+    "x-aspnet-version",
+    "x-aspnetmvc-version",
+    "x-runtime",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StripResponseHeadersConfig {
+    /// Strip `DEFAULT_STRIPPED_RESPONSE_HEADERS` in addition to `headers`
+    #[serde(default = "default_strip_default_headers")]
+    pub defaults: bool,
+    #[serde(default)]
+    pub headers: Vec<String>,
+}
+
+impl Default for StripResponseHeadersConfig {
+    fn default() -> Self {
+        StripResponseHeadersConfig {
+            defaults: default_strip_default_headers(),
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl StripResponseHeadersConfig {
+    /// Every header removed from upstream responses, invalid names are skipped.
+    pub fn header_names(&self) -> Vec<HeaderName> {
+        let defaults: &[&str] = if self.defaults {
+            &DEFAULT_STRIPPED_RESPONSE_HEADERS
+        } else {
+            &[]
+        };
+        defaults
+            .iter()
+            .copied()
+            .chain(self.headers.iter().map(String::as_str))
+            .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct MeteringConfig {
+    /// Request header identifying the API key, bytes are also counted per key when set
+    #[serde(default)]
+    pub api_key_header: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ServedByConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_served_by_header")]
+    pub header: String,
+}
+
+impl Default for ServedByConfig {
+    fn default() -> Self {
+        ServedByConfig {
+            enabled: false,
+            header: default_served_by_header(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DebugEchoConfig {
+    #[serde(default = "default_debug_echo_path")]
+    pub path: String,
+    /// Listeners exposing the endpoint, typically internal ones only
+    pub listeners: Vec<String>,
+}
+
+/// Sends a request to the upstream named by a header instead of the one picked by the load
+/// balancer, the header is ignored from untrusted clients and never forwarded.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UpstreamOverrideConfig {
+    #[serde(default = "default_upstream_override_header")]
+    pub header: String,
+    /// Client addresses or CIDR ranges allowed to send the header
+    pub trusted_ips: Vec<String>,
+}
+
+impl UpstreamOverrideConfig {
+    pub fn is_trusted(&self, client_ip: IpAddr) -> bool {
+        self.trusted_ips
+            .iter()
+            .filter_map(|range| parse_ip_range(range))
+            .any(|range| range.contains(&client_ip))
+    }
+}
+
+/// CIDR range such as `10.0.0.0/8`, a bare address is a range of its own.
+pub fn parse_ip_range(range: &str) -> Option<IpNet> {
+    range
+        .parse::<IpNet>()
+        .ok()
+        .or_else(|| range.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RequestIdConfig {
+    /// Header the request ID is forwarded upstream under, e.g. `x-correlation-id`
+    #[serde(default = "default_upstream_request_id_header")]
+    pub upstream_header: String,
+}
+
+impl Default for RequestIdConfig {
+    fn default() -> Self {
+        RequestIdConfig {
+            upstream_header: default_upstream_request_id_header(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HttpServiceConfig {
+    pub upstreams: Vec<Upstream>,
+    #[serde(default)]
+    pub strategy: LoadBalancingStrategy,
+    #[serde(default, with = "humantime_serde")]
+    pub pool_idle_timeout: Option<Duration>,
+    /// Connect deadline for the upstreams of this service, `upstream.connect_timeout` when omitted
+    #[serde(default, with = "humantime_serde")]
+    pub connect_timeout: Option<Duration>,
+    /// Deadline for requests to the upstreams of this service, `upstream.request_timeout` when
+    /// omitted
+    #[serde(default, alias = "timeout", with = "humantime_serde")]
+    pub request_timeout: Option<Duration>,
+    #[serde(default)]
+    pub retry_stale_connections: bool,
+    /// Send `Connection: close` so every request uses a fresh upstream connection
+    #[serde(default)]
+    pub force_connection_close: bool,
+    /// Requests after which `Connection: close` is sent so the upstream connection is recycled
+    pub max_requests_per_connection: Option<u64>,
+    /// Pin each downstream connection to the upstream picked for its first request
+    #[serde(default)]
+    pub connection_affinity: bool,
+    pub tls: Option<UpstreamTlsConfig>,
+    /// HTTP/2 PING frames keeping upstream connections alive through NATs and load balancers
+    pub http2_keep_alive: Option<Http2KeepAliveConfig>,
+    /// Outbound proxy the upstreams of this service are reached through
+    pub proxy: Option<UpstreamProxyConfig>,
+    /// How upstream `3xx` answers are handled, followed up to 10 times when omitted
+    pub redirects: Option<RedirectConfig>,
+    /// Active probes taking failing upstreams out of the rotation
+    pub health_check: Option<HealthCheckConfig>,
+    /// Ejects upstreams failing live requests from the rotation for a while
+    pub passive_health: Option<PassiveHealthConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PassiveHealthConfig {
+    /// Connection errors or 5xx answers in a row before an upstream is ejected
+    #[serde(default = "default_passive_health_consecutive_failures")]
+    pub consecutive_failures: u32,
+    #[serde(
+        default = "default_passive_health_ejection_duration",
+        with = "humantime_serde"
+    )]
+    pub ejection_duration: Duration,
+    /// Share of the upstreams that may be ejected at once, at least one upstream always remains
+    #[serde(default = "default_passive_health_max_ejection_percent")]
+    pub max_ejection_percent: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HealthCheckConfig {
+    /// Path probed with a `GET` on every upstream
+    pub path: String,
+    #[serde(default = "default_health_check_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(default = "default_health_check_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// Consecutive passing probes marking an unhealthy upstream healthy again
+    #[serde(default = "default_health_check_threshold")]
+    pub healthy_threshold: u32,
+    /// Consecutive failing probes marking a healthy upstream unhealthy
+    #[serde(default = "default_health_check_threshold")]
+    pub unhealthy_threshold: u32,
+    /// Statuses meaning healthy, any 2xx or 3xx when omitted
+    pub expected_statuses: Option<Vec<u16>>,
+}
+
+impl HealthCheckConfig {
+    fn validate(&self) -> Result<(), String> {
+        if !self.path.starts_with('/') {
+            return Err(String::from("health_check path must start with '/'"));
+        }
+        if self.interval.is_zero() || self.timeout.is_zero() {
+            return Err(String::from(
+                "health_check interval and timeout must be greater than 0",
+            ));
+        }
+        if self.healthy_threshold == 0 || self.unhealthy_threshold == 0 {
+            return Err(String::from(
+                "health_check thresholds must be greater than 0",
+            ));
+        }
+        if let Some(status) = self
+            .expected_statuses
+            .iter()
+            .flatten()
+            .find(|status| StatusCode::from_u16(**status).is_err())
+        {
+            return Err(format!("Invalid health_check expected status {status}"));
+        }
+        Ok(())
+    }
+}
+
+impl HttpServiceConfig {
+    /// Pool, TLS and HTTP/2 settings differing from the gateway wide client need a client of
+    /// their own.
+    pub fn needs_dedicated_client(&self) -> bool {
+        self.pool_idle_timeout.is_some()
+            || self.tls.is_some()
+            || self.http2_keep_alive.is_some()
+            || self.proxy.is_some()
+            || self.redirects.is_some()
+            || self.connect_timeout.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancingStrategy {
+    /// Smooth weighted round robin
+    #[default]
+    WeightedRoundRobin,
+    /// Cycle through the upstreams in order, ignoring weights
+    RoundRobin,
+    /// Random upstream, biased by weight
+    Random,
+    /// Upstream with the fewest in flight requests, ignoring weights
+    LeastConnections,
+    /// Same upstream for the same client IP on a consistent hash ring, shares follow the weights
+    IpHash,
+}
+
+/// Reduced upstream weights above this are valid but most likely a typo.
+const LARGE_REDUCED_WEIGHT: u32 = 1_000;
+
+impl LoadBalancingStrategy {
+    /// Weighted strategies cannot pick anything when every weight is 0, and reject weights whose
+    /// reduced sum would make their selection state unreasonably large.
+    fn validate(&self, upstreams: &[Upstream]) -> Result<(), String> {
+        let uses_weights = matches!(
+            self,
+            LoadBalancingStrategy::WeightedRoundRobin
+                | LoadBalancingStrategy::Random
+                | LoadBalancingStrategy::IpHash
+        );
+        if !uses_weights {
+            return Ok(());
+        }
+        if upstreams.iter().all(|upstream| upstream.weight == 0) {
+            return Err(format!(
+                "{self:?} strategy needs an upstream with a weight greater than 0"
+            ));
+        }
+        let weights = reduced_weights(upstreams);
+        let weight_sum: u64 = weights.iter().copied().map(u64::from).sum();
+        if weight_sum > MAX_REDUCED_WEIGHT_SUM {
+            return Err(format!(
+                "Upstream weights reduced by their greatest common divisor must sum to at most \
+                 {MAX_REDUCED_WEIGHT_SUM}, found {weight_sum}"
+            ));
+        }
+        if let Some(upstream) = upstreams
+            .iter()
+            .zip(&weights)
+            .find(|&(_, &weight)| weight > LARGE_REDUCED_WEIGHT)
+            .map(|(upstream, _)| upstream)
+        {
+            tracing::warn!(
+                "Upstream {} has a weight of {}, weights are relative shares, small ratios like \
+                 3:1 are enough",
+                upstream.target,
+                upstream.weight
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UpstreamProxyConfig {
+    /// `http://`, `https://`, `socks5://` or `socks5h://` (proxy side DNS resolution) URL
+    #[serde(serialize_with = "serialize_redacted_url")]
+    pub url: String,
+    pub username: Option<String>,
+    #[serde(serialize_with = "serialize_redacted_secret")]
+    pub password: Option<String>,
+    /// Hosts, domains and IP ranges reached directly, in the `NO_PROXY` format
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RedirectConfig {
+    #[serde(default)]
+    pub mode: RedirectMode,
+    /// Redirects followed before the request fails with `502`
+    #[serde(default = "default_max_redirects")]
+    pub max_redirects: usize,
+    /// Passed through `Location` headers pointing at the upstream are rewritten to the host the
+    /// client asked for
+    #[serde(default)]
+    pub rewrite_location: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectMode {
+    #[default]
+    Follow,
+    /// The `3xx` reaches the client unchanged
+    PassThrough,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Http2KeepAliveConfig {
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    /// Time to wait for a PING acknowledgement before the connection is closed
+    #[serde(default = "default_http2_keep_alive_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// Also ping connections without open streams
+    #[serde(default = "default_http2_keep_alive_while_idle")]
+    pub while_idle: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UpstreamTlsConfig {
+    /// Additional PEM encoded root certificates trusted for the upstreams of this service
+    pub ca_file: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct TcpConfig {
     pub services: HashMap<String, TcpServiceConfig>,
     pub routes: Vec<TcpRouteConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TcpServiceConfig {
     pub upstreams: Vec<Upstream>,
+    #[serde(default)]
+    pub strategy: LoadBalancingStrategy,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TcpRouteConfig {
     pub listeners: Vec<String>,
     pub service: String,
     pub tls_mode: Option<TcpTlsMode>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum TcpTlsMode {
     Terminate,
     Passthrough,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct RouteConfig {
+    /// Identifies the route in the admin API, e.g. for its error log
+    pub name: Option<String>,
     pub hosts: Option<Vec<String>>,
+    /// Exact path, prefix ending in `/*` or regex prefixed with `~`, e.g. `~^/users/\d+$`
     pub path: Option<String>,
+    /// Methods the route serves, any when omitted. Other methods get 405 unless another route
+    /// serves them
+    pub methods: Option<Vec<String>>,
+    /// Higher wins among matching routes regardless of how specific they are, routes without a
+    /// priority rank below every route with one
+    pub priority: Option<i32>,
     pub listeners: Vec<String>,
-    pub service: String,
+    pub service: Option<String>,
+    pub upstreams: Option<Vec<Upstream>>,
+    /// Probes the inline upstreams the way a service's health check does
+    pub health_check: Option<HealthCheckConfig>,
+    /// Upstream derived from the request host, e.g. `http://{host_label_0}.backend.internal:8080`
+    pub upstream_template: Option<String>,
     pub middlewares: Option<Vec<String>>,
+    pub skip_middlewares: Option<Vec<String>>,
+    pub timeout: Option<RouteTimeoutConfig>,
+    /// Overrides the listener's `request_body_timeout`
+    #[serde(default, with = "humantime_serde")]
+    pub request_body_timeout: Option<Duration>,
+    /// Overrides the listener's `max_request_body_size`
+    pub max_request_body_size: Option<usize>,
+    /// Overrides the global `request_body_buffer_threshold`
+    pub request_body_buffer_threshold: Option<usize>,
+    /// How an exact path matches requests differing from it by a trailing slash
+    #[serde(default)]
+    pub trailing_slash: TrailingSlash,
+    /// How `HEAD` requests are sent upstream
+    #[serde(default)]
+    pub head: HeadHandling,
+    /// Share of the requests sent to another service, adjustable through the admin API
+    pub canary: Option<CanaryConfig>,
+    /// Sticky split of the requests between variant services
+    pub ab_test: Option<AbTestConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CanaryConfig {
+    pub service: String,
+    /// Percentage of the requests sent to the canary service until changed through the admin API
+    #[serde(default)]
+    pub weight: u8,
+    /// Sets the weight back to 0 when the canary fails too often
+    pub rollback: Option<CanaryRollbackConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AbTestConfig {
+    pub variants: Vec<AbTestVariant>,
+    /// Hashed to pick the variant of a client without the cookie, a random pick when omitted or
+    /// missing from the request
+    pub key: Option<AbTestKey>,
+    /// Remembers the variant of a client
+    #[serde(default = "default_ab_test_cookie")]
+    pub cookie: String,
+    /// Session cookie when omitted
+    #[serde(default, with = "humantime_serde")]
+    pub cookie_max_age: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AbTestVariant {
+    /// Stored in the cookie
+    pub name: String,
+    pub service: String,
+    #[serde(default = "default_ab_test_weight")]
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AbTestKey {
+    Header(String),
+    Cookie(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CanaryRollbackConfig {
+    /// Share of 5xx and connection errors among the canary's responses in a window
+    pub error_threshold: f64,
+    #[serde(default = "default_canary_rollback_window", with = "humantime_serde")]
+    pub window: Duration,
+    /// Canary responses needed in a window before it can be rolled back
+    #[serde(default = "default_canary_rollback_min_requests")]
+    pub min_requests: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailingSlash {
+    /// Only the path as configured
+    Strict,
+    /// `/foo` also matches `/foo/`
+    #[default]
+    Tolerant,
+    /// `/foo/` for a route on `/foo` and the other way around get a 301 to the configured form
+    Redirect,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HeadHandling {
+    /// Forwarded as `HEAD`, a body sent by the upstream anyway is dropped
+    #[default]
+    PassThrough,
+    /// Sent upstream as `GET` for backends mishandling `HEAD`, answered with its headers only
+    FromGet,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RouteTimeoutConfig {
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    pub response: Option<ErrorResponseConfig>,
+}
+
+/// Custom body and headers for a gateway generated error, `{request_id}` in the body is replaced
+/// with the ID of the failed request.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ErrorResponseConfig {
+    pub body: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+impl ErrorResponseConfig {
+    fn validate(&self) -> Result<(), String> {
+        for (name, value) in &self.headers {
+            if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(format!("invalid header name {name}"));
+            }
+            if HeaderValue::from_str(value).is_err() {
+                return Err(format!("invalid value for header {name}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The template must only refer to labels every matching host has and render to an http(s) URL.
+/// Whether `value` can be used as a cookie name or value without quoting.
+fn is_cookie_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+fn validate_upstream_template(template: &str, hosts: Option<&[String]>) -> Result<(), String> {
+    let template = UpstreamTemplate::parse(template)?;
+    let Some(hosts) = hosts.filter(|hosts| !hosts.is_empty()) else {
+        return Err(String::from("hosts are required to derive the upstream"));
+    };
+    if let Some(max_label) = template.max_label() {
+        // `*.example.com` matches hosts with at least as many labels as the pattern
+        let min_labels = hosts
+            .iter()
+            .map(|host| host.split('.').count())
+            .min()
+            .unwrap_or_default();
+        if max_label >= min_labels {
+            return Err(format!(
+                "host_label_{max_label} is out of range for hosts with {min_labels} labels"
+            ));
+        }
+    }
+    let sample_host = hosts[0].replace('*', "tenant");
+    let target = template
+        .render(&sample_host)
+        .ok_or_else(|| format!("cannot render for host {}", hosts[0]))?;
+    match reqwest::Url::parse(&target) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => Ok(()),
+        _ => Err(format!("{target} is not a valid http(s) URL")),
+    }
+}
+
+impl RouteConfig {
+    /// Name of the service backing this route, routes with inline upstreams get an
+    /// anonymous service keyed by their position in the routes list.
+    pub fn service_name(&self, index: usize) -> String {
+        match &self.service {
+            Some(service) => service.clone(),
+            None => format!("@route/{index}"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
@@ -190,12 +1518,13 @@ pub enum Protocol {
     Tcp,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct AddPrefixConfig {
+    /// `:name` segments are replaced by the route path param of that name
     pub prefix: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum RateLimitKeySource {
     #[serde(rename = "ip")]
@@ -209,7 +1538,7 @@ impl Default for RateLimitKeySource {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct RateLimitConfig {
     #[serde(default)]
     pub source: RateLimitKeySource,
@@ -218,71 +1547,1020 @@ pub struct RateLimitConfig {
     pub period: Duration,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum MiddlewareConfig {
-    AddPrefix(AddPrefixConfig),
-    RateLimit(RateLimitConfig),
+/// Adds, removes and renames query parameters before the request is forwarded, removing first,
+/// then renaming, then adding.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QueryRewriteConfig {
+    /// Parameter name to value, replacing any value sent by the client
+    #[serde(default)]
+    pub add: HashMap<String, String>,
+    /// Parameter names dropped with every occurrence
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Parameter name to the name it is forwarded under
+    #[serde(default)]
+    pub rename: HashMap<String, String>,
 }
 
+/// Answers `401` unless the request carries the `Basic` credentials of a configured user.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(default)]
-pub struct GatewayLog {
-    #[serde(default = "default_log_level")]
-    pub level: String,
-    #[serde(default)]
-    pub format: LogFormat,
-    #[serde(default = "default_log_file_path")]
-    pub file_path: String,
+pub struct BasicAuthConfig {
+    #[serde(default = "default_basic_auth_realm")]
+    pub realm: String,
+    /// User name to the hex SHA-256 digest of its password or a salted
+    /// `pbkdf2_sha256$<iterations>$<salt>$<base64 hash>`
+    #[serde(serialize_with = "serialize_redacted_users")]
+    pub users: HashMap<String, String>,
 }
 
-impl Default for GatewayLog {
-    fn default() -> Self {
-        GatewayLog {
-            level: default_log_level(),
-            format: LogFormat::default(),
-            file_path: default_log_file_path(),
-        }
-    }
+/// Answers `403` to clients matching a `deny` range, or outside every `allow` range when any is
+/// set. Ranges are CIDRs such as `10.0.0.0/8` or bare addresses.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct IpFilterConfig {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Wins over `allow`
+    #[serde(default)]
+    pub deny: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct AccessLog {
-    #[serde(default = "default_access_log_enabled")]
-    pub enabled: bool,
-    #[serde(default)]
-    pub format: LogFormat,
-    #[serde(default = "default_log_file_path")]
-    pub file_path: String,
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeySource {
+    RequestHeader(String),
+    /// Query parameter
+    Query(String),
 }
 
-impl Default for AccessLog {
+impl Default for ApiKeySource {
     fn default() -> Self {
-        AccessLog {
-            enabled: default_access_log_enabled(),
-            format: LogFormat::default(),
-            file_path: default_log_file_path(),
-        }
+        ApiKeySource::RequestHeader(String::from("x-api-key"))
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Upstream {
-    pub target: String,
-    #[serde(default = "default_upstream_weight")]
-    pub weight: u32,
+/// Answers `401` without an API key and `403` for a key that is not configured.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiKeyConfig {
+    #[serde(default)]
+    pub source: ApiKeySource,
+    /// API key to the client it identifies, shown as the list of clients by the admin API
+    #[serde(serialize_with = "serialize_redacted_keys")]
+    pub keys: HashMap<String, String>,
+    /// Forwards the client of the key upstream, replacing any value sent by the client
+    pub client_header: Option<String>,
+}
+
+/// Answers `401` unless the request carries a `Bearer` token with a valid signature and claims.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JwtConfig {
+    /// Shared secret of the `HS*` algorithms
+    #[serde(serialize_with = "serialize_redacted_secret")]
+    pub secret: Option<String>,
+    /// JSON Web Key Set with the public keys of the other algorithms
+    pub jwks_url: Option<String>,
+    /// How long fetched keys are used before the set is fetched again
+    #[serde(default = "default_jwks_refresh_interval", with = "humantime_serde")]
+    pub jwks_refresh_interval: Duration,
+    /// `HS256` with a secret and `RS256` with a JWKS URL when empty
+    #[serde(default)]
+    pub algorithms: Vec<String>,
+    /// Required `iss` claim
+    pub issuer: Option<String>,
+    /// Required `aud` claim
+    pub audience: Option<String>,
+    /// Clock skew allowed when checking `exp` and `nbf`
+    #[serde(default, with = "humantime_serde")]
+    pub leeway: Duration,
+    /// Claim name to the request header its value is forwarded in, replacing any sent by the
+    /// client
+    #[serde(default)]
+    pub forward_claims: HashMap<String, String>,
+}
+
+/// Request and response header changes, applied in order: removing, setting, then appending.
+/// `{client_ip}` in a value is replaced by the client IP.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct HeadersConfig {
+    /// Replaces any value sent by the client
+    #[serde(default)]
+    pub request_set: HashMap<String, String>,
+    /// Added next to the values sent by the client
+    #[serde(default)]
+    pub request_append: HashMap<String, String>,
+    #[serde(default)]
+    pub request_remove: Vec<String>,
+    #[serde(default)]
+    pub response_set: HashMap<String, String>,
+    #[serde(default)]
+    pub response_append: HashMap<String, String>,
+    #[serde(default)]
+    pub response_remove: Vec<String>,
+}
+
+/// Regex search and replace on the request path, the query is kept as is.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RewriteConfig {
+    pub from: String,
+    /// Replaces every match of `from`, `$1` or `${name}` refer to its capture groups
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatusRemapConfig {
+    /// Upstream status code to the status code returned to the client
+    pub mappings: HashMap<u16, u16>,
+}
+
+/// Answers preflight requests itself and adds the allowed origin to other responses.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CorsConfig {
+    /// Exact origins, `*` for any origin or subdomain patterns like `https://*.example.com`
+    #[serde(alias = "allow_origins")]
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed by preflight requests
+    #[serde(default = "default_cors_allowed_methods", alias = "allow_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed by preflight requests besides the safelisted ones, `*` allows the
+    /// requested ones
+    #[serde(default, alias = "allow_headers")]
+    pub allowed_headers: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight answer, their own default when omitted
+    #[serde(default, with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RequestDecompressConfig {
+    #[serde(default = "default_request_decompress_encodings")]
+    pub encodings: Vec<ContentEncoding>,
+    /// Upper bound in bytes for the decompressed body, larger bodies are rejected with 413
+    #[serde(default = "default_max_decompressed_size")]
+    pub max_decompressed_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+    Gzip,
+    Br,
+}
+
+impl ContentEncoding {
+    pub fn from_header(value: &str) -> Option<Self> {
+        if value.eq_ignore_ascii_case("gzip") || value.eq_ignore_ascii_case("x-gzip") {
+            Some(ContentEncoding::Gzip)
+        } else if value.eq_ignore_ascii_case("br") {
+            Some(ContentEncoding::Br)
+        } else {
+            None
+        }
+    }
+}
+
+/// Rejects request bodies and `Accept` headers outside the allowed media types.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ContentTypeConfig {
+    /// Media types like `application/json`, `text/*` allows every text subtype
+    pub allowed_types: Vec<String>,
+    /// Answer 406 when no range in the request `Accept` header overlaps `allowed_types`
+    #[serde(default)]
+    pub enforce_accept: bool,
+}
+
+/// Caches `200` responses to `GET` requests in memory, keyed by host and path with query.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CacheConfig {
+    #[serde(default = "default_cache_ttl", with = "humantime_serde")]
+    pub ttl: Duration,
+    /// Total size in bytes of all cached responses, least recently used ones are evicted beyond it
+    #[serde(default = "default_cache_max_bytes")]
+    pub max_bytes: usize,
+    /// Responses larger than this many bytes are never cached
+    #[serde(default = "default_cache_max_entry_bytes")]
+    pub max_entry_bytes: usize,
+    /// Request headers and cookies selecting separate entries, on top of the upstream `Vary`
+    #[serde(default)]
+    pub vary_by: CacheVaryConfig,
+    /// Entries kept per host and path, the oldest variant is evicted beyond it
+    #[serde(default = "default_cache_max_variants")]
+    pub max_variants: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CacheVaryConfig {
+    #[serde(default)]
+    pub headers: Vec<String>,
+    #[serde(default)]
+    pub cookies: Vec<String>,
+}
+
+/// Sends a copy of every request to another upstream, the client only ever gets the primary
+/// response.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MirrorConfig {
+    /// Base url the copies are sent to, e.g. `http://new-backend:8080`
+    pub target: String,
+    #[serde(default = "default_mirror_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// Compares the mirror response with the primary one and logs when they disagree
+    pub compare: Option<MirrorCompareConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MirrorCompareConfig {
+    #[serde(default = "default_mirror_compare_status")]
+    pub status: bool,
+    /// Response headers whose values must match
+    #[serde(default)]
+    pub headers: Vec<String>,
+    /// Compares a hash of the bodies, they are never buffered
+    #[serde(default)]
+    pub body: bool,
+}
+
+/// Answers 503 without calling the upstream while too many of its responses were 5xx, then lets
+/// a single trial request through once the cooldown passed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// Share of 5xx responses within the window opening the circuit, e.g. `0.5`
+    pub failure_threshold: f64,
+    #[serde(default = "default_circuit_breaker_window", with = "humantime_serde")]
+    pub window: Duration,
+    /// How long the circuit stays open before the trial request
+    #[serde(default = "default_circuit_breaker_cooldown", with = "humantime_serde")]
+    pub cooldown: Duration,
+    /// Responses needed within the window before the share is considered
+    #[serde(default = "default_circuit_breaker_min_requests")]
+    pub min_requests: u32,
+}
+
+/// Caps the requests in flight through the middleware, extra ones wait in a bounded queue for a
+/// slot before they are shed with 503.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConcurrencyLimitConfig {
+    pub max_concurrent: usize,
+    /// Requests allowed to wait for a slot, beyond it they are shed right away
+    #[serde(default)]
+    pub queue_depth: usize,
+    /// How long a queued request waits for a slot
+    #[serde(
+        default = "default_concurrency_limit_max_wait",
+        with = "humantime_serde"
+    )]
+    pub max_wait: Duration,
+}
+
+/// Retries requests with a retriable method answered with a retriable status, by default
+/// idempotent requests answered with 502, 503 or 504.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RetryConfig {
+    /// Retries after the first attempt
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: u32,
+    #[serde(default = "default_retry_base_backoff", with = "humantime_serde")]
+    pub base_backoff: Duration,
+    #[serde(default = "default_retry_max_backoff", with = "humantime_serde")]
+    pub max_backoff: Duration,
+    #[serde(default)]
+    pub jitter: RetryJitter,
+    /// Bounds retries to a fraction of requests, unlimited when omitted
+    pub budget: Option<RetryBudgetConfig>,
+    #[serde(default = "default_retry_statuses")]
+    pub statuses: Vec<u16>,
+    /// Replaying other methods could apply a request twice upstream
+    #[serde(default = "default_retry_methods")]
+    pub methods: Vec<String>,
+    /// Retry when the upstream could not be reached or its response could not be read, whatever
+    /// the status the gateway answers with
+    #[serde(default = "default_retry_connection_errors")]
+    pub connection_errors: bool,
+    /// Longest `Retry-After` of a 429 or 503 waited for, longer ones are not retried
+    #[serde(default = "default_retry_max_retry_after", with = "humantime_serde")]
+    pub max_retry_after: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RetryBudgetConfig {
+    /// Retries allowed per request, e.g. `0.1` for at most one retry in ten requests
+    pub ratio: f64,
+    /// Retries that can be banked while the upstream is healthy
+    #[serde(default = "default_retry_budget_burst")]
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryJitter {
+    /// Plain exponential backoff
+    #[default]
+    None,
+    /// Random delay between zero and the exponential backoff
+    Full,
+    /// Random delay between the base and three times the previous delay
+    Decorrelated,
+}
+
+/// Applies the wrapped middleware only when every configured condition matches the request.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WhenConfig {
+    pub methods: Option<Vec<String>>,
+    pub path_prefix: Option<String>,
+    /// Header name to a value that must be contained in the header, empty only requires presence
+    pub headers: Option<HashMap<String, String>>,
+    pub middleware: Box<MiddlewareConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MiddlewareConfig {
+    AddPrefix(AddPrefixConfig),
+    RateLimit(RateLimitConfig),
+    StatusRemap(StatusRemapConfig),
+    When(WhenConfig),
+    Cors(CorsConfig),
+    RequestDecompress(RequestDecompressConfig),
+    Retry(RetryConfig),
+    ContentType(ContentTypeConfig),
+    Cache(CacheConfig),
+    Mirror(MirrorConfig),
+    CircuitBreaker(CircuitBreakerConfig),
+    ConcurrencyLimit(ConcurrencyLimitConfig),
+    QueryRewrite(QueryRewriteConfig),
+    Rewrite(RewriteConfig),
+    Headers(HeadersConfig),
+    BasicAuth(BasicAuthConfig),
+    Jwt(JwtConfig),
+    ApiKey(ApiKeyConfig),
+    IpFilter(IpFilterConfig),
+}
+
+impl MiddlewareConfig {
+    /// Name of the middleware factory this config is meant for.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MiddlewareConfig::AddPrefix(_) => ADD_PREFIX_MIDDLEWARE,
+            MiddlewareConfig::RateLimit(_) => RATE_LIMIT_MIDDLEWARE,
+            MiddlewareConfig::StatusRemap(_) => STATUS_REMAP_MIDDLEWARE,
+            MiddlewareConfig::When(_) => WHEN_MIDDLEWARE,
+            MiddlewareConfig::Cors(_) => CORS_MIDDLEWARE,
+            MiddlewareConfig::RequestDecompress(_) => REQUEST_DECOMPRESS_MIDDLEWARE,
+            MiddlewareConfig::Retry(_) => RETRY_MIDDLEWARE,
+            MiddlewareConfig::ContentType(_) => CONTENT_TYPE_MIDDLEWARE,
+            MiddlewareConfig::Cache(_) => CACHE_MIDDLEWARE,
+            MiddlewareConfig::Mirror(_) => MIRROR_MIDDLEWARE,
+            MiddlewareConfig::CircuitBreaker(_) => CIRCUIT_BREAKER_MIDDLEWARE,
+            MiddlewareConfig::ConcurrencyLimit(_) => CONCURRENCY_LIMIT_MIDDLEWARE,
+            MiddlewareConfig::QueryRewrite(_) => QUERY_REWRITE_MIDDLEWARE,
+            MiddlewareConfig::Rewrite(_) => REWRITE_MIDDLEWARE,
+            MiddlewareConfig::Headers(_) => HEADERS_MIDDLEWARE,
+            MiddlewareConfig::BasicAuth(_) => BASIC_AUTH_MIDDLEWARE,
+            MiddlewareConfig::Jwt(_) => JWT_MIDDLEWARE,
+            MiddlewareConfig::ApiKey(_) => API_KEY_MIDDLEWARE,
+            MiddlewareConfig::IpFilter(_) => IP_FILTER_MIDDLEWARE,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            MiddlewareConfig::AddPrefix(cfg) => {
+                if !cfg.prefix.starts_with('/') {
+                    return Err(format!("prefix must start with '/', found {}", cfg.prefix));
+                }
+                if cfg.prefix.parse::<PathAndQuery>().is_err() || cfg.prefix.contains('?') {
+                    return Err(format!("prefix {} is not a valid path", cfg.prefix));
+                }
+            }
+            MiddlewareConfig::RateLimit(cfg) => {
+                if cfg.limit == 0 {
+                    return Err(String::from("limit must be greater than 0"));
+                }
+                if cfg.period.is_zero() {
+                    return Err(String::from("period must be greater than 0"));
+                }
+            }
+            MiddlewareConfig::StatusRemap(cfg) => {
+                for (from, to) in &cfg.mappings {
+                    for code in [from, to] {
+                        if StatusCode::from_u16(*code).is_err() {
+                            return Err(format!("invalid status code {code}"));
+                        }
+                    }
+                }
+            }
+            MiddlewareConfig::When(cfg) => {
+                for method in cfg.methods.iter().flatten() {
+                    if Method::from_bytes(method.as_bytes()).is_err() {
+                        return Err(format!("invalid method {method}"));
+                    }
+                }
+                for header in cfg.headers.iter().flat_map(|headers| headers.keys()) {
+                    if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                        return Err(format!("invalid header name {header}"));
+                    }
+                }
+                if cfg
+                    .path_prefix
+                    .as_ref()
+                    .is_some_and(|prefix| !prefix.starts_with('/'))
+                {
+                    return Err(String::from("path_prefix must start with '/'"));
+                }
+                cfg.middleware.validate()?;
+            }
+            MiddlewareConfig::RequestDecompress(cfg) => {
+                if cfg.encodings.is_empty() {
+                    return Err(String::from("encodings must not be empty"));
+                }
+                if cfg.max_decompressed_size == 0 {
+                    return Err(String::from("max_decompressed_size must be greater than 0"));
+                }
+            }
+            MiddlewareConfig::Retry(cfg) => {
+                if cfg.base_backoff.is_zero() {
+                    return Err(String::from("base_backoff must be greater than 0"));
+                }
+                if cfg.max_backoff < cfg.base_backoff {
+                    return Err(String::from(
+                        "max_backoff must not be less than base_backoff",
+                    ));
+                }
+                if cfg.statuses.is_empty() && !cfg.connection_errors {
+                    return Err(String::from(
+                        "statuses must not be empty unless connection errors are retried",
+                    ));
+                }
+                for code in &cfg.statuses {
+                    if StatusCode::from_u16(*code).is_err() {
+                        return Err(format!("invalid status code {code}"));
+                    }
+                }
+                if let Some(method) = cfg
+                    .methods
+                    .iter()
+                    .find(|method| parse_method(method).is_none())
+                {
+                    return Err(format!("invalid method {method}"));
+                }
+                if let Some(budget) = &cfg.budget {
+                    if !(budget.ratio > 0.0 && budget.ratio <= 1.0) {
+                        return Err(String::from("budget ratio must be in (0, 1]"));
+                    }
+                    if budget.burst == 0 {
+                        return Err(String::from("budget burst must be greater than 0"));
+                    }
+                }
+            }
+            MiddlewareConfig::Cache(cfg) => {
+                if cfg.ttl.is_zero() {
+                    return Err(String::from("ttl must be greater than 0"));
+                }
+                if cfg.max_entry_bytes == 0 {
+                    return Err(String::from("max_entry_bytes must be greater than 0"));
+                }
+                if cfg.max_bytes < cfg.max_entry_bytes {
+                    return Err(String::from(
+                        "max_bytes must not be less than max_entry_bytes",
+                    ));
+                }
+                if cfg.max_variants == 0 {
+                    return Err(String::from("max_variants must be greater than 0"));
+                }
+                for header in &cfg.vary_by.headers {
+                    if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                        return Err(format!("invalid vary_by header {header}"));
+                    }
+                }
+                for cookie in &cfg.vary_by.cookies {
+                    if cookie.is_empty()
+                        || cookie
+                            .bytes()
+                            .any(|b| b.is_ascii_whitespace() || matches!(b, b';' | b'=' | b','))
+                    {
+                        return Err(format!("invalid vary_by cookie {cookie}"));
+                    }
+                }
+            }
+            MiddlewareConfig::CircuitBreaker(cfg) => {
+                if !(cfg.failure_threshold > 0.0 && cfg.failure_threshold <= 1.0) {
+                    return Err(String::from("failure_threshold must be in (0, 1]"));
+                }
+                if cfg.window.is_zero() || cfg.cooldown.is_zero() {
+                    return Err(String::from("window and cooldown must be greater than 0"));
+                }
+                if cfg.min_requests == 0 {
+                    return Err(String::from("min_requests must be greater than 0"));
+                }
+            }
+            MiddlewareConfig::QueryRewrite(cfg) => {
+                if cfg.add.is_empty() && cfg.remove.is_empty() && cfg.rename.is_empty() {
+                    return Err(String::from("add, remove or rename must not be empty"));
+                }
+                let names = cfg.add.keys().chain(&cfg.remove).chain(cfg.rename.keys());
+                if names.chain(cfg.rename.values()).any(String::is_empty) {
+                    return Err(String::from("query parameter names must not be empty"));
+                }
+                if let Some(name) = cfg
+                    .remove
+                    .iter()
+                    .find(|name| cfg.rename.contains_key(*name))
+                {
+                    return Err(format!(
+                        "query parameter {name} is both removed and renamed"
+                    ));
+                }
+            }
+            MiddlewareConfig::Rewrite(cfg) => {
+                if let Err(err) = Regex::new(&cfg.from) {
+                    return Err(format!("invalid from regex {}: {err}", cfg.from));
+                }
+            }
+            MiddlewareConfig::Headers(cfg) => {
+                let changes = [&cfg.request_set, &cfg.request_append]
+                    .into_iter()
+                    .chain([&cfg.response_set, &cfg.response_append])
+                    .flatten();
+                let removed = cfg.request_remove.iter().chain(&cfg.response_remove);
+                if changes.clone().next().is_none() && removed.clone().next().is_none() {
+                    return Err(String::from("no header to set, append or remove"));
+                }
+                for name in changes.clone().map(|(name, _)| name).chain(removed) {
+                    if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                        return Err(format!("invalid header name {name}"));
+                    }
+                }
+                for (_, value) in changes {
+                    let sample = value.replace("{client_ip}", "127.0.0.1");
+                    if HeaderValue::from_str(&sample).is_err() {
+                        return Err(format!("invalid header value {value}"));
+                    }
+                }
+            }
+            MiddlewareConfig::BasicAuth(cfg) => {
+                if cfg.users.is_empty() {
+                    return Err(String::from("users must not be empty"));
+                }
+                if cfg.realm.contains('"') || HeaderValue::from_str(&cfg.realm).is_err() {
+                    return Err(format!("invalid realm {}", cfg.realm));
+                }
+                for (name, hash) in &cfg.users {
+                    if name.is_empty() || name.contains(':') {
+                        return Err(format!("invalid user name {name}"));
+                    }
+                    if parse_password_hash(hash).is_none() {
+                        return Err(format!(
+                            "password of user {name} must be a hex SHA-256 digest or a pbkdf2_sha256 hash"
+                        ));
+                    }
+                }
+            }
+            MiddlewareConfig::Jwt(cfg) => {
+                jwt_algorithms(cfg)?;
+                if let Some(url) = &cfg.jwks_url
+                    && !reqwest::Url::parse(url)
+                        .is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+                {
+                    return Err(format!("jwks_url {url} is not an http(s) url"));
+                }
+                if cfg.jwks_refresh_interval.is_zero() {
+                    return Err(String::from("jwks_refresh_interval must be greater than 0"));
+                }
+                for header in cfg.forward_claims.values() {
+                    if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                        return Err(format!("invalid header name {header}"));
+                    }
+                }
+            }
+            MiddlewareConfig::ApiKey(cfg) => {
+                if cfg.keys.is_empty() || cfg.keys.contains_key("") {
+                    return Err(String::from("keys must not be empty"));
+                }
+                match &cfg.source {
+                    ApiKeySource::RequestHeader(header) => {
+                        if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                            return Err(format!("invalid header name {header}"));
+                        }
+                    }
+                    ApiKeySource::Query(name) => {
+                        if name.is_empty() {
+                            return Err(String::from("query parameter name must not be empty"));
+                        }
+                    }
+                }
+                if let Some(header) = &cfg.client_header {
+                    if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                        return Err(format!("invalid header name {header}"));
+                    }
+                    for client in cfg.keys.values() {
+                        if HeaderValue::from_str(client).is_err() {
+                            return Err(format!("invalid client name {client}"));
+                        }
+                    }
+                }
+            }
+            MiddlewareConfig::IpFilter(cfg) => {
+                if cfg.allow.is_empty() && cfg.deny.is_empty() {
+                    return Err(String::from("allow or deny must be set"));
+                }
+                for range in cfg.allow.iter().chain(&cfg.deny) {
+                    if parse_ip_range(range).is_none() {
+                        return Err(format!("invalid ip range {range}"));
+                    }
+                }
+            }
+            MiddlewareConfig::ConcurrencyLimit(cfg) => {
+                if cfg.max_concurrent == 0 {
+                    return Err(String::from("max_concurrent must be greater than 0"));
+                }
+                if cfg.queue_depth > 0 && cfg.max_wait.is_zero() {
+                    return Err(String::from(
+                        "max_wait must be greater than 0 when requests are queued",
+                    ));
+                }
+            }
+            MiddlewareConfig::Mirror(cfg) => {
+                let valid_target = reqwest::Url::parse(&cfg.target)
+                    .is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+                if !valid_target {
+                    return Err(format!(
+                        "mirror target {} is not an http(s) url",
+                        cfg.target
+                    ));
+                }
+                if cfg.timeout.is_zero() {
+                    return Err(String::from("timeout must be greater than 0"));
+                }
+                for header in cfg.compare.iter().flat_map(|compare| &compare.headers) {
+                    if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                        return Err(format!("invalid compare header {header}"));
+                    }
+                }
+            }
+            MiddlewareConfig::ContentType(cfg) => {
+                if cfg.allowed_types.is_empty() {
+                    return Err(String::from("allowed_types must not be empty"));
+                }
+                for allowed in &cfg.allowed_types {
+                    let valid = allowed
+                        .split_once('/')
+                        .is_some_and(|(main_type, sub_type)| {
+                            !main_type.is_empty()
+                                && !sub_type.is_empty()
+                                && (main_type != "*" || sub_type == "*")
+                                && !allowed.contains(';')
+                        });
+                    if !valid {
+                        return Err(format!("invalid allowed type {allowed}"));
+                    }
+                }
+            }
+            MiddlewareConfig::Cors(cfg) => {
+                for origin in &cfg.allowed_origins {
+                    let wildcards = origin.matches('*').count();
+                    if origin.is_empty()
+                        || (origin != "*" && wildcards > 0 && !origin.contains("://*."))
+                        || wildcards > 1
+                    {
+                        return Err(format!("invalid allowed origin {origin}"));
+                    }
+                }
+                // Browsers reject `*` on credentialed requests, reflecting any origin instead
+                // would let every site read authenticated responses
+                if cfg.allow_credentials && cfg.allowed_origins.iter().any(|origin| origin == "*") {
+                    return Err(String::from(
+                        "allow_credentials must not be combined with the `*` origin",
+                    ));
+                }
+                if let Some(method) = cfg
+                    .allowed_methods
+                    .iter()
+                    .find(|method| parse_method(method).is_none())
+                {
+                    return Err(format!("invalid method {method}"));
+                }
+                if let Some(header) = cfg.allowed_headers.iter().find(|header| {
+                    *header != "*" && HeaderName::from_bytes(header.as_bytes()).is_err()
+                }) {
+                    return Err(format!("invalid header name {header}"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct GatewayLog {
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default = "default_log_file_path")]
+    pub file_path: String,
+}
+
+impl Default for GatewayLog {
+    fn default() -> Self {
+        GatewayLog {
+            level: default_log_level(),
+            format: LogFormat::default(),
+            file_path: default_log_file_path(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AccessLog {
+    #[serde(default = "default_access_log_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default = "default_log_file_path")]
+    pub file_path: String,
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        AccessLog {
+            enabled: default_access_log_enabled(),
+            format: LogFormat::default(),
+            file_path: default_log_file_path(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Upstream {
+    pub target: String,
+    /// Label exposed in place of the target, e.g. by the served by header
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default = "default_upstream_weight")]
+    pub weight: u32,
+    /// Server name presented in the TLS handshake to an `https` upstream addressed by IP
+    pub tls_sni: Option<String>,
+}
+
+/// IP address host of an upstream url, `None` for names.
+pub fn upstream_ip(url: &reqwest::Url) -> Option<IpAddr> {
+    url.host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Upstream names are sent in response headers and must tell the upstreams of a service apart.
+fn validate_upstream_names(upstreams: &[Upstream]) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for name in upstreams
+        .iter()
+        .filter_map(|upstream| upstream.name.as_deref())
+    {
+        if name.is_empty() || HeaderValue::from_str(name).is_err() {
+            return Err(format!("Invalid upstream name {name:?}"));
+        }
+        // Unnamed upstreams go by their index
+        if name.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("Upstream name {name} must not be a number"));
+        }
+        if !seen.insert(name) {
+            return Err(format!("Duplicate upstream name {name}"));
+        }
+    }
+    Ok(())
+}
+
+impl Upstream {
+    /// The SNI override requires an `https` target with an IP address host.
+    fn validate_tls_sni(&self) -> Result<(), String> {
+        let Some(sni) = &self.tls_sni else {
+            return Ok(());
+        };
+        match ServerName::try_from(sni.as_str()) {
+            Ok(ServerName::DnsName(_)) => {}
+            _ => return Err(format!("tls_sni {sni} is not a valid DNS name")),
+        }
+        let url = reqwest::Url::parse(&self.target)
+            .map_err(|err| format!("Invalid upstream target {}: {err}", self.target))?;
+        if url.scheme() != "https" || upstream_ip(&url).is_none() {
+            return Err(format!(
+                "tls_sni requires an https upstream addressed by IP, got {}",
+                self.target
+            ));
+        }
+        Ok(())
+    }
 }
 
 fn default_log_level() -> String {
     "INFO".to_string()
 }
 
-fn default_access_log_enabled() -> bool {
+fn default_access_log_enabled() -> bool {
+    true
+}
+
+fn default_log_file_path() -> String {
+    "stdout".to_string()
+}
+
+fn default_served_by_header() -> String {
+    "x-served-by".to_string()
+}
+
+fn default_upstream_override_header() -> String {
+    String::from("x-portiq-upstream")
+}
+
+fn default_debug_echo_path() -> String {
+    "/_portiq/echo".to_string()
+}
+
+fn default_upstream_request_id_header() -> String {
+    "x-request-id".to_string()
+}
+
+fn default_strip_default_headers() -> bool {
+    true
+}
+
+fn default_http2_keep_alive_timeout() -> Duration {
+    Duration::from_secs(20)
+}
+
+fn default_http2_keep_alive_while_idle() -> bool {
     true
 }
 
-fn default_log_file_path() -> String {
-    "stdout".to_string()
+fn default_request_decompress_encodings() -> Vec<ContentEncoding> {
+    vec![ContentEncoding::Gzip, ContentEncoding::Br]
+}
+
+fn default_max_decompressed_size() -> usize {
+    10 * 1024 * 1024
+}
+
+fn default_retry_attempts() -> u32 {
+    2
+}
+
+fn default_retry_base_backoff() -> Duration {
+    Duration::from_millis(100)
+}
+
+fn default_retry_max_backoff() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_basic_auth_realm() -> String {
+    String::from("portiq")
+}
+
+fn default_jwks_refresh_interval() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_retry_statuses() -> Vec<u16> {
+    vec![502, 503, 504]
+}
+
+fn default_retry_methods() -> Vec<String> {
+    ["GET", "HEAD", "OPTIONS", "PUT", "DELETE", "TRACE"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_retry_connection_errors() -> bool {
+    true
+}
+
+fn default_retry_max_retry_after() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_health_check_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_health_check_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_health_check_threshold() -> u32 {
+    1
+}
+
+fn default_passive_health_consecutive_failures() -> u32 {
+    5
+}
+
+fn default_max_redirects() -> usize {
+    10
+}
+
+fn default_upstream_connect_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_upstream_request_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_passive_health_ejection_duration() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_passive_health_max_ejection_percent() -> u8 {
+    50
+}
+
+fn default_retry_budget_burst() -> u32 {
+    10
+}
+
+fn default_mirror_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_mirror_compare_status() -> bool {
+    true
+}
+
+fn default_cache_ttl() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_cache_max_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_cache_max_entry_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_cache_max_variants() -> usize {
+    8
+}
+
+fn default_ab_test_cookie() -> String {
+    String::from("portiq_ab")
+}
+
+fn default_ab_test_weight() -> u32 {
+    1
+}
+
+fn default_canary_rollback_window() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_canary_rollback_min_requests() -> u32 {
+    10
+}
+
+fn default_circuit_breaker_window() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_circuit_breaker_cooldown() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_circuit_breaker_min_requests() -> u32 {
+    10
+}
+
+fn default_concurrency_limit_max_wait() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_drain_delay() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_max_concurrent_handshakes() -> usize {
+    1024
+}
+
+fn default_request_body_buffer_threshold() -> usize {
+    1024 * 1024
+}
+
+fn default_paused_route_status() -> u16 {
+    503
+}
+
+fn default_max_uri_length() -> usize {
+    8192
 }
 
 fn default_upstream_weight() -> u32 {
@@ -293,9 +2571,127 @@ fn default_config_version() -> u8 {
     1
 }
 
+/// Shown by the admin API in place of credentials.
+const REDACTED: &str = "***";
+
+fn serialize_redacted_secret<S: Serializer>(
+    secret: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    secret.as_ref().map(|_| REDACTED).serialize(serializer)
+}
+
+fn serialize_redacted_url<S: Serializer>(url: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    redact_url_credentials(url).serialize(serializer)
+}
+
+/// `url` with the credentials of its userinfo replaced, unparsable urls holding an `@` entirely.
+fn redact_url_credentials(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut parsed) if !parsed.username().is_empty() || parsed.password().is_some() => {
+            let _ = parsed.set_username(REDACTED);
+            if parsed.password().is_some() {
+                let _ = parsed.set_password(Some(REDACTED));
+            }
+            parsed.to_string()
+        }
+        Ok(_) => url.to_string(),
+        Err(_) if url.contains('@') => REDACTED.to_string(),
+        Err(_) => url.to_string(),
+    }
+}
+
+fn serialize_redacted_keys<S: Serializer>(
+    keys: &HashMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut clients = keys.values().collect::<Vec<_>>();
+    clients.sort();
+    clients.serialize(serializer)
+}
+
+fn serialize_redacted_users<S: Serializer>(
+    users: &HashMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(users.keys().map(|name| (name, REDACTED)))
+}
+
+/// Replaces the credentials of the middlewares in a raw config, the way serializing a
+/// `GatewayConfig` does.
+pub fn redact_raw_config(config: &mut serde_json::Value) {
+    if let Some(middlewares) = config
+        .pointer_mut("/http/middlewares")
+        .and_then(|middlewares| middlewares.as_object_mut())
+    {
+        middlewares.values_mut().for_each(redact_raw_middleware);
+    }
+    if let Some(services) = config
+        .pointer_mut("/http/services")
+        .and_then(|services| services.as_object_mut())
+    {
+        for proxy in services
+            .values_mut()
+            .filter_map(|service| service.get_mut("proxy"))
+        {
+            if let Some(password) = proxy.get_mut("password")
+                && !password.is_null()
+            {
+                *password = REDACTED.into();
+            }
+            if let Some(url) = proxy.get_mut("url")
+                && let Some(redacted) = url.as_str().map(redact_url_credentials)
+            {
+                *url = redacted.into();
+            }
+        }
+    }
+}
+
+fn redact_raw_middleware(middleware: &mut serde_json::Value) {
+    if let Some(secret) = middleware.pointer_mut("/jwt/secret")
+        && !secret.is_null()
+    {
+        *secret = REDACTED.into();
+    }
+    if let Some(keys) = middleware.pointer_mut("/api_key/keys")
+        && let Some(key_clients) = keys.as_object()
+    {
+        let mut clients = key_clients.values().cloned().collect::<Vec<_>>();
+        clients.sort_by_key(|client| client.to_string());
+        *keys = clients.into();
+    }
+    if let Some(users) = middleware
+        .pointer_mut("/basic_auth/users")
+        .and_then(|users| users.as_object_mut())
+    {
+        users
+            .values_mut()
+            .for_each(|password| *password = REDACTED.into());
+    }
+    if let Some(inner) = middleware.pointer_mut("/when/middleware") {
+        redact_raw_middleware(inner);
+    }
+}
+
+/// The config file as written, without defaults applied or validation.
+pub fn load_raw_config() -> Result<serde_json::Value, String> {
+    let file_path = CONFIG_FILE_PATH.get().ok_or("Config file path not found")?;
+
+    Config::builder()
+        .add_source(File::with_name(file_path))
+        .build()
+        .map_err(|err| err.to_string())?
+        .try_deserialize::<serde_json::Value>()
+        .map_err(|err| err.to_string())
+}
+
 pub fn load_config() -> Result<GatewayConfig, String> {
     let file_path = CONFIG_FILE_PATH.get().ok_or("Config file path not found")?;
+    load_config_file(file_path)
+}
 
+fn load_config_file(file_path: &str) -> Result<GatewayConfig, String> {
     let cfg = Config::builder()
         .add_source(File::with_name(file_path))
         .build()
@@ -306,8 +2702,105 @@ pub fn load_config() -> Result<GatewayConfig, String> {
     cfg.validate().map_or_else(Err, |_| Ok(cfg))
 }
 
-pub fn reload_config(current_state: SharedGatewayState) -> Result<(), String> {
-    let cfg = load_config()?;
+/// Outcome of checking a config file without starting the gateway.
+#[derive(Debug, Serialize)]
+pub struct ValidationReport {
+    pub ok: bool,
+    pub errors: Vec<ValidationIssue>,
+    pub warnings: Vec<ValidationIssue>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidationIssue {
+    pub message: String,
+}
+
+impl ValidationReport {
+    // Validation stops at the first error, so at most one error is reported
+    pub fn from_result(result: &Result<GatewayConfig, String>) -> Self {
+        let errors = match result {
+            Ok(_) => Vec::new(),
+            Err(err) => vec![ValidationIssue {
+                message: err.clone(),
+            }],
+        };
+        ValidationReport {
+            ok: errors.is_empty(),
+            errors,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        if self.ok {
+            return String::from("Config is valid");
+        }
+        self.errors
+            .iter()
+            .map(|issue| format!("error: {}", issue.message))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Validation report is serializable")
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReloadSource {
+    AdminApi,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReloadStatus {
+    #[serde(with = "humantime_serde")]
+    pub timestamp: SystemTime,
+    pub source: ReloadSource,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+pub fn reload_config(
+    current_state: SharedGatewayState,
+    source: ReloadSource,
+) -> Result<(), String> {
+    reload_config_file(
+        CONFIG_FILE_PATH.get().map(String::as_str),
+        current_state,
+        source,
+    )
+}
+
+/// Applies the file and records the outcome on the gateway state, whichever way it was triggered.
+fn reload_config_file(
+    file_path: Option<&str>,
+    current_state: SharedGatewayState,
+    source: ReloadSource,
+) -> Result<(), String> {
+    let result = file_path
+        .ok_or_else(|| String::from("Config file path not found"))
+        .and_then(|file_path| apply_config_from_file(file_path, current_state.clone()));
+    if let Err(err) = &result {
+        tracing::error!("Config reload failed, keeping the running config: {err}");
+    }
+    current_state.load().record_reload(ReloadStatus {
+        timestamp: SystemTime::now(),
+        source,
+        success: result.is_ok(),
+        error: result.as_ref().err().cloned(),
+    });
+    result
+}
+
+/// Swaps in a runtime built from the file, the running one stays untouched on any failure
+/// before that, e.g. a missing, half written or invalid file.
+fn apply_config_from_file(
+    file_path: &str,
+    current_state: SharedGatewayState,
+) -> Result<(), String> {
+    let cfg = load_config_file(file_path)?;
     {
         let current_state = current_state.load();
         // perform validations for non-reloadable values, currently reject if anything changes
@@ -321,8 +2814,10 @@ pub fn reload_config(current_state: SharedGatewayState) -> Result<(), String> {
 
     // Build new gateway runtime and swap
     let new_config = Arc::new(cfg);
-    let new_runtime = GatewayRuntime::new(new_config);
+    let new_runtime = current_state.load().reload(new_config.clone())?;
     current_state.store(Arc::new(new_runtime));
+    MIDDLEWARE_REGISTRY.retain(&new_config.http.middlewares);
+    LIFECYCLE.publish(LifecycleEvent::ConfigReloaded);
 
     Ok(())
 }
@@ -335,3 +2830,831 @@ fn static_config_same(previous: &GatewayConfig, new: &GatewayConfig) -> bool {
         && previous.tls == new.tls
         && previous.listeners == new.listeners
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway_runtime::GatewayRuntime;
+    use config::FileFormat;
+
+    fn parse_config(config: &str) -> Result<GatewayConfig, String> {
+        let cfg = Config::builder()
+            .add_source(File::from_str(config, FileFormat::Yaml))
+            .build()
+            .map_err(|err| err.to_string())?
+            .try_deserialize::<GatewayConfig>()
+            .map_err(|err| err.to_string())?;
+        cfg.validate().map(|_| cfg)
+    }
+
+    fn config_with_middleware(middleware: &str) -> String {
+        format!(
+            r#"
+            listeners:
+              - name: http-main
+                addr: 0.0.0.0:3000
+
+            http:
+              middlewares:
+                test-middleware:
+{middleware}
+
+              services:
+                echo-service:
+                  upstreams:
+                    - target: http://localhost:4000
+
+              routes:
+                - path: /echo
+                  listeners: [ http-main ]
+                  service: echo-service
+                  middlewares: [ test-middleware ]
+            "#
+        )
+    }
+
+    #[test]
+    fn test_valid_middleware_config_is_accepted() {
+        let config = config_with_middleware(
+            "                  rate_limit:\n                    limit: 2\n                    period: 10s",
+        );
+        assert!(parse_config(&config).is_ok());
+    }
+
+    #[test]
+    fn test_status_remap_config_is_parsed() {
+        let config = config_with_middleware(
+            "                  status_remap:\n                    mappings:\n                      418: 503\n                      500: 502",
+        );
+        let config = parse_config(&config).unwrap();
+        assert_eq!(
+            config.http.middlewares["test-middleware"],
+            MiddlewareConfig::StatusRemap(StatusRemapConfig {
+                mappings: HashMap::from([(418, 503), (500, 502)]),
+            })
+        );
+
+        let config = config_with_middleware(
+            "                  status_remap:\n                    mappings:\n                      418: 1000",
+        );
+        assert!(parse_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_strip_response_headers_defaults_can_be_extended_or_disabled() {
+        let config = parse_config(&config_with_middleware(
+            "                  add_prefix:\n                    prefix: /api",
+        ))
+        .unwrap();
+        let names = config.http.strip_response_headers.header_names();
+        assert_eq!(names.len(), DEFAULT_STRIPPED_RESPONSE_HEADERS.len());
+
+        let strip_config: StripResponseHeadersConfig =
+            serde_json::from_str(r#"{ "defaults": false, "headers": ["X-Internal-Host"] }"#)
+                .unwrap();
+        assert_eq!(
+            strip_config.header_names(),
+            vec![HeaderName::from_static("x-internal-host")]
+        );
+    }
+
+    #[test]
+    fn test_validation_report_json_lists_errors() {
+        let config = config_with_middleware(
+            "                  add_prefix:\n                    prefix: /api",
+        )
+        .replace("service: echo-service", "service: missing-service");
+        let report = ValidationReport::from_result(&parse_config(&config));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["ok"], false);
+        assert_eq!(
+            json["errors"],
+            serde_json::json!([{ "message": "Undefined service missing-service" }])
+        );
+        assert_eq!(json["warnings"], serde_json::json!([]));
+
+        let valid = config_with_middleware(
+            "                  add_prefix:\n                    prefix: /api",
+        );
+        let report = ValidationReport::from_result(&parse_config(&valid));
+        assert!(report.ok && report.errors.is_empty());
+    }
+
+    #[test]
+    fn test_mismatched_middleware_config_is_rejected() {
+        // Every rate limit field is present, but the limit is no number
+        let config = config_with_middleware(
+            "                  rate_limit:\n                    limit: two\n                    period: 10s",
+        );
+        let err = parse_config(&config).unwrap_err();
+        assert_eq!(
+            err,
+            "invalid type: string \"two\", expected an integer for key `http.middlewares.test-middleware.limit`"
+        );
+    }
+
+    #[test]
+    fn test_invalid_middleware_values_are_rejected() {
+        let config = config_with_middleware(
+            "                  rate_limit:\n                    limit: 0\n                    period: 10s",
+        );
+        let err = parse_config(&config).unwrap_err();
+        assert!(err.contains("test-middleware"), "{err}");
+        assert!(err.contains("limit must be greater than 0"), "{err}");
+
+        let config = config_with_middleware(
+            "                  add_prefix:\n                    prefix: api",
+        );
+        let err = parse_config(&config).unwrap_err();
+        assert!(err.contains("prefix must start with '/'"), "{err}");
+
+        let config = config_with_middleware(
+            "                  rewrite:\n                    from: '^/api/(.*$'\n                    to: /$1",
+        );
+        let err = parse_config(&config).unwrap_err();
+        assert!(err.contains("invalid from regex"), "{err}");
+
+        let config = config_with_middleware(
+            "                  cors:\n                    allowed_origins: [ '*' ]\n                    allow_credentials: true",
+        );
+        let err = parse_config(&config).unwrap_err();
+        assert!(
+            err.contains("allow_credentials must not be combined with the `*` origin"),
+            "{err}"
+        );
+
+        let config = config_with_middleware(
+            "                  jwt:\n                    secret: s3cret\n                    algorithms: [ RS256 ]",
+        );
+        let err = parse_config(&config).unwrap_err();
+        assert!(err.contains("algorithm RS256 needs a jwks_url"), "{err}");
+
+        let config = config_with_middleware(
+            "                  jwt:\n                    secret: s3cret\n                    jwks_url: https://auth.example.com/jwks.json",
+        );
+        let err = parse_config(&config).unwrap_err();
+        assert!(
+            err.contains("exactly one of secret or jwks_url must be set"),
+            "{err}"
+        );
+
+        let config = config_with_middleware(
+            "                  api_key:\n                    source: { query: '' }\n                    keys: { abc: partner }",
+        );
+        let err = parse_config(&config).unwrap_err();
+        assert!(
+            err.contains("query parameter name must not be empty"),
+            "{err}"
+        );
+
+        let config = config_with_middleware(
+            "                  ip_filter:\n                    allow: [ 10.0.0.0/8 ]\n                    deny: [ 10.0.0.256/24 ]",
+        );
+        let err = parse_config(&config).unwrap_err();
+        assert!(err.contains("invalid ip range 10.0.0.256/24"), "{err}");
+    }
+
+    #[test]
+    fn test_invalid_upstream_template_is_rejected() {
+        let route = |hosts: &str, template: &str| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  services: {{}}
+                  routes:
+                    - hosts: {hosts}
+                      listeners: [ http-main ]
+                      upstream_template: "{template}"
+                "#
+            )
+        };
+        assert!(
+            parse_config(&route(
+                "[ \"*.example.com\" ]",
+                "http://{host_label_0}.internal"
+            ))
+            .is_ok()
+        );
+
+        for (hosts, template, expected) in [
+            (
+                "[ \"*.example.com\" ]",
+                "http://{host_label_3}.internal",
+                "out of range",
+            ),
+            (
+                "[ \"*.example.com\" ]",
+                "http://{tenant}.internal",
+                "unknown placeholder",
+            ),
+            (
+                "[ \"*.example.com\" ]",
+                "http://{host_label_0.internal",
+                "unclosed",
+            ),
+            (
+                "[ \"*.example.com\" ]",
+                "{host_label_0}",
+                "not a valid http(s) URL",
+            ),
+            ("[]", "http://{host}", "hosts are required"),
+        ] {
+            let err = parse_config(&route(hosts, template)).unwrap_err();
+            assert!(err.contains(expected), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_path_regex_is_rejected() {
+        let route = |path: &str| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  services: {{}}
+                  routes:
+                    - path: '{path}'
+                      listeners: [ http-main ]
+                      upstreams:
+                        - target: http://localhost:4000
+                "#
+            )
+        };
+        assert!(parse_config(&route(r"~^/users/\d+$")).is_ok());
+        // Only paths starting with `~` are regexes
+        assert!(parse_config(&route("/users/(")).is_ok());
+        let err = parse_config(&route("~^/users/(\\d+$")).unwrap_err();
+        assert!(err.contains("Invalid path regex"), "{err}");
+    }
+
+    #[test]
+    fn test_tcp_options_are_parsed_and_validated() {
+        let listener = |tcp: &str| {
+            format!(
+                r#"
+                listeners:
+                  - name: tcp-main
+                    addr: 0.0.0.0:5000
+                    protocol: tcp
+                    tcp: {tcp}
+                "#
+            )
+        };
+        let cfg = parse_config(&listener(
+            "{ nodelay: true, keepalive: 60s, send_buffer_size: 65536 }",
+        ))
+        .unwrap();
+        let tcp = &cfg.listeners[0].tcp;
+        assert_eq!(tcp.nodelay, Some(true));
+        assert_eq!(tcp.keepalive, Some(Duration::from_secs(60)));
+        assert_eq!(tcp.send_buffer_size, Some(65536));
+        assert_eq!(tcp.reuse_port, None);
+
+        let err = parse_config(&listener("{ keepalive: 0s }")).unwrap_err();
+        assert!(err.contains("keepalive must be greater than 0"), "{err}");
+        let err = parse_config(&listener("{ recv_buffer_size: 0 }")).unwrap_err();
+        assert!(
+            err.contains("Invalid tcp options for listener tcp-main"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_acceptors_config_is_validated() {
+        let listener = |acceptors: &str, tcp: &str| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+                    acceptors: {acceptors}
+                    tcp: {tcp}
+                "#
+            )
+        };
+        let cfg = parse_config(&listener("{ enabled: true, count: 4 }", "{}")).unwrap();
+        assert_eq!(
+            cfg.listeners[0].acceptors.effective_count(),
+            if REUSE_PORT_SUPPORTED { 4 } else { 1 }
+        );
+        let cfg = parse_config(&listener("{ count: 4 }", "{}")).unwrap();
+        assert_eq!(cfg.listeners[0].acceptors.effective_count(), 1);
+
+        let err = parse_config(&listener("{ enabled: true, count: 0 }", "{}")).unwrap_err();
+        assert!(
+            err.contains("acceptors.count must be greater than 0"),
+            "{err}"
+        );
+        let err =
+            parse_config(&listener("{ enabled: true }", "{ reuse_port: false }")).unwrap_err();
+        assert!(err.contains("acceptors require tcp.reuse_port"), "{err}");
+    }
+
+    #[test]
+    fn test_upstream_override_needs_valid_trusted_ips() {
+        let config = |trusted_ips: &str| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  upstream_override:
+                    trusted_ips: {trusted_ips}
+                  services: {{}}
+                  routes: []
+                "#
+            )
+        };
+        let cfg = parse_config(&config("[ 10.0.0.0/8, '::1' ]")).unwrap();
+        let upstream_override = cfg.http.upstream_override.unwrap();
+        assert_eq!(upstream_override.header, "x-portiq-upstream");
+        assert!(upstream_override.is_trusted("10.1.2.3".parse().unwrap()));
+        assert!(upstream_override.is_trusted("::1".parse().unwrap()));
+        assert!(!upstream_override.is_trusted("192.168.0.1".parse().unwrap()));
+
+        let err = parse_config(&config("[]")).unwrap_err();
+        assert!(err.contains("upstream_override needs trusted_ips"), "{err}");
+        let err = parse_config(&config("[ 10.0.0.0/33 ]")).unwrap_err();
+        assert!(
+            err.contains("Invalid upstream_override trusted ip 10.0.0.0/33"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_ab_test_config_is_validated() {
+        let route = |ab_test: &str| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  services:
+                    control:
+                      upstreams:
+                        - target: http://localhost:4000
+                    redesign:
+                      upstreams:
+                        - target: http://localhost:4001
+                  routes:
+                    - path: /*
+                      listeners: [ http-main ]
+                      service: control
+                      ab_test: {ab_test}
+                "#
+            )
+        };
+        let config = parse_config(&route(
+            "{ key: { header: x-user-id }, variants: [ { name: a, service: control }, { name: b, service: redesign, weight: 3 } ] }",
+        ))
+        .unwrap();
+        let ab_test = config.http.routes[0].ab_test.as_ref().unwrap();
+        assert_eq!(ab_test.cookie, "portiq_ab");
+        assert_eq!(
+            ab_test.key,
+            Some(AbTestKey::Header(String::from("x-user-id")))
+        );
+        assert_eq!(ab_test.variants[0].weight, 1);
+
+        for (ab_test, expected) in [
+            ("{ variants: [] }", "needs at least one variant"),
+            (
+                "{ variants: [ { name: a, service: missing } ] }",
+                "Undefined ab_test service missing",
+            ),
+            (
+                "{ variants: [ { name: a, service: control, weight: 0 } ] }",
+                "needs a cookie-safe name",
+            ),
+            (
+                "{ variants: [ { name: 'a;b', service: control } ] }",
+                "needs a cookie-safe name",
+            ),
+            (
+                "{ variants: [ { name: a, service: control }, { name: a, service: redesign } ] }",
+                "Duplicate ab_test variant a",
+            ),
+            (
+                "{ cookie: 'ab test', variants: [ { name: a, service: control } ] }",
+                "Invalid ab_test cookie name",
+            ),
+        ] {
+            let err = parse_config(&route(ab_test)).unwrap_err();
+            assert!(err.contains(expected), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_load_balancing_strategy_round_trips() {
+        let service = |strategy: &str, weight: u32| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  services:
+                    balanced:
+                      upstreams:
+                        - target: http://localhost:4000
+                          weight: {weight}
+                      {strategy}
+                  routes: []
+                "#
+            )
+        };
+        let strategy_of = |config: &GatewayConfig| config.http.services["balanced"].strategy;
+
+        let config = parse_config(&service("", 1)).unwrap();
+        assert_eq!(
+            strategy_of(&config),
+            LoadBalancingStrategy::WeightedRoundRobin
+        );
+        for (name, expected) in [
+            ("round_robin", LoadBalancingStrategy::RoundRobin),
+            ("random", LoadBalancingStrategy::Random),
+            ("least_connections", LoadBalancingStrategy::LeastConnections),
+            ("ip_hash", LoadBalancingStrategy::IpHash),
+        ] {
+            let config = parse_config(&service(&format!("strategy: {name}"), 1)).unwrap();
+            assert_eq!(strategy_of(&config), expected);
+            let json = serde_json::to_value(&config).unwrap();
+            assert_eq!(json["http"]["services"]["balanced"]["strategy"], name);
+            let round_tripped: GatewayConfig = serde_json::from_value(json).unwrap();
+            assert_eq!(round_tripped, config);
+        }
+
+        // Only weighted strategies need a non-zero weight
+        assert!(parse_config(&service("strategy: round_robin", 0)).is_ok());
+        let err = parse_config(&service("strategy: ip_hash", 0)).unwrap_err();
+        assert!(err.contains("weight greater than 0"), "{err}");
+        assert!(parse_config(&service("strategy: sticky", 1)).is_err());
+    }
+
+    #[test]
+    fn test_absurd_upstream_weights_are_rejected() {
+        let service = |strategy: &str, first: u32, second: u32| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  services:
+                    balanced:
+                      strategy: {strategy}
+                      upstreams:
+                        - target: http://localhost:4000
+                          weight: {first}
+                        - target: http://localhost:4001
+                          weight: {second}
+                  routes: []
+                "#
+            )
+        };
+
+        // Large weights sharing a divisor reduce to a small ratio
+        assert!(parse_config(&service("ip_hash", 3_000_000, 1_000_000)).is_ok());
+        for strategy in ["weighted_round_robin", "random", "ip_hash"] {
+            let err = parse_config(&service(strategy, 1_000_000, 1)).unwrap_err();
+            assert!(err.contains("must sum to at most 10000"), "{err}");
+        }
+        // Strategies ignoring weights accept any
+        assert!(parse_config(&service("least_connections", 1_000_000, 1)).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_tls_sni_is_rejected() {
+        let route = |target: &str, sni: &str| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  services: {{}}
+                  routes:
+                    - path: /
+                      listeners: [ http-main ]
+                      upstreams:
+                        - target: {target}
+                          tls_sni: "{sni}"
+                "#
+            )
+        };
+        assert!(parse_config(&route("https://10.0.0.1:8443", "backend.internal")).is_ok());
+        assert!(parse_config(&route("https://[::1]", "backend.internal")).is_ok());
+
+        for (target, sni, expected) in [
+            ("https://10.0.0.1", "not a name", "not a valid DNS name"),
+            ("https://10.0.0.1", "10.0.0.2", "not a valid DNS name"),
+            (
+                "http://10.0.0.1",
+                "backend.internal",
+                "requires an https upstream",
+            ),
+            (
+                "https://backend.host",
+                "backend.internal",
+                "requires an https upstream",
+            ),
+        ] {
+            let err = parse_config(&route(target, sni)).unwrap_err();
+            assert!(err.contains(expected), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_upstream_names_are_rejected() {
+        let service = |first: &str, second: &str| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  services:
+                    backend:
+                      upstreams:
+                        - target: http://10.0.0.1
+                          name: "{first}"
+                        - target: http://10.0.0.2
+                          name: "{second}"
+                  routes: []
+                "#
+            )
+        };
+        assert!(parse_config(&service("backend-a", "backend-b")).is_ok());
+
+        for (first, second, expected) in [
+            (
+                "backend-a",
+                "backend-a",
+                "Duplicate upstream name backend-a for service backend",
+            ),
+            (
+                "",
+                "backend-b",
+                "Invalid upstream name \"\" for service backend",
+            ),
+            (
+                "backend-a",
+                "0",
+                "Upstream name 0 must not be a number for service backend",
+            ),
+            (
+                "backend-a",
+                "line\\nbreak",
+                "Invalid upstream name \"line\\nbreak\" for service backend",
+            ),
+        ] {
+            let err = parse_config(&service(first, second)).unwrap_err();
+            assert_eq!(err, expected);
+        }
+    }
+
+    #[test]
+    fn test_route_health_check_needs_inline_upstreams() {
+        let route = |target: &str, path: &str| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  services:
+                    backend:
+                      upstreams:
+                        - target: http://10.0.0.1
+                  routes:
+                    - path: /
+                      listeners: [ http-main ]
+                      {target}
+                      health_check:
+                        path: {path}
+                "#
+            )
+        };
+        let inline = "upstreams: [ { target: http://10.0.0.2 } ]";
+        assert!(parse_config(&route(inline, "/healthz")).is_ok());
+        assert_eq!(
+            parse_config(&route(inline, "healthz")).unwrap_err(),
+            "health_check path must start with '/' for route @route/0"
+        );
+        assert_eq!(
+            parse_config(&route("service: backend", "/healthz")).unwrap_err(),
+            "health_check needs inline upstreams for route backend"
+        );
+    }
+
+    #[test]
+    fn test_mismatched_tls_key_is_reported() {
+        let dir = std::env::temp_dir().join(format!("portiq-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a", "b"] {
+            let certified =
+                rcgen::generate_simple_self_signed(vec![format!("{name}.example.com")]).unwrap();
+            std::fs::write(dir.join(format!("{name}.crt")), certified.cert.pem()).unwrap();
+            std::fs::write(
+                dir.join(format!("{name}.key")),
+                certified.signing_key.serialize_pem(),
+            )
+            .unwrap();
+        }
+        let config = |cert: &str, key: &str| {
+            format!(
+                r#"
+                tls:
+                  - cert_file: {}
+                    key_file: {}
+                    default: true
+                listeners: []
+                "#,
+                dir.join(cert).display(),
+                dir.join(key).display()
+            )
+        };
+
+        assert!(parse_config(&config("a.crt", "a.key")).is_ok());
+        let err = parse_config(&config("a.crt", "b.key")).unwrap_err();
+        assert!(
+            err.contains(&format!(
+                "Private key {} does not match certificate {}",
+                dir.join("b.key").display(),
+                dir.join("a.crt").display()
+            )),
+            "{err}"
+        );
+        let err = parse_config(&config("a.crt", "missing.key")).unwrap_err();
+        assert!(err.contains("missing.key"), "{err}");
+        // A key is no certificate
+        let err = parse_config(&config("a.key", "a.key")).unwrap_err();
+        assert!(err.contains("No PEM certificate found"), "{err}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unusable_upstream_ca_file_is_rejected() {
+        let config = |ca_file: &std::path::Path| {
+            format!(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  services:
+                    secure-service:
+                      upstreams:
+                        - target: https://secure.service:3443
+                      tls:
+                        ca_file: {}
+                  routes: []
+                "#,
+                ca_file.display()
+            )
+        };
+        let path = std::env::temp_dir().join(format!("portiq-ca-{}.pem", uuid::Uuid::new_v4()));
+        let certified = rcgen::generate_simple_self_signed(vec![String::from("secure")]).unwrap();
+        std::fs::write(&path, certified.cert.pem()).unwrap();
+        assert!(parse_config(&config(&path)).is_ok());
+
+        std::fs::write(&path, "not a certificate").unwrap();
+        let err = parse_config(&config(&path)).unwrap_err();
+        assert_eq!(
+            err,
+            format!(
+                "CA file {} contains no certificates for service secure-service",
+                path.display()
+            )
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let err = parse_config(&config(&path)).unwrap_err();
+        assert!(
+            err.starts_with(&format!("Failed to read CA file {}", path.display())),
+            "{err}"
+        );
+        assert!(err.ends_with("for service secure-service"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_reload_publishes_lifecycle_event() {
+        let config = r#"
+listeners:
+  - name: http-main
+    addr: 0.0.0.0:3000
+"#;
+        let path = std::env::temp_dir().join(format!("portiq-reload-{}.yml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, config).unwrap();
+        CONFIG_FILE_PATH
+            .set(path.to_string_lossy().into_owned())
+            .unwrap();
+        let gateway_runtime = GatewayRuntime::new(Arc::new(load_config().unwrap())).unwrap();
+        let state = SharedGatewayState::new(arc_swap::ArcSwap::from_pointee(gateway_runtime));
+
+        let mut events = LIFECYCLE.subscribe();
+        reload_config(state, ReloadSource::AdminApi).unwrap();
+        std::fs::remove_file(path).unwrap();
+        // Other tests may drain the shared lifecycle concurrently
+        let reloaded =
+            async { while events.recv().await.unwrap() != LifecycleEvent::ConfigReloaded {} };
+        tokio::time::timeout(Duration::from_secs(1), reloaded)
+            .await
+            .expect("Reload should publish an event");
+    }
+
+    #[test]
+    fn test_reload_status_is_recorded_on_gateway_state() {
+        let config = r#"
+listeners:
+  - name: http-main
+    addr: 0.0.0.0:3000
+"#;
+        let new_state = || {
+            let gateway_runtime =
+                GatewayRuntime::new(Arc::new(parse_config(config).unwrap())).unwrap();
+            SharedGatewayState::new(arc_swap::ArcSwap::from_pointee(gateway_runtime))
+        };
+        let state = new_state();
+        assert!(state.load().last_reload_status().is_none());
+
+        let path = std::env::temp_dir().join(format!("portiq-reload-{}.yml", uuid::Uuid::new_v4()));
+        let file_path = path.to_str().unwrap();
+        std::fs::write(&path, config).unwrap();
+        reload_config_file(Some(file_path), state.clone(), ReloadSource::AdminApi).unwrap();
+        let status = state.load().last_reload_status().unwrap();
+        assert!(status.success);
+        assert_eq!(status.source, ReloadSource::AdminApi);
+        assert_eq!(status.error, None);
+
+        std::fs::write(&path, "listeners: []\nhttp:\n  services: {}\n").unwrap();
+        let err =
+            reload_config_file(Some(file_path), state.clone(), ReloadSource::AdminApi).unwrap_err();
+        let status = state.load().last_reload_status().unwrap();
+        assert!(!status.success);
+        assert_eq!(status.error, Some(err));
+        std::fs::remove_file(path).unwrap();
+
+        // Kept per gateway state, not process wide
+        assert!(new_state().load().last_reload_status().is_none());
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_running_config() {
+        let config = r#"
+listeners:
+  - name: http-main
+    addr: 0.0.0.0:3000
+"#;
+        let gateway_runtime = GatewayRuntime::new(Arc::new(parse_config(config).unwrap())).unwrap();
+        let state = SharedGatewayState::new(arc_swap::ArcSwap::from_pointee(gateway_runtime));
+        let running = state.load_full();
+
+        let path = std::env::temp_dir().join(format!("portiq-reload-{}.yml", uuid::Uuid::new_v4()));
+        let file_path = path.to_str().unwrap();
+        let err = apply_config_from_file(file_path, state.clone()).unwrap_err();
+        assert!(err.contains("not found"), "{err}");
+
+        for (contents, expected) in [
+            // Cut off mid write
+            (
+                "listeners:\n  - name: http-main\n    addr: [",
+                "while parsing",
+            ),
+            (
+                "listeners: []\nhttp:\n  services: {}\n",
+                "missing configuration field",
+            ),
+            (
+                &format!(
+                    "{config}http:\n  services: {{}}\n  routes:\n    - path: /\n      listeners: [ http-main ]\n      service: missing\n"
+                ),
+                "Undefined service missing",
+            ),
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            let err = apply_config_from_file(file_path, state.clone()).unwrap_err();
+            assert!(err.contains(expected), "{err}");
+            assert!(Arc::ptr_eq(&running, &state.load_full()));
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
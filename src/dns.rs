@@ -0,0 +1,87 @@
+use crate::config::IpFamily;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::net::SocketAddr;
+
+/// System resolver whose results are ordered or filtered by IP family.
+///
+/// The connector tries the family of the first address and races the other family after a short
+/// delay (happy eyeballs), so ordering picks the preferred family while keeping the fallback.
+pub struct FamilyResolver {
+    family: IpFamily,
+}
+
+impl FamilyResolver {
+    pub fn new(family: IpFamily) -> Self {
+        FamilyResolver { family }
+    }
+}
+
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.family;
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0)).await?;
+            let addrs = order_by_family(addrs.collect(), family);
+            if addrs.is_empty() {
+                return Err(format!("No {family:?} address found for {}", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn order_by_family(mut addrs: Vec<SocketAddr>, family: IpFamily) -> Vec<SocketAddr> {
+    match family {
+        IpFamily::Any => {}
+        IpFamily::PreferIpv4 => addrs.sort_by_key(|addr| addr.is_ipv6()),
+        IpFamily::PreferIpv6 => addrs.sort_by_key(|addr| addr.is_ipv4()),
+        IpFamily::Ipv4Only => addrs.retain(SocketAddr::is_ipv4),
+        IpFamily::Ipv6Only => addrs.retain(SocketAddr::is_ipv6),
+    }
+    addrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs() -> Vec<SocketAddr> {
+        [
+            "[2001:db8::1]:0",
+            "192.0.2.1:0",
+            "[2001:db8::2]:0",
+            "192.0.2.2:0",
+        ]
+        .map(|addr| addr.parse().unwrap())
+        .into()
+    }
+
+    fn ips(addrs: Vec<SocketAddr>) -> Vec<String> {
+        addrs.iter().map(|addr| addr.ip().to_string()).collect()
+    }
+
+    #[test]
+    fn test_preferred_family_is_tried_first() {
+        assert_eq!(
+            ips(order_by_family(addrs(), IpFamily::PreferIpv4)),
+            ["192.0.2.1", "192.0.2.2", "2001:db8::1", "2001:db8::2"]
+        );
+        assert_eq!(
+            ips(order_by_family(addrs(), IpFamily::PreferIpv6)),
+            ["2001:db8::1", "2001:db8::2", "192.0.2.1", "192.0.2.2"]
+        );
+        assert_eq!(ips(order_by_family(addrs(), IpFamily::Any)), ips(addrs()));
+    }
+
+    #[test]
+    fn test_single_family_drops_fallback() {
+        assert_eq!(
+            ips(order_by_family(addrs(), IpFamily::Ipv4Only)),
+            ["192.0.2.1", "192.0.2.2"]
+        );
+        assert_eq!(
+            ips(order_by_family(addrs(), IpFamily::Ipv6Only)),
+            ["2001:db8::1", "2001:db8::2"]
+        );
+    }
+}
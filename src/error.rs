@@ -1,12 +1,17 @@
 use hyper::StatusCode;
 use thiserror::Error;
 
+/// Error of response bodies, which may come from the client connection or an upstream.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 #[derive(Error, Debug)]
 pub enum RouterError {
     #[error("Route not found")]
     NotFound,
     #[error("No upstream available")]
     NoUpstream,
+    #[error("Method not allowed")]
+    MethodNotAllowed,
 }
 
 impl RouterError {
@@ -14,6 +19,7 @@ impl RouterError {
         match self {
             RouterError::NotFound => StatusCode::NOT_FOUND,
             RouterError::NoUpstream => StatusCode::SERVICE_UNAVAILABLE,
+            RouterError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
         }
     }
 }
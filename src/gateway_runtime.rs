@@ -1,21 +1,39 @@
-use crate::config::GatewayConfig;
+use crate::config::{GatewayConfig, ReloadStatus};
 use crate::router::Router;
 use crate::service::ServiceRegistry;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 pub struct GatewayRuntime {
     router: Arc<Router>,
     applied_config: GatewayConfig,
+    // Carried over to every runtime reloaded from this one
+    last_reload: Arc<Mutex<Option<ReloadStatus>>>,
 }
 
 impl GatewayRuntime {
-    pub fn new(gateway_config: Arc<GatewayConfig>) -> Self {
-        let service_registry = Arc::new(ServiceRegistry::init(gateway_config.clone()));
+    pub fn new(gateway_config: Arc<GatewayConfig>) -> Result<Self, String> {
+        let service_registry = Arc::new(ServiceRegistry::init(gateway_config.clone())?);
         let router = Arc::new(Router::new(gateway_config.clone(), service_registry));
-        GatewayRuntime {
+        Ok(GatewayRuntime {
             router,
             applied_config: (*gateway_config).clone(),
-        }
+            last_reload: Arc::default(),
+        })
+    }
+
+    /// Runtime for a reloaded config, keeping the reload status of this one.
+    pub fn reload(&self, gateway_config: Arc<GatewayConfig>) -> Result<Self, String> {
+        let mut runtime = GatewayRuntime::new(gateway_config)?;
+        runtime.last_reload = self.last_reload.clone();
+        Ok(runtime)
+    }
+
+    pub fn last_reload_status(&self) -> Option<ReloadStatus> {
+        self.last_reload.lock().unwrap().clone()
+    }
+
+    pub fn record_reload(&self, status: ReloadStatus) {
+        *self.last_reload.lock().unwrap() = Some(status);
     }
 
     pub fn get_last_applied_config(&self) -> &GatewayConfig {
@@ -0,0 +1,688 @@
+use crate::config::{HealthCheckConfig, PassiveHealthConfig};
+use crate::gateway_runtime::GatewayRuntime;
+use crate::{BoxedStr, SharedGatewayState};
+use hyper::StatusCode;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// How often the checker looks for probes that are due.
+const HEALTH_CHECK_TICK: Duration = Duration::from_secs(1);
+
+/// How often a listener waiting for healthy upstreams checks them again.
+const LISTENER_READY_TICK: Duration = Duration::from_millis(100);
+
+/// Probe of an upstream target, services probing the same path of the same target with the same
+/// thresholds and expected statuses through the gateway wide client share it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Probe {
+    url: String,
+    /// Service whose own client, e.g. with a CA file, proxy or `tls_sni`, sends the probe
+    client: Option<BoxedStr>,
+    healthy_threshold: u32,
+    unhealthy_threshold: u32,
+    expected_statuses: Option<Vec<u16>>,
+}
+
+impl Probe {
+    pub fn new(target: &str, health_check: &HealthCheckConfig, client: Option<&str>) -> Self {
+        Probe {
+            url: format!("{}{}", target.trim_end_matches('/'), health_check.path),
+            client: client.map(BoxedStr::from),
+            healthy_threshold: health_check.healthy_threshold,
+            unhealthy_threshold: health_check.unhealthy_threshold,
+            expected_statuses: health_check.expected_statuses.clone(),
+        }
+    }
+
+    fn passes(&self, status: StatusCode) -> bool {
+        match &self.expected_statuses {
+            Some(expected) => expected.contains(&status.as_u16()),
+            None => status.is_success() || status.is_redirection(),
+        }
+    }
+}
+
+/// Health of a probed upstream, it flips once the opposite outcome was seen often enough in a row.
+#[derive(Clone, Copy)]
+struct ProbeState {
+    healthy: bool,
+    streak: u32,
+    checked_at: SystemTime,
+    /// Whether any probe passed yet, upstreams are not ready before
+    passed_once: bool,
+}
+
+impl ProbeState {
+    /// Records an outcome, returns whether the health changed.
+    fn record(&mut self, probe: &Probe, passed: bool) -> bool {
+        self.checked_at = SystemTime::now();
+        self.passed_once |= passed;
+        if passed == self.healthy {
+            self.streak = 0;
+            return false;
+        }
+        self.streak += 1;
+        let threshold = if passed {
+            probe.healthy_threshold
+        } else {
+            probe.unhealthy_threshold
+        };
+        if self.streak < threshold {
+            return false;
+        }
+        self.healthy = passed;
+        self.streak = 0;
+        true
+    }
+}
+
+/// When and how a probe is sent.
+pub struct ProbeSchedule {
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Differs from the probe url for upstreams with a `tls_sni` override
+    pub url: String,
+    /// Client of the service, the gateway wide one when `None`
+    pub client: Option<Arc<reqwest::Client>>,
+}
+
+/// Distinct probes of the runtime's services, a probe used by several services runs at the
+/// shortest interval and timeout configured for it.
+fn probes(runtime: &GatewayRuntime) -> HashMap<Probe, ProbeSchedule> {
+    let mut probes = HashMap::<Probe, ProbeSchedule>::new();
+    for service in runtime.get_router().http_services() {
+        for (probe, schedule) in service.health_probes() {
+            match probes.get_mut(&probe) {
+                Some(shared) => {
+                    shared.interval = shared.interval.min(schedule.interval);
+                    shared.timeout = shared.timeout.min(schedule.timeout);
+                }
+                None => {
+                    probes.insert(probe, schedule);
+                }
+            }
+        }
+    }
+    probes
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamHealth {
+    Healthy,
+    /// Failing its active health check
+    Unhealthy,
+    /// Taken out of the rotation by passive health checks
+    Ejected,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamStatus {
+    pub target: String,
+    pub weight: u32,
+    pub status: UpstreamHealth,
+    /// Last active health check, `None` without one or before it first ran
+    #[serde(with = "humantime_serde")]
+    pub last_checked: Option<SystemTime>,
+}
+
+/// Results of the active health checks by probe, shared by every service and kept across config
+/// reloads.
+pub struct HealthChecks {
+    states: RwLock<HashMap<Probe, ProbeState>>,
+}
+
+impl HealthChecks {
+    pub fn new() -> Self {
+        HealthChecks {
+            states: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Upstreams are healthy until enough probes in a row fail.
+    pub fn is_healthy(&self, probe: &Probe) -> bool {
+        self.states
+            .read()
+            .unwrap()
+            .get(probe)
+            .is_none_or(|state| state.healthy)
+    }
+
+    /// Whether `probe` passed at least once and is healthy, unlike `is_healthy` it is false until
+    /// the first passing probe.
+    pub fn has_passed(&self, probe: &Probe) -> bool {
+        self.states
+            .read()
+            .unwrap()
+            .get(probe)
+            .is_some_and(|state| state.passed_once && state.healthy)
+    }
+
+    /// Whether every HTTP route of `listener` has an upstream that passed its health check, routes
+    /// without a health checked service are always ready.
+    pub fn is_listener_ready(&self, runtime: &GatewayRuntime, listener: &str) -> bool {
+        let router = runtime.get_router();
+        runtime
+            .get_last_applied_config()
+            .http
+            .routes
+            .iter()
+            .enumerate()
+            .filter(|(_, route)| route.listeners.iter().any(|name| name == listener))
+            .filter_map(|(index, route)| router.get_http_service(&route.service_name(index)))
+            .all(|service| {
+                let probes = service.health_probes();
+                probes.is_empty() || probes.iter().any(|(probe, _)| self.has_passed(probe))
+            })
+    }
+
+    /// Waits until `listener` is ready, at most `max_wait`, returns false when cancelled.
+    pub async fn wait_for_listener(
+        &self,
+        gateway_state: &SharedGatewayState,
+        listener: &str,
+        max_wait: Duration,
+        cancel_token: &CancellationToken,
+    ) -> bool {
+        let deadline = Instant::now() + max_wait;
+        let mut tick = tokio::time::interval(LISTENER_READY_TICK);
+        loop {
+            if self.is_listener_ready(&gateway_state.load(), listener) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                tracing::warn!(
+                    "Listener `{listener}` still has routes without a healthy upstream after {max_wait:?}, accepting anyway"
+                );
+                return true;
+            }
+            tokio::select! {
+                _ = tick.tick() => {}
+                _ = cancel_token.cancelled() => return false,
+            }
+        }
+    }
+
+    /// When `probe` last ran, `None` before its first run.
+    pub fn last_checked(&self, probe: &Probe) -> Option<SystemTime> {
+        self.states
+            .read()
+            .unwrap()
+            .get(probe)
+            .map(|state| state.checked_at)
+    }
+
+    /// Runs every probe once, concurrently, and records whether the answer had an expected status.
+    async fn probe(
+        &self,
+        http_client: &Arc<reqwest::Client>,
+        probes: impl IntoIterator<Item = (Probe, ProbeSchedule)>,
+    ) {
+        let mut checks = JoinSet::new();
+        for (probe, schedule) in probes {
+            let http_client = schedule.client.unwrap_or_else(|| http_client.clone());
+            checks.spawn(async move {
+                let result = http_client
+                    .get(&schedule.url)
+                    .timeout(schedule.timeout)
+                    .send()
+                    .await;
+                let outcome = result.map(|response| response.status());
+                (probe, outcome)
+            });
+        }
+        while let Some(Ok((probe, outcome))) = checks.join_next().await {
+            let passed = outcome.as_ref().is_ok_and(|status| probe.passes(*status));
+            let changed = self
+                .states
+                .write()
+                .unwrap()
+                .entry(probe.clone())
+                .or_insert(ProbeState {
+                    healthy: true,
+                    streak: 0,
+                    checked_at: SystemTime::now(),
+                    passed_once: false,
+                })
+                .record(&probe, passed);
+            if changed {
+                let url = &probe.url;
+                match outcome {
+                    Err(err) => tracing::warn!("Health check of {url} failed: {err}"),
+                    Ok(status) if !passed => {
+                        tracing::warn!("Health check of {url} failed with {status}")
+                    }
+                    Ok(_) => tracing::info!("Health check of {url} passed"),
+                }
+            }
+        }
+    }
+
+    /// Probes the upstreams of services with a health check until cancelled, picking up reloads.
+    pub async fn run(
+        &self,
+        gateway_state: SharedGatewayState,
+        http_client: Arc<reqwest::Client>,
+        cancel_token: CancellationToken,
+    ) {
+        let mut last_probed = HashMap::<Probe, Instant>::new();
+        let mut tick = tokio::time::interval(HEALTH_CHECK_TICK);
+        loop {
+            tokio::select! {
+                _ = tick.tick() => {}
+                _ = cancel_token.cancelled() => return,
+            }
+            let probes = probes(&gateway_state.load());
+            last_probed.retain(|probe, _| probes.contains_key(probe));
+            let now = Instant::now();
+            let due = probes
+                .into_iter()
+                .filter(|(probe, schedule)| {
+                    last_probed
+                        .get(probe)
+                        .is_none_or(|probed| now.duration_since(*probed) >= schedule.interval)
+                })
+                .collect::<Vec<_>>();
+            for (probe, _) in &due {
+                last_probed.insert(probe.clone(), now);
+            }
+            self.probe(&http_client, due).await;
+        }
+    }
+}
+
+#[derive(Default)]
+struct PassiveState {
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+/// Ejects upstreams of a service failing live requests in a row. Once the ejection ends the next
+/// request probes the upstream, a single failure ejects it again and a success restores it.
+pub struct PassiveHealth {
+    consecutive_failures: u32,
+    ejection_duration: Duration,
+    /// Never every upstream, so traffic always has somewhere to go
+    max_ejected: usize,
+    upstreams: RwLock<HashMap<BoxedStr, PassiveState>>,
+}
+
+impl PassiveHealth {
+    pub fn new(config: &PassiveHealthConfig, upstream_count: usize) -> Self {
+        let max_ejected = (upstream_count * usize::from(config.max_ejection_percent) / 100)
+            .max(1)
+            .min(upstream_count.saturating_sub(1));
+        PassiveHealth {
+            consecutive_failures: config.consecutive_failures,
+            ejection_duration: config.ejection_duration,
+            max_ejected,
+            upstreams: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_ejected(&self, target: &str) -> bool {
+        self.upstreams
+            .read()
+            .unwrap()
+            .get(target)
+            .and_then(|state| state.ejected_until)
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Records the outcome of a request proxied to `target`, failed when the upstream could not be
+    /// reached or answered with a 5xx.
+    pub fn record(&self, target: &str, failed: bool) {
+        let mut upstreams = self.upstreams.write().unwrap();
+        if !failed {
+            if let Some(state) = upstreams.get_mut(target) {
+                state.consecutive_failures = 0;
+            }
+            return;
+        }
+
+        let now = Instant::now();
+        let is_ejected =
+            |state: &PassiveState| state.ejected_until.is_some_and(|until| now < until);
+        let ejected = upstreams.values().filter(|state| is_ejected(state)).count();
+        let state = upstreams.entry(target.into()).or_default();
+        // Requests sent before the ejection may still complete
+        if is_ejected(state) {
+            return;
+        }
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.consecutive_failures && ejected < self.max_ejected {
+            tracing::warn!(
+                "Ejecting upstream {target} for {:?} after {} failed requests",
+                self.ejection_duration,
+                state.consecutive_failures
+            );
+            state.ejected_until = Some(now + self.ejection_duration);
+            state.consecutive_failures = self.consecutive_failures - 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GatewayConfig;
+    use config::{Config, File, FileFormat};
+    use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_shared_target_is_probed_once() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let probes_received = Arc::new(AtomicUsize::new(0));
+        let counter = probes_received.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = upstream.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await.unwrap();
+                stream
+                    .write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let service = |name: &str, interval: &str| {
+            format!(
+                "    {name}:\n      upstreams:\n        - target: http://{upstream_addr}\n      health_check:\n        path: /healthz\n        interval: {interval}\n"
+            )
+        };
+        let config: GatewayConfig = Config::builder()
+            .add_source(File::from_str(
+                &format!(
+                    "listeners: []\nhttp:\n  services:\n{}{}  routes: []\n",
+                    service("users", "10s"),
+                    service("accounts", "5s")
+                ),
+                FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let probe = Probe::new(
+            &format!("http://{upstream_addr}"),
+            config.http.services["users"].health_check.as_ref().unwrap(),
+            None,
+        );
+        let probes = probes(&GatewayRuntime::new(Arc::new(config)).unwrap());
+        assert_eq!(probes.len(), 1);
+        assert_eq!(probes[&probe].interval, Duration::from_secs(5));
+
+        let health_checks = HealthChecks::new();
+        assert!(health_checks.is_healthy(&probe));
+        let http_client = Arc::new(reqwest::Client::new());
+        health_checks.probe(&http_client, probes).await;
+        assert_eq!(probes_received.load(Ordering::SeqCst), 1);
+        assert!(!health_checks.is_healthy(&probe));
+    }
+
+    #[tokio::test]
+    async fn test_probes_go_through_the_service_client() {
+        // Answers both as the proxy of one service and as the inline upstream of a route
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let request_lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = request_lines.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = upstream.accept().await.unwrap();
+                let mut buf = [0; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+                received
+                    .lock()
+                    .unwrap()
+                    .push(request.lines().next().unwrap_or_default().to_string());
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let config: GatewayConfig = Config::builder()
+            .add_source(File::from_str(
+                &format!(
+                    r#"
+                    listeners:
+                      - name: http-main
+                        addr: 0.0.0.0:3000
+                    http:
+                      services:
+                        partner:
+                          upstreams:
+                            - target: http://partner.invalid
+                          proxy:
+                            url: http://{upstream_addr}
+                          health_check:
+                            path: /healthz
+                      routes:
+                        - path: /partner
+                          listeners: [ http-main ]
+                          service: partner
+                        - path: /inline
+                          listeners: [ http-main ]
+                          upstreams:
+                            - target: http://{upstream_addr}
+                          health_check:
+                            path: /ready
+                    "#
+                ),
+                FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        let runtime = GatewayRuntime::new(Arc::new(config)).unwrap();
+        let probes = probes(&runtime);
+        assert_eq!(probes.len(), 2);
+
+        let health_checks = HealthChecks::new();
+        // Would fail to resolve the upstream without the service's proxy
+        let http_client = Arc::new(reqwest::Client::builder().no_proxy().build().unwrap());
+        health_checks.probe(&http_client, probes).await;
+        assert!(health_checks.is_listener_ready(&runtime, "http-main"));
+        let mut request_lines = request_lines.lock().unwrap().clone();
+        request_lines.sort();
+        assert_eq!(
+            request_lines,
+            [
+                "GET /ready HTTP/1.1",
+                "GET http://partner.invalid/healthz HTTP/1.1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_passive_health_ejects_within_limit() {
+        let passive_health = PassiveHealth::new(
+            &PassiveHealthConfig {
+                consecutive_failures: 2,
+                ejection_duration: Duration::from_millis(50),
+                max_ejection_percent: 50,
+            },
+            4,
+        );
+        let fail_twice = |target: &str| {
+            passive_health.record(target, true);
+            passive_health.record(target, true);
+            passive_health.is_ejected(target)
+        };
+
+        passive_health.record("a", true);
+        passive_health.record("a", false);
+        passive_health.record("a", true);
+        assert!(!passive_health.is_ejected("a"));
+        assert!(fail_twice("b"));
+        assert!(fail_twice("c"));
+        // Half of the upstreams are ejected already
+        assert!(!fail_twice("d"));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(!passive_health.is_ejected("b"));
+        passive_health.record("b", true);
+        assert!(passive_health.is_ejected("b"));
+        passive_health.record("c", false);
+        passive_health.record("c", true);
+        assert!(!passive_health.is_ejected("c"));
+    }
+
+    #[test]
+    fn test_passive_health_keeps_a_single_upstream() {
+        let passive_health = PassiveHealth::new(
+            &PassiveHealthConfig {
+                consecutive_failures: 1,
+                ejection_duration: Duration::from_secs(30),
+                max_ejection_percent: 100,
+            },
+            1,
+        );
+        passive_health.record("a", true);
+        assert!(!passive_health.is_ejected("a"));
+    }
+
+    #[tokio::test]
+    async fn test_health_flips_after_consecutive_probes() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let status = Arc::new(AtomicU16::new(200));
+        let answered = status.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = upstream.accept().await.unwrap();
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await.unwrap();
+                let status = answered.load(Ordering::SeqCst);
+                stream
+                    .write_all(
+                        format!("HTTP/1.1 {status} Status\r\nContent-Length: 0\r\n\r\n").as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let health_check = HealthCheckConfig {
+            path: String::from("/healthz"),
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(1),
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+            expected_statuses: Some(vec![200, 204]),
+        };
+        let probe = Probe::new(&format!("http://{upstream_addr}"), &health_check, None);
+        let health_checks = HealthChecks::new();
+        let http_client = Arc::new(reqwest::Client::new());
+        let probe_with = async |answer: u16| {
+            status.store(answer, Ordering::SeqCst);
+            let schedule = ProbeSchedule {
+                interval: health_check.interval,
+                timeout: health_check.timeout,
+                url: format!("http://{upstream_addr}/healthz"),
+                client: None,
+            };
+            health_checks
+                .probe(&http_client, [(probe.clone(), schedule)])
+                .await;
+            health_checks.is_healthy(&probe)
+        };
+
+        assert!(probe_with(200).await);
+        // A redirect is not among the expected statuses
+        assert!(probe_with(302).await);
+        assert!(probe_with(302).await);
+        assert!(!probe_with(302).await);
+        assert!(!probe_with(204).await);
+        assert!(probe_with(204).await);
+    }
+
+    #[tokio::test]
+    async fn test_listener_is_not_ready_until_an_upstream_passes() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let status = Arc::new(AtomicU16::new(503));
+        let answered = status.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = upstream.accept().await.unwrap();
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await.unwrap();
+                let status = answered.load(Ordering::SeqCst);
+                stream
+                    .write_all(
+                        format!("HTTP/1.1 {status} Status\r\nContent-Length: 0\r\n\r\n").as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let config: GatewayConfig = Config::builder()
+            .add_source(File::from_str(
+                &format!(
+                    r#"
+                    listeners:
+                      - name: http-main
+                        addr: 0.0.0.0:3000
+                      - name: http-internal
+                        addr: 0.0.0.0:3001
+                    http:
+                      services:
+                        users:
+                          upstreams:
+                            - target: http://{upstream_addr}
+                            - target: http://127.0.0.1:1
+                          health_check:
+                            path: /healthz
+                            unhealthy_threshold: 3
+                      routes:
+                        - path: /users
+                          listeners: [ http-main ]
+                          service: users
+                        - path: /echo
+                          listeners: [ http-internal ]
+                          upstreams:
+                            - target: http://127.0.0.1:1
+                    "#
+                ),
+                FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let runtime = GatewayRuntime::new(Arc::new(config)).unwrap();
+        let health_checks = HealthChecks::new();
+        let http_client = Arc::new(reqwest::Client::new());
+        let probe_all = async || {
+            health_checks.probe(&http_client, probes(&runtime)).await;
+        };
+
+        assert!(!health_checks.is_listener_ready(&runtime, "http-main"));
+        // Still healthy below the threshold, yet never passed
+        probe_all().await;
+        assert!(!health_checks.is_listener_ready(&runtime, "http-main"));
+        status.store(200, Ordering::SeqCst);
+        probe_all().await;
+        assert!(health_checks.is_listener_ready(&runtime, "http-main"));
+        // Routes without health checked services are always ready
+        assert!(health_checks.is_listener_ready(&runtime, "http-internal"));
+    }
+}
@@ -0,0 +1,133 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tokio::sync::{Notify, broadcast};
+
+/// Events published on lifecycle transitions, late subscribers only see later events.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LifecycleEvent {
+    Started,
+    ConfigReloaded,
+    Draining,
+    Stopped,
+}
+
+// Subscribers lagging by more than this many events miss the oldest ones
+const EVENT_CAPACITY: usize = 16;
+
+/// Process wide lifecycle state shared by the listeners and the admin API.
+pub struct Lifecycle {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+    events: broadcast::Sender<LifecycleEvent>,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Lifecycle {
+            draining: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            idle: Notify::new(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LifecycleEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn publish(&self, event: LifecycleEvent) {
+        // Sending only fails without subscribers, events are optional
+        let _ = self.events.send(event);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    /// Marks the gateway as draining, returns false if a drain was already in progress.
+    pub fn start_draining(&self) -> bool {
+        let started = !self.draining.swap(true, Ordering::AcqRel);
+        if started {
+            self.publish(LifecycleEvent::Draining);
+        }
+        started
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    pub fn track_request(&self) -> InFlightGuard<'_> {
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard { lifecycle: self }
+    }
+
+    /// Resolves once no requests are in flight.
+    pub async fn wait_idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            if self.in_flight() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+pub struct InFlightGuard<'a> {
+    lifecycle: &'a Lifecycle,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.lifecycle.in_flight.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.lifecycle.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_start_draining_only_once() {
+        let lifecycle = Lifecycle::new();
+        let mut events = lifecycle.subscribe();
+        assert!(!lifecycle.is_draining());
+        assert!(lifecycle.start_draining());
+        assert!(!lifecycle.start_draining());
+        assert!(lifecycle.is_draining());
+        assert_eq!(events.try_recv(), Ok(LifecycleEvent::Draining));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait_idle_resolves_after_requests_complete() {
+        let lifecycle = Arc::new(Lifecycle::new());
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let request = {
+            let lifecycle = lifecycle.clone();
+            tokio::spawn(async move {
+                let _guard = lifecycle.track_request();
+                let _ = rx.await;
+            })
+        };
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(lifecycle.in_flight(), 1);
+        let waiter = tokio::time::timeout(Duration::from_millis(50), lifecycle.wait_idle()).await;
+        assert!(
+            waiter.is_err(),
+            "Should not be idle while a request is in flight"
+        );
+
+        tx.send(()).unwrap();
+        request.await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), lifecycle.wait_idle())
+            .await
+            .expect("Should be idle once the request completes");
+    }
+}
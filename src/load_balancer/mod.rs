@@ -1,53 +1,279 @@
 use crate::config::Upstream;
+use rand::Rng;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 pub trait LoadBalancerStrategy: Send + Sync {
     fn select(&self) -> Option<&Upstream>;
+
+    /// Selection for a request identified by `key`, e.g. the client IP, for strategies mapping
+    /// keys to upstreams. Others ignore it.
+    fn select_with_key(&self, _key: Option<&str>) -> Option<&Upstream> {
+        self.select()
+    }
+
+    /// In flight request counter of `target` for strategies that balance on it.
+    fn in_flight(&self, _target: &str) -> Option<Arc<AtomicUsize>> {
+        None
+    }
+}
+
+/// Counts a request as in flight until dropped, so requests abandoned mid flight are released.
+pub struct InFlightGuard {
+    counter: Arc<AtomicUsize>,
+}
+
+impl InFlightGuard {
+    pub fn new(counter: Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard { counter }
+    }
 }
 
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Smooth weighted round robin (as used by nginx), on every pick each upstream's current
+/// weight grows by its configured weight and the largest one is selected and pushed back by
+/// the total weight. This interleaves selections (5:1 yields AAABAA rather than AAAAAB)
+/// while only keeping one counter per upstream.
 pub struct WeightedRoundRobin {
     upstreams: Box<[Upstream]>,
-    weighted: Box<[u16]>,
-    next_index: AtomicUsize,
+    weights: Box<[i64]>,
+    current_weights: Mutex<Box<[i64]>>,
+    total_weight: i64,
 }
 
 impl WeightedRoundRobin {
     pub fn new(upstreams: &[Upstream]) -> Self {
-        assert!(
-            upstreams.len() <= u16::MAX as usize,
-            "support up to 2^16 upstreams"
-        );
-
         let servers = upstreams.to_owned().into_boxed_slice();
-        let mut weighted = Vec::with_capacity(servers.len());
-        for (index, server) in servers.iter().enumerate() {
-            for _ in 0..server.weight {
-                weighted.push(index as u16);
-            }
-        }
+
+        // Reduced weights keep the counters small and the selection cycle short
+        let weights = reduced_weights(&servers)
+            .into_iter()
+            .map(i64::from)
+            .collect::<Box<[_]>>();
+        let total_weight = weights.iter().sum();
 
         WeightedRoundRobin {
+            current_weights: Mutex::new(vec![0; servers.len()].into_boxed_slice()),
             upstreams: servers,
-            weighted: weighted.into_boxed_slice(),
-            next_index: AtomicUsize::new(0),
+            weights,
+            total_weight,
         }
     }
 }
 
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Upstream weights divided by their GCD, 3000:1000 behaves exactly like 3:1.
+pub fn reduced_weights(upstreams: &[Upstream]) -> Vec<u32> {
+    let divisor = upstreams
+        .iter()
+        .map(|upstream| upstream.weight)
+        .fold(0, gcd)
+        .max(1);
+    upstreams
+        .iter()
+        .map(|upstream| upstream.weight / divisor)
+        .collect()
+}
+
+/// Upper bound for the sum of a service's reduced weights. Weighted round robin cycles through
+/// that many picks and consistent hashing places `VIRTUAL_NODES_PER_WEIGHT` ring nodes per unit,
+/// so a typo like `weight: 1000000` next to `weight: 1` would allocate a ring of gigabytes.
+pub const MAX_REDUCED_WEIGHT_SUM: u64 = 10_000;
+
 impl LoadBalancerStrategy for WeightedRoundRobin {
     fn select(&self) -> Option<&Upstream> {
-        if self.weighted.is_empty() {
+        if self.total_weight == 0 {
+            return None;
+        }
+
+        let mut current_weights = self.current_weights.lock().unwrap();
+        let mut selected = None;
+        for (index, &weight) in self.weights.iter().enumerate() {
+            if weight == 0 {
+                continue;
+            }
+            current_weights[index] += weight;
+            if selected.is_none_or(|best| current_weights[index] > current_weights[best]) {
+                selected = Some(index);
+            }
+        }
+
+        let selected = selected?;
+        current_weights[selected] -= self.total_weight;
+        Some(&self.upstreams[selected])
+    }
+}
+
+/// Cycles through the upstreams in order regardless of their weights.
+pub struct RoundRobin {
+    upstreams: Box<[Upstream]>,
+    next: AtomicUsize,
+}
+
+impl RoundRobin {
+    pub fn new(upstreams: &[Upstream]) -> Self {
+        RoundRobin {
+            upstreams: upstreams.to_owned().into_boxed_slice(),
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl LoadBalancerStrategy for RoundRobin {
+    fn select(&self) -> Option<&Upstream> {
+        if self.upstreams.is_empty() {
+            return None;
+        }
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.upstreams.len();
+        Some(&self.upstreams[index])
+    }
+}
+
+/// Picks a random upstream with a probability proportional to its weight, so gateway
+/// instances do not send synchronized bursts to the same upstream.
+pub struct Random {
+    upstreams: Box<[Upstream]>,
+    /// Running sum of the weights, the draw lands on the first entry above it
+    cumulative_weights: Box<[u64]>,
+}
+
+impl Random {
+    pub fn new(upstreams: &[Upstream]) -> Self {
+        let cumulative_weights = upstreams
+            .iter()
+            .scan(0, |total, upstream| {
+                *total += upstream.weight as u64;
+                Some(*total)
+            })
+            .collect();
+        Random {
+            upstreams: upstreams.to_owned().into_boxed_slice(),
+            cumulative_weights,
+        }
+    }
+}
+
+impl LoadBalancerStrategy for Random {
+    fn select(&self) -> Option<&Upstream> {
+        let total_weight = self.cumulative_weights.last().copied().unwrap_or(0);
+        if total_weight == 0 {
+            return None;
+        }
+        let draw = rand::rng().random_range(0..total_weight);
+        let index = self
+            .cumulative_weights
+            .partition_point(|&cumulative| cumulative <= draw);
+        Some(&self.upstreams[index])
+    }
+}
+
+/// Picks the upstream with the fewest in flight requests, ignoring weights. Ties are broken
+/// round robin so an idle service still spreads its requests.
+pub struct LeastConnections {
+    upstreams: Box<[Upstream]>,
+    in_flight: Box<[Arc<AtomicUsize>]>,
+    next: AtomicUsize,
+}
+
+impl LeastConnections {
+    pub fn new(upstreams: &[Upstream]) -> Self {
+        LeastConnections {
+            upstreams: upstreams.to_owned().into_boxed_slice(),
+            in_flight: upstreams.iter().map(|_| Arc::default()).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl LoadBalancerStrategy for LeastConnections {
+    fn select(&self) -> Option<&Upstream> {
+        let len = self.upstreams.len();
+        if len == 0 {
             return None;
         }
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let index = (start..start + len)
+            .map(|index| index % len)
+            .min_by_key(|&index| self.in_flight[index].load(Ordering::Relaxed))?;
+        Some(&self.upstreams[index])
+    }
+
+    fn in_flight(&self, target: &str) -> Option<Arc<AtomicUsize>> {
+        let index = self
+            .upstreams
+            .iter()
+            .position(|upstream| upstream.target == target)?;
+        Some(self.in_flight[index].clone())
+    }
+}
+
+/// Virtual nodes placed on the ring per unit of weight, more nodes spread keys more evenly.
+const VIRTUAL_NODES_PER_WEIGHT: u32 = 64;
+
+fn ring_hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hash ring mapping each key to the first virtual node at or after its hash. Nodes are placed
+/// by upstream target, so removing an upstream only remaps the keys that landed on its nodes.
+pub struct ConsistentHashing {
+    upstreams: Box<[Upstream]>,
+    /// Virtual node hashes with the index of their upstream, sorted by hash
+    ring: Box<[(u64, usize)]>,
+    /// Requests without a key walk the ring, which keeps the weighted share
+    next: AtomicUsize,
+}
 
-        let next_index = self.next_index.load(Ordering::Relaxed);
-        let upstream_index = self.weighted[next_index] as usize;
-        self.next_index
-            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
-                Some((x + 1) % self.weighted.len())
+impl ConsistentHashing {
+    pub fn new(upstreams: &[Upstream]) -> Self {
+        let mut ring = upstreams
+            .iter()
+            .zip(reduced_weights(upstreams))
+            .enumerate()
+            .flat_map(|(index, (upstream, weight))| {
+                (0..weight * VIRTUAL_NODES_PER_WEIGHT)
+                    .map(move |node| (ring_hash((&upstream.target, node)), index))
             })
-            .unwrap_or(0);
-        Some(&self.upstreams[upstream_index])
+            .collect::<Vec<_>>();
+        ring.sort_unstable();
+        ConsistentHashing {
+            upstreams: upstreams.to_owned().into_boxed_slice(),
+            ring: ring.into_boxed_slice(),
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl LoadBalancerStrategy for ConsistentHashing {
+    fn select(&self) -> Option<&Upstream> {
+        self.select_with_key(None)
+    }
+
+    fn select_with_key(&self, key: Option<&str>) -> Option<&Upstream> {
+        if self.ring.is_empty() {
+            return None;
+        }
+        let node = match key {
+            Some(key) => {
+                let hash = ring_hash(key);
+                self.ring.partition_point(|&(node, _)| node < hash) % self.ring.len()
+            }
+            None => self.next.fetch_add(1, Ordering::Relaxed) % self.ring.len(),
+        };
+        Some(&self.upstreams[self.ring[node].1])
     }
 }
 
@@ -60,8 +286,12 @@ impl LoadBalancer {
         LoadBalancer { strategy }
     }
 
-    pub fn get_next(&self) -> Option<&Upstream> {
-        self.strategy.select()
+    pub fn get_next(&self, key: Option<&str>) -> Option<&Upstream> {
+        self.strategy.select_with_key(key)
+    }
+
+    pub fn in_flight(&self, target: &str) -> Option<Arc<AtomicUsize>> {
+        self.strategy.in_flight(target)
     }
 }
 
@@ -77,10 +307,14 @@ mod tests {
             Upstream {
                 target: "server1".to_string(),
                 weight: 3,
+                tls_sni: None,
+                name: None,
             },
             Upstream {
                 target: "server2".to_string(),
                 weight: 1,
+                tls_sni: None,
+                name: None,
             },
         ];
         let lb = WeightedRoundRobin::new(&upstreams);
@@ -103,10 +337,14 @@ mod tests {
             Upstream {
                 target: "server1".to_string(),
                 weight: 1,
+                tls_sni: None,
+                name: None,
             },
             Upstream {
                 target: "server2".to_string(),
                 weight: 1,
+                tls_sni: None,
+                name: None,
             },
         ];
         let lb = WeightedRoundRobin::new(&upstreams);
@@ -120,6 +358,294 @@ mod tests {
         assert_eq!(server3.target, upstreams[0].target);
     }
 
+    #[test]
+    fn test_smooth_interleaved_sequence() {
+        let upstreams = vec![
+            Upstream {
+                target: "server1".to_string(),
+                weight: 5,
+                tls_sni: None,
+                name: None,
+            },
+            Upstream {
+                target: "server2".to_string(),
+                weight: 1,
+                tls_sni: None,
+                name: None,
+            },
+        ];
+        let lb = WeightedRoundRobin::new(&upstreams);
+
+        let sequence = (0..12)
+            .map(|_| lb.select().unwrap().target.as_str())
+            .collect::<Vec<_>>();
+
+        // server2 is picked in the middle of each cycle instead of after a burst of server1
+        let cycle = [
+            "server1", "server1", "server1", "server2", "server1", "server1",
+        ];
+        assert_eq!(sequence, [cycle, cycle].concat());
+    }
+
+    #[test]
+    fn test_large_weights_are_reduced() {
+        let upstreams = vec![
+            Upstream {
+                target: "server1".to_string(),
+                weight: 30_000,
+                tls_sni: None,
+                name: None,
+            },
+            Upstream {
+                target: "server2".to_string(),
+                weight: 10_000,
+                tls_sni: None,
+                name: None,
+            },
+        ];
+        let lb = WeightedRoundRobin::new(&upstreams);
+
+        // Only one counter per upstream regardless of the weight values
+        assert_eq!(lb.current_weights.lock().unwrap().len(), 2);
+        assert_eq!(lb.total_weight, 4);
+
+        let mut counts = HashMap::new();
+        for _ in 0..1000 {
+            if let Some(upstream) = lb.select() {
+                *counts.entry(upstream.target.clone()).or_insert(0) += 1;
+            }
+        }
+        assert_eq!(counts["server1"], 750);
+        assert_eq!(counts["server2"], 250);
+    }
+
+    #[test]
+    fn test_large_coprime_weights_keep_one_counter_per_upstream() {
+        // Sums to the largest reduced weight sum validation accepts
+        let upstreams = vec![
+            Upstream {
+                target: "server1".to_string(),
+                weight: 9_993,
+                tls_sni: None,
+                name: None,
+            },
+            Upstream {
+                target: "server2".to_string(),
+                weight: 7,
+                tls_sni: None,
+                name: None,
+            },
+        ];
+        let lb = WeightedRoundRobin::new(&upstreams);
+        assert_eq!(lb.current_weights.lock().unwrap().len(), 2);
+        assert_eq!(lb.total_weight as u64, MAX_REDUCED_WEIGHT_SUM);
+
+        // One full cycle yields exactly the configured shares
+        let mut counts = HashMap::new();
+        for _ in 0..MAX_REDUCED_WEIGHT_SUM {
+            let upstream = lb.select().unwrap();
+            *counts.entry(upstream.target.as_str()).or_insert(0) += 1;
+        }
+        assert_eq!(counts["server1"], 9_993);
+        assert_eq!(counts["server2"], 7);
+    }
+
+    #[test]
+    fn test_round_robin_ignores_weights() {
+        let upstreams = vec![
+            Upstream {
+                target: "server1".to_string(),
+                weight: 3,
+                tls_sni: None,
+                name: None,
+            },
+            Upstream {
+                target: "server2".to_string(),
+                weight: 0,
+                tls_sni: None,
+                name: None,
+            },
+        ];
+        let lb = RoundRobin::new(&upstreams);
+
+        let sequence = (0..4)
+            .map(|_| lb.select().unwrap().target.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(sequence, ["server1", "server2", "server1", "server2"]);
+        assert!(RoundRobin::new(&[]).select().is_none());
+    }
+
+    #[test]
+    fn test_concurrent_selection_keeps_exact_distribution() {
+        let upstreams = vec![
+            Upstream {
+                target: "server1".to_string(),
+                weight: 3,
+                tls_sni: None,
+                name: None,
+            },
+            Upstream {
+                target: "server2".to_string(),
+                weight: 1,
+                tls_sni: None,
+                name: None,
+            },
+        ];
+        let strategies: [(Box<dyn LoadBalancerStrategy>, usize); 2] = [
+            (Box::new(WeightedRoundRobin::new(&upstreams)), 6000),
+            (Box::new(RoundRobin::new(&upstreams)), 4000),
+        ];
+
+        for (lb, expected_server1) in strategies {
+            // 8 threads of 1000 selections complete whole cycles, so nothing may be skipped
+            let server1_selections = std::thread::scope(|scope| {
+                let handles = (0..8)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            (0..1000)
+                                .filter(|_| lb.select().unwrap().target == "server1")
+                                .count()
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap())
+                    .sum::<usize>()
+            });
+            assert_eq!(server1_selections, expected_server1);
+        }
+    }
+
+    #[test]
+    fn test_random_weight_distribution() {
+        let upstreams = vec![
+            Upstream {
+                target: "server1".to_string(),
+                weight: 3,
+                tls_sni: None,
+                name: None,
+            },
+            Upstream {
+                target: "server2".to_string(),
+                weight: 0,
+                tls_sni: None,
+                name: None,
+            },
+            Upstream {
+                target: "server3".to_string(),
+                weight: 1,
+                tls_sni: None,
+                name: None,
+            },
+        ];
+        let lb = Random::new(&upstreams);
+
+        let mut counts = HashMap::new();
+        for _ in 0..10_000 {
+            if let Some(upstream) = lb.select() {
+                *counts.entry(upstream.target.clone()).or_insert(0) += 1;
+            }
+        }
+
+        // Should be around 75% server1, 25% server3 and never server2
+        assert!(counts["server1"] > 7_200 && counts["server1"] < 7_800);
+        assert!(counts["server3"] > 2_200 && counts["server3"] < 2_800);
+        assert!(!counts.contains_key("server2"));
+        assert!(Random::new(&[]).select().is_none());
+    }
+
+    #[test]
+    fn test_least_connections_prefers_idle_upstream() {
+        let upstreams = ["server1", "server2", "server3"]
+            .map(|target| Upstream {
+                target: target.to_string(),
+                weight: 1,
+                tls_sni: None,
+                name: None,
+            })
+            .to_vec();
+        let lb = LeastConnections::new(&upstreams);
+        let guard = |target| InFlightGuard::new(lb.in_flight(target).unwrap());
+
+        let busy = [guard("server1"), guard("server1"), guard("server3")];
+        for _ in 0..3 {
+            assert_eq!(lb.select().unwrap().target, "server2");
+        }
+
+        // Released counters make the upstreams eligible again, ties rotate
+        drop(busy);
+        let sequence = (0..3)
+            .map(|_| lb.select().unwrap().target.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(sequence, ["server1", "server2", "server3"]);
+        assert!(lb.in_flight("unknown").is_none());
+        assert!(LeastConnections::new(&[]).select().is_none());
+    }
+
+    #[test]
+    fn test_consistent_hashing_keeps_keys_on_upstream() {
+        let upstream = |target: &str, weight| Upstream {
+            target: target.to_string(),
+            weight,
+            tls_sni: None,
+            name: None,
+        };
+        let upstreams = vec![
+            upstream("server1", 1),
+            upstream("server2", 1),
+            upstream("server3", 2),
+        ];
+        let lb = ConsistentHashing::new(&upstreams);
+        let keys = (0..1000)
+            .map(|index| format!("10.0.{}.{}", index / 256, index % 256))
+            .collect::<Vec<_>>();
+        let select = |lb: &ConsistentHashing, key: &str| {
+            lb.select_with_key(Some(key)).unwrap().target.clone()
+        };
+
+        let mut counts = HashMap::new();
+        for key in &keys {
+            let target = select(&lb, key);
+            assert_eq!(select(&lb, key), target);
+            *counts.entry(target).or_insert(0) += 1;
+        }
+        // Virtual nodes follow the weights, roughly 25%, 25% and 50%
+        assert!(
+            counts["server1"] > 150 && counts["server1"] < 350,
+            "{counts:?}"
+        );
+        assert!(
+            counts["server3"] > 400 && counts["server3"] < 600,
+            "{counts:?}"
+        );
+
+        // Without server2 only its keys move
+        let remaining = ConsistentHashing::new(&[upstream("server1", 1), upstream("server3", 2)]);
+        for key in &keys {
+            let target = select(&lb, key);
+            if target != "server2" {
+                assert_eq!(select(&remaining, key), target);
+            }
+        }
+        assert!(ConsistentHashing::new(&[]).select().is_none());
+    }
+
+    #[test]
+    fn test_consistent_hashing_ring_follows_reduced_weights() {
+        let upstream = |target: &str, weight| Upstream {
+            target: target.to_string(),
+            weight,
+            tls_sni: None,
+            name: None,
+        };
+        let lb = ConsistentHashing::new(&[
+            upstream("server1", 3_000_000),
+            upstream("server2", 1_000_000),
+        ]);
+        assert_eq!(lb.ring.len(), 4 * VIRTUAL_NODES_PER_WEIGHT as usize);
+    }
+
     #[test]
     fn test_no_upstream_returns_none() {
         let upstreams = vec![];
@@ -133,10 +659,14 @@ mod tests {
             Upstream {
                 target: "server1".to_string(),
                 weight: 0,
+                tls_sni: None,
+                name: None,
             },
             Upstream {
                 target: "server2".to_string(),
                 weight: 0,
+                tls_sni: None,
+                name: None,
             },
         ];
         let lb = WeightedRoundRobin::new(&upstreams);
@@ -1,14 +1,20 @@
 #![deny(warnings)]
 #![forbid(unsafe_code)]
 
-use crate::config::load_config;
+use crate::canary::Canaries;
+use crate::config::{ValidationReport, load_config};
 use crate::gateway_runtime::GatewayRuntime;
+use crate::health::HealthChecks;
+use crate::lifecycle::{Lifecycle, LifecycleEvent};
+use crate::metering::Traffic;
 use crate::middleware::registry::MiddlewareRegistry;
-use crate::utils::{graceful_shutdown, shutdown_signal};
+use crate::paused_routes::PausedRoutes;
+use crate::route_errors::RouteErrors;
+use crate::utils::{build_http_client, graceful_shutdown, shutdown_signal};
 use arc_swap::ArcSwap;
-use std::env;
 use std::sync::{Arc, LazyLock, OnceLock};
-use std::time::Duration;
+use std::{env, process};
+use tokio::sync::broadcast;
 use tokio::task::JoinSet;
 use tokio_rustls::TlsAcceptor;
 use tokio_util::sync::CancellationToken;
@@ -35,6 +41,22 @@ mod load_balancer;
 
 mod gateway_runtime;
 
+mod lifecycle;
+
+mod dns;
+
+mod metering;
+
+mod route_errors;
+
+mod health;
+
+mod canary;
+
+mod ab_test;
+
+mod paused_routes;
+
 pub type SharedGatewayState = Arc<ArcSwap<GatewayRuntime>>;
 
 pub type BoxedSlice<T> = Box<[T]>;
@@ -49,6 +71,18 @@ const PACKAGE_DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
 
 static MIDDLEWARE_REGISTRY: LazyLock<MiddlewareRegistry> = LazyLock::new(MiddlewareRegistry::init);
 
+static LIFECYCLE: LazyLock<Lifecycle> = LazyLock::new(Lifecycle::new);
+
+static TRAFFIC: LazyLock<Traffic> = LazyLock::new(Traffic::new);
+
+static ROUTE_ERRORS: LazyLock<RouteErrors> = LazyLock::new(RouteErrors::new);
+
+static HEALTH_CHECKS: LazyLock<HealthChecks> = LazyLock::new(HealthChecks::new);
+
+static CANARIES: LazyLock<Canaries> = LazyLock::new(Canaries::new);
+
+static PAUSED_ROUTES: LazyLock<PausedRoutes> = LazyLock::new(PausedRoutes::new);
+
 static CONFIG_FILE_PATH: OnceLock<String> = OnceLock::new();
 
 #[tokio::main]
@@ -56,11 +90,15 @@ async fn main() {
     let args = env::args().collect::<Box<[_]>>();
     assert!(
         args.len() > 2,
-        "Config file is required\nUsage: cargo run --config <config-file-path>"
+        "Config file is required\nUsage: cargo run --config <config-file-path>\n       cargo run --check <config-file-path> [--format text|json]"
     );
 
+    if args[1] == "--check" {
+        process::exit(check_config(&args[2], &args[3..]));
+    }
+
     if args[1] != "--config" {
-        panic!("expected --config found {:?}", args[1]);
+        panic!("expected --config or --check found {:?}", args[1]);
     }
 
     tracing::info!("Starting {PACKAGE_NAME}-v{PACKAGE_VERSION}");
@@ -72,24 +110,44 @@ async fn main() {
 
     let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
 
-    let _guard = logger::init_layers(&gateway_config.log, &gateway_config.access_log);
-
-    let tls_acceptor = gateway_config.tls.as_ref().map(|tls_config| {
-        let rustls_server_config = server::init_rustls_server_config(tls_config);
-        TlsAcceptor::from(rustls_server_config)
-    });
-
-    let http_client = Arc::new(
-        reqwest::Client::builder()
-            .use_rustls_tls()
-            .timeout(Duration::from_secs(30))
-            .build()
-            .expect("Invalid tls config"),
-    );
+    let log_guards = logger::init_layers(&gateway_config.log, &gateway_config.access_log);
+
+    let tls_acceptor = match gateway_config
+        .tls
+        .as_ref()
+        .map(|tls_config| server::init_rustls_server_config(tls_config))
+        .transpose()
+    {
+        Ok(rustls_server_config) => rustls_server_config.map(TlsAcceptor::from),
+        Err(err) => {
+            tracing::error!("Failed to load TLS certificates: {err}");
+            // Flush buffered logs, exit skips destructors
+            drop(log_guards);
+            process::exit(1);
+        }
+    };
+
+    let http_client = match build_http_client(&gateway_config.upstream, None) {
+        Ok(http_client) => Arc::new(http_client),
+        Err(err) => {
+            tracing::error!("Failed to build upstream HTTP client: {err}");
+            // Flush buffered logs, exit skips destructors
+            drop(log_guards);
+            process::exit(1);
+        }
+    };
+    MIDDLEWARE_REGISTRY.set_http_client(http_client.clone());
 
     let cancel_token = CancellationToken::new();
 
-    let gateway_runtime = GatewayRuntime::new(gateway_config.clone());
+    let gateway_runtime = match GatewayRuntime::new(gateway_config.clone()) {
+        Ok(gateway_runtime) => gateway_runtime,
+        Err(err) => {
+            tracing::error!("Invalid config: {err}");
+            drop(log_guards);
+            process::exit(1);
+        }
+    };
     let gateway_state = SharedGatewayState::new(ArcSwap::from_pointee(gateway_runtime));
 
     let mut listener_joinset = JoinSet::new();
@@ -112,11 +170,58 @@ async fn main() {
         });
     }
 
+    // A single checker for the process rather than one per `GatewayRuntime`: runtimes are rebuilt
+    // on every reload, while the checker follows them through the shared state and keeps the
+    // probe schedule and results across reloads
+    tokio::spawn(HEALTH_CHECKS.run(
+        gateway_state.clone(),
+        http_client.clone(),
+        cancel_token.clone(),
+    ));
+    tokio::spawn(log_lifecycle_events(LIFECYCLE.subscribe()));
+    LIFECYCLE.publish(LifecycleEvent::Started);
+
     tokio::select! {
         _ = listener_joinset.join_next() => {}
         _ = api::start_api_server(gateway_state.clone(), cancel_token.clone()) => {}
         _ = shutdown_signal() => {
+            LIFECYCLE.start_draining();
             graceful_shutdown(cancel_token).await;
         }
     }
 }
+
+/// Records lifecycle transitions, integrations subscribe to `LIFECYCLE` the same way.
+async fn log_lifecycle_events(mut events: broadcast::Receiver<LifecycleEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => tracing::info!(target: "lifecycle", "Lifecycle event {event:?}"),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::warn!(target: "lifecycle", "Missed {missed} lifecycle events");
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Validates the config file and prints the result, returns the process exit code.
+fn check_config(file_path: &str, options: &[String]) -> i32 {
+    let format = match options {
+        [] => "text",
+        [flag, format] if flag == "--format" && matches!(format.as_str(), "text" | "json") => {
+            format.as_str()
+        }
+        _ => {
+            eprintln!("Usage: cargo run --check <config-file-path> [--format text|json]");
+            return 2;
+        }
+    };
+
+    let _ = CONFIG_FILE_PATH.set(file_path.to_string());
+    let report = ValidationReport::from_result(&load_config());
+    match format {
+        "json" => println!("{}", report.to_json()),
+        _ => println!("{}", report.to_text()),
+    }
+    if report.ok { 0 } else { 1 }
+}
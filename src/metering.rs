@@ -0,0 +1,214 @@
+use http_body_util::BodyExt;
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+
+/// Request and response body bytes seen by the gateway for one metering key.
+#[derive(Default)]
+pub struct ByteCounters {
+    request_bytes: AtomicU64,
+    response_bytes: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct ByteCountersSnapshot {
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrafficSnapshot {
+    pub routes: HashMap<String, ByteCountersSnapshot>,
+    pub api_keys: HashMap<String, ByteCountersSnapshot>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+impl ByteCounters {
+    fn add(&self, direction: Direction, bytes: u64) {
+        let counter = match direction {
+            Direction::Request => &self.request_bytes,
+            Direction::Response => &self.response_bytes,
+        };
+        counter.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> ByteCountersSnapshot {
+        ByteCountersSnapshot {
+            request_bytes: self.request_bytes.load(Ordering::Relaxed),
+            response_bytes: self.response_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Process wide body byte counters, keyed by route and optionally by API key.
+///
+/// Counters survive config reloads, a route keeps its counters as long as its key is unchanged.
+pub struct Traffic {
+    routes: RwLock<HashMap<String, Arc<ByteCounters>>>,
+    api_keys: RwLock<HashMap<String, Arc<ByteCounters>>>,
+}
+
+impl Traffic {
+    pub fn new() -> Self {
+        Traffic {
+            routes: RwLock::new(HashMap::new()),
+            api_keys: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The counters a request on `route` with the given API key contributes to.
+    pub fn counters_for(&self, route: &str, api_key: Option<&str>) -> Arc<[Arc<ByteCounters>]> {
+        let mut counters = vec![Self::entry(&self.routes, route)];
+        if let Some(api_key) = api_key {
+            counters.push(Self::entry(&self.api_keys, api_key));
+        }
+        counters.into()
+    }
+
+    pub fn snapshot(&self) -> TrafficSnapshot {
+        let snapshot = |map: &RwLock<HashMap<String, Arc<ByteCounters>>>| {
+            map.read()
+                .unwrap()
+                .iter()
+                .map(|(key, counters)| (key.clone(), counters.snapshot()))
+                .collect()
+        };
+        TrafficSnapshot {
+            routes: snapshot(&self.routes),
+            api_keys: snapshot(&self.api_keys),
+        }
+    }
+
+    fn entry(map: &RwLock<HashMap<String, Arc<ByteCounters>>>, key: &str) -> Arc<ByteCounters> {
+        if let Some(counters) = map.read().unwrap().get(key) {
+            return counters.clone();
+        }
+        map.write()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+/// Body wrapper adding the size of every data frame to the counters as it streams through.
+pub struct CountingBody<B> {
+    inner: B,
+    counters: Arc<[Arc<ByteCounters>]>,
+    direction: Direction,
+}
+
+impl<B> CountingBody<B> {
+    pub fn new(inner: B, counters: Arc<[Arc<ByteCounters>]>, direction: Direction) -> Self {
+        CountingBody {
+            inner,
+            counters,
+            direction,
+        }
+    }
+}
+
+impl<B> Body for CountingBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll
+            && let Some(data) = frame.data_ref()
+        {
+            for counters in self.counters.iter() {
+                counters.add(self.direction, data.len() as u64);
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Wraps a boxed body so its bytes are metered.
+pub fn count_body<E>(
+    body: BoxBody<Bytes, E>,
+    counters: Arc<[Arc<ByteCounters>]>,
+    direction: Direction,
+) -> BoxBody<Bytes, E>
+where
+    E: 'static,
+{
+    CountingBody::new(body, counters, direction).boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+
+    /// Yields one data frame per chunk, like a chunked upload.
+    struct ChunkedBody(VecDeque<Bytes>);
+
+    impl Body for ChunkedBody {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Ready(self.0.pop_front().map(|chunk| Ok(Frame::data(chunk))))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_counts_streamed_frames() {
+        let traffic = Traffic::new();
+        let counters = traffic.counters_for("/api/*", Some("tenant-a"));
+
+        let body = ChunkedBody(
+            ["hello", " ", "world"]
+                .map(|chunk| Bytes::from_static(chunk.as_bytes()))
+                .into(),
+        );
+        let body = CountingBody::new(body, counters.clone(), Direction::Request);
+        body.collect().await.unwrap();
+
+        let body = CountingBody::new(
+            Full::new(Bytes::from_static(b"ok")),
+            counters,
+            Direction::Response,
+        );
+        body.collect().await.unwrap();
+
+        let snapshot = traffic.snapshot();
+        let expected = ByteCountersSnapshot {
+            request_bytes: 11,
+            response_bytes: 2,
+        };
+        assert_eq!(snapshot.routes["/api/*"], expected);
+        assert_eq!(snapshot.api_keys["tenant-a"], expected);
+    }
+}
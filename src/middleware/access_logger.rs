@@ -70,7 +70,11 @@ impl Middleware for AccessLogger {
 }
 
 impl MiddlewareFactory for AccessLogger {
-    fn create(&self, _config: Option<MiddlewareConfig>) -> Arc<dyn Middleware> {
-        Arc::new(AccessLogger)
+    fn create(
+        &self,
+        _name: &str,
+        _config: Option<MiddlewareConfig>,
+    ) -> std::result::Result<Arc<dyn Middleware>, String> {
+        Ok(Arc::new(AccessLogger))
     }
 }
@@ -1,6 +1,7 @@
 use crate::config::MiddlewareConfig;
 use crate::middleware::registry::MiddlewareFactory;
 use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::router::PathParams;
 use async_trait::async_trait;
 use hyper::{Request, Response, Uri};
 use std::sync::Arc;
@@ -9,6 +10,20 @@ pub struct AddPrefix {
     prefix: String,
 }
 
+/// `prefix` with its `:name` segments replaced by the captured route path params.
+fn expand_params(prefix: &str, params: &PathParams) -> String {
+    prefix
+        .split('/')
+        .map(|segment| {
+            segment
+                .strip_prefix(':')
+                .and_then(|name| params.get(name))
+                .unwrap_or(segment)
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
 #[async_trait]
 impl Middleware for AddPrefix {
     async fn call(
@@ -22,7 +37,11 @@ impl Middleware for AddPrefix {
             .query()
             .map(|q| format!("?{q}"))
             .unwrap_or_default();
-        let prefixed_path = format!("{}{}", self.prefix, req.uri().path());
+        let prefix = match req.extensions().get::<PathParams>() {
+            Some(params) => expand_params(&self.prefix, params),
+            None => self.prefix.clone(),
+        };
+        let prefixed_path = format!("{prefix}{}", req.uri().path());
 
         let mut parts = req.uri().clone().into_parts();
         parts.path_and_query = Some(
@@ -40,10 +59,32 @@ impl Middleware for AddPrefix {
 pub struct AddPrefixFactory;
 
 impl MiddlewareFactory for AddPrefixFactory {
-    fn create(&self, config: Option<MiddlewareConfig>) -> Arc<dyn Middleware> {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
         match config {
-            Some(MiddlewareConfig::AddPrefix(cfg)) => Arc::new(AddPrefix { prefix: cfg.prefix }),
-            _ => panic!("Invalid config for add prefix middleware"),
+            Some(MiddlewareConfig::AddPrefix(cfg)) => {
+                Ok(Arc::new(AddPrefix { prefix: cfg.prefix }))
+            }
+            _ => Err(String::from("Invalid config for add prefix middleware")),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_refers_to_path_params() {
+        let params = PathParams::from([("tenant", "acme"), ("id", "42")]);
+        assert_eq!(
+            expand_params("/tenants/:tenant/v2", &params),
+            "/tenants/acme/v2"
+        );
+        // Unknown params are kept as written
+        assert_eq!(expand_params("/:region/api", &params), "/:region/api");
+    }
+}
@@ -0,0 +1,198 @@
+use crate::config::{ApiKeySource, MiddlewareConfig};
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::utils::response_with_status;
+use async_trait::async_trait;
+use aws_lc_rs::digest::{SHA256, SHA256_OUTPUT_LEN, digest};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Request, Response, StatusCode};
+use std::sync::Arc;
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+
+struct Client {
+    key_sha256: [u8; SHA256_OUTPUT_LEN],
+    name: HeaderValue,
+}
+
+pub struct ApiKey {
+    source: ApiKeySource,
+    clients: Box<[Client]>,
+    client_header: Option<HeaderName>,
+}
+
+impl ApiKey {
+    fn key(&self, req: &Request<RequestBody>) -> Option<String> {
+        match &self.source {
+            ApiKeySource::RequestHeader(header) => req
+                .headers()
+                .get(header)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from),
+            ApiKeySource::Query(name) => form_urlencoded::parse(req.uri().query()?.as_bytes())
+                .find(|(param, _)| param == name)
+                .map(|(_, value)| value.into_owned()),
+        }
+        .filter(|key| !key.is_empty())
+    }
+
+    /// Client of the key, every configured key is compared by its digest so neither the keys
+    /// nor their lengths leak through how long a wrong attempt takes.
+    fn client(&self, key: &str) -> Option<&Client> {
+        let key_sha256 = digest(&SHA256, key.as_bytes());
+        let mut found = subtle::Choice::from(0);
+        let mut index = 0u32;
+        for (i, client) in self.clients.iter().enumerate() {
+            let matches = client.key_sha256.ct_eq(key_sha256.as_ref());
+            index.conditional_assign(&(i as u32), matches);
+            found |= matches;
+        }
+        bool::from(found).then(|| &self.clients[index as usize])
+    }
+}
+
+#[async_trait]
+impl Middleware for ApiKey {
+    async fn call(
+        &self,
+        mut req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let Some(key) = self.key(&req) else {
+            return Ok(response_with_status(StatusCode::UNAUTHORIZED));
+        };
+        let Some(client) = self.client(&key) else {
+            tracing::debug!("Rejecting unknown API key");
+            return Ok(response_with_status(StatusCode::FORBIDDEN));
+        };
+        if let Some(header) = &self.client_header {
+            req.headers_mut()
+                .insert(header.clone(), client.name.clone());
+        }
+        next.run(req).await
+    }
+}
+
+pub struct ApiKeyFactory;
+
+impl MiddlewareFactory for ApiKeyFactory {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::ApiKey(cfg)) => {
+                let clients = cfg
+                    .keys
+                    .iter()
+                    .map(|(key, name)| {
+                        let mut key_sha256 = [0; SHA256_OUTPUT_LEN];
+                        key_sha256.copy_from_slice(digest(&SHA256, key.as_bytes()).as_ref());
+                        let name = HeaderValue::from_str(name)
+                            .map_err(|_| format!("Invalid client name {name}"))?;
+                        Ok(Client { key_sha256, name })
+                    })
+                    .collect::<Result<_, String>>()?;
+                let client_header = cfg
+                    .client_header
+                    .map(|header| {
+                        HeaderName::from_bytes(header.as_bytes())
+                            .map_err(|_| format!("Invalid header name {header}"))
+                    })
+                    .transpose()?;
+                Ok(Arc::new(ApiKey {
+                    source: cfg.source,
+                    clients,
+                    client_header,
+                }))
+            }
+            _ => Err(String::from("Invalid config for api key middleware")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiKeyConfig;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::Bytes;
+    use std::collections::HashMap;
+
+    /// Status of the request and the client header the upstream received.
+    async fn run(
+        source: ApiKeySource,
+        uri: &str,
+        key_header: Option<&str>,
+    ) -> (StatusCode, Option<String>) {
+        let config = ApiKeyConfig {
+            source,
+            keys: HashMap::from([
+                (String::from("key-mobile"), String::from("mobile-app")),
+                (String::from("key-partner"), String::from("partner")),
+            ]),
+            client_header: Some(String::from("x-client")),
+        };
+        let middlewares = [ApiKeyFactory
+            .create("api-key", Some(MiddlewareConfig::ApiKey(config)))
+            .unwrap()];
+        let handler: HandlerFunc = Arc::new(|req| {
+            Box::pin(async move {
+                let client = req.headers().get("x-client").cloned();
+                let mut response =
+                    Response::new(Empty::new().map_err(|never| match never {}).boxed());
+                if let Some(client) = client {
+                    response.headers_mut().insert("x-client", client);
+                }
+                Ok(response)
+            })
+        });
+        let mut req = Request::builder().uri(uri).header("x-client", "spoofed");
+        if let Some(key) = key_header {
+            req = req.header("x-api-key", key);
+        }
+        let req = req
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        let response = Next::new(handler, &middlewares).run(req).await.unwrap();
+        let client = response
+            .headers()
+            .get("x-client")
+            .map(|client| client.to_str().unwrap().to_string());
+        (response.status(), client)
+    }
+
+    #[tokio::test]
+    async fn test_key_from_header_identifies_client() {
+        let source = ApiKeySource::default();
+        let (status, client) = run(source.clone(), "/orders", Some("key-partner")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(client.as_deref(), Some("partner"));
+
+        let (status, _) = run(source, "/orders?api_key=key-partner", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_key_from_query_identifies_client() {
+        let source = ApiKeySource::Query(String::from("api_key"));
+        let (status, client) = run(source.clone(), "/orders?page=2&api_key=key-mobile", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(client.as_deref(), Some("mobile-app"));
+
+        let (status, _) = run(source, "/orders?page=2", Some("key-mobile")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_key_is_forbidden() {
+        let (status, client) = run(ApiKeySource::default(), "/orders", Some("key-mobil")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(client, None);
+    }
+}
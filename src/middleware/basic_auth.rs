@@ -0,0 +1,291 @@
+use crate::config::MiddlewareConfig;
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::utils::response_with_status;
+use async_trait::async_trait;
+use aws_lc_rs::digest::{SHA256, SHA256_OUTPUT_LEN, digest};
+use aws_lc_rs::pbkdf2::{self, PBKDF2_HMAC_SHA256};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hyper::header::{AUTHORIZATION, HeaderValue, WWW_AUTHENTICATE};
+use hyper::{Request, Response, StatusCode};
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+
+/// Configured password hash, a bare hex SHA-256 digest or a salted
+/// `pbkdf2_sha256$<iterations>$<salt>$<base64 hash>` as written by Django.
+pub enum PasswordHash {
+    Sha256([u8; SHA256_OUTPUT_LEN]),
+    Pbkdf2Sha256 {
+        iterations: NonZeroU32,
+        salt: Box<[u8]>,
+        hash: Box<[u8]>,
+    },
+}
+
+impl PasswordHash {
+    fn verify(&self, password: &[u8]) -> subtle::Choice {
+        match self {
+            PasswordHash::Sha256(password_sha256) => {
+                password_sha256.ct_eq(digest(&SHA256, password).as_ref())
+            }
+            PasswordHash::Pbkdf2Sha256 {
+                iterations,
+                salt,
+                hash,
+            } => subtle::Choice::from(u8::from(
+                pbkdf2::verify(PBKDF2_HMAC_SHA256, *iterations, salt, password, hash).is_ok(),
+            )),
+        }
+    }
+}
+
+struct User {
+    name: Box<[u8]>,
+    password: PasswordHash,
+}
+
+pub struct BasicAuth {
+    users: Box<[User]>,
+    challenge: HeaderValue,
+}
+
+impl BasicAuth {
+    /// The user is picked in constant time and a password is always verified, so neither the user
+    /// names nor the passwords leak through how long a wrong attempt takes. Only the password of
+    /// the picked user is hashed, a slow hash is computed once per attempt.
+    fn is_authorized(&self, authorization: Option<&HeaderValue>) -> bool {
+        let Some((name, password)) = authorization.and_then(credentials) else {
+            return false;
+        };
+        let mut found = subtle::Choice::from(0);
+        let mut index = 0u32;
+        for (i, user) in self.users.iter().enumerate() {
+            let matches = user.name.ct_eq(name.as_bytes());
+            index.conditional_assign(&(i as u32), matches);
+            found |= matches;
+        }
+        let Some(user) = self.users.get(index as usize) else {
+            return false;
+        };
+        (found & user.password.verify(password.as_bytes())).into()
+    }
+}
+
+/// User name and password of a `Basic` authorization header.
+fn credentials(authorization: &HeaderValue) -> Option<(String, String)> {
+    let (scheme, encoded) = authorization.to_str().ok()?.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (name, password) = decoded.split_once(':')?;
+    Some((name.to_string(), password.to_string()))
+}
+
+pub fn parse_password_hash(hash: &str) -> Option<PasswordHash> {
+    let Some(pbkdf2) = hash.strip_prefix("pbkdf2_sha256$") else {
+        return parse_sha256_hex(hash).map(PasswordHash::Sha256);
+    };
+    let mut parts = pbkdf2.splitn(3, '$');
+    let iterations = parts.next()?.parse().ok()?;
+    let salt = parts.next()?.as_bytes().into();
+    let hash = STANDARD.decode(parts.next()?).ok()?;
+    if hash.is_empty() {
+        return None;
+    }
+    Some(PasswordHash::Pbkdf2Sha256 {
+        iterations,
+        salt,
+        hash: hash.into(),
+    })
+}
+
+fn parse_sha256_hex(hex: &str) -> Option<[u8; SHA256_OUTPUT_LEN]> {
+    if hex.len() != SHA256_OUTPUT_LEN * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; SHA256_OUTPUT_LEN];
+    for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[async_trait]
+impl Middleware for BasicAuth {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        if self.is_authorized(req.headers().get(AUTHORIZATION)) {
+            return next.run(req).await;
+        }
+        let mut response = response_with_status(StatusCode::UNAUTHORIZED);
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, self.challenge.clone());
+        Ok(response)
+    }
+}
+
+pub struct BasicAuthFactory;
+
+impl MiddlewareFactory for BasicAuthFactory {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::BasicAuth(cfg)) => {
+                let users = cfg
+                    .users
+                    .iter()
+                    .map(|(name, hash)| {
+                        let password = parse_password_hash(hash)
+                            .ok_or(format!("Invalid password hash of user {name}"))?;
+                        Ok(User {
+                            name: name.as_bytes().into(),
+                            password,
+                        })
+                    })
+                    .collect::<Result<_, String>>()?;
+                let challenge = HeaderValue::from_str(&format!(
+                    "Basic realm=\"{}\", charset=\"UTF-8\"",
+                    cfg.realm
+                ))
+                .map_err(|_| format!("Invalid realm {}", cfg.realm))?;
+                Ok(Arc::new(BasicAuth { users, challenge }))
+            }
+            _ => Err(String::from("Invalid config for basic auth middleware")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BasicAuthConfig;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::Bytes;
+    use std::collections::HashMap;
+
+    /// `printf %s 's3cret' | sha256sum`
+    const S3CRET_SHA256: &str = "1ec1c26b50d5d3c58d9583181af8076655fe00756bf7285940ba3670f99fcba0";
+
+    fn pbkdf2_hash(password: &str) -> String {
+        let mut hash = [0; SHA256_OUTPUT_LEN];
+        let iterations = NonZeroU32::new(1000).unwrap();
+        pbkdf2::derive(
+            PBKDF2_HMAC_SHA256,
+            iterations,
+            b"pepper",
+            password.as_bytes(),
+            &mut hash,
+        );
+        format!(
+            "pbkdf2_sha256${iterations}$pepper${}",
+            STANDARD.encode(hash)
+        )
+    }
+
+    fn ok_handler() -> HandlerFunc {
+        Arc::new(|_req| Box::pin(async { Ok(response_with_status(StatusCode::OK)) }))
+    }
+
+    async fn run(authorization: Option<&str>) -> Response<ResponseBody> {
+        let hunter2_sha256 = digest(&SHA256, b"hunter2")
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        let config = MiddlewareConfig::BasicAuth(BasicAuthConfig {
+            realm: String::from("admin area"),
+            users: HashMap::from([
+                (String::from("alice"), String::from(S3CRET_SHA256)),
+                (String::from("bob"), hunter2_sha256),
+                (String::from("carol"), pbkdf2_hash("open sesame")),
+            ]),
+        });
+        let middlewares = [BasicAuthFactory.create("basic-auth", Some(config)).unwrap()];
+        let mut req = Request::builder().uri("/");
+        if let Some(authorization) = authorization {
+            req = req.header(AUTHORIZATION, authorization);
+        }
+        let req = req
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        Next::new(ok_handler(), &middlewares)
+            .run(req)
+            .await
+            .unwrap()
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", STANDARD.encode(credentials))
+    }
+
+    #[tokio::test]
+    async fn test_valid_credentials_are_forwarded() {
+        let response = run(Some(&basic("alice:s3cret"))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = run(Some(&basic("bob:hunter2").replace("Basic", "basic"))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = run(Some(&basic("carol:open sesame"))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_credentials_are_challenged() {
+        for credentials in [
+            "alice:hunter2",
+            "mallory:s3cret",
+            "alice",
+            "alice:s3cret:",
+            "carol:open sesame!",
+        ] {
+            let response = run(Some(&basic(credentials))).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{credentials}");
+            assert_eq!(
+                response.headers()[WWW_AUTHENTICATE],
+                "Basic realm=\"admin area\", charset=\"UTF-8\""
+            );
+        }
+        let response = run(Some("Bearer token")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_missing_header_is_challenged() {
+        let response = run(None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(WWW_AUTHENTICATE));
+    }
+
+    #[test]
+    fn test_password_hashes_are_parsed() {
+        assert!(parse_password_hash(S3CRET_SHA256).is_some());
+        assert!(parse_password_hash(&S3CRET_SHA256[1..]).is_none());
+        assert!(parse_password_hash(&S3CRET_SHA256.replace('a', "g")).is_none());
+
+        let pbkdf2 = pbkdf2_hash("open sesame");
+        assert!(parse_password_hash(&pbkdf2).is_some());
+        for invalid in [
+            pbkdf2.replace("$1000$", "$0$"),
+            pbkdf2.replace("$1000$", "$many$"),
+            String::from("pbkdf2_sha256$1000$pepper$"),
+            String::from("pbkdf2_sha256$1000$pepper"),
+            String::from("pbkdf2_sha256$1000$pepper$not base64"),
+        ] {
+            assert!(parse_password_hash(&invalid).is_none(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,816 @@
+use crate::config::{CacheConfig, MiddlewareConfig};
+use crate::middleware::registry::{MiddlewareFactory, NamedStates};
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::utils::bad_gateway_response;
+use async_trait::async_trait;
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::Bytes;
+use hyper::header::{
+    AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, COOKIE, HOST, HeaderMap, HeaderName,
+    HeaderValue, SET_COOKIE, VARY,
+};
+use hyper::{Method, Request, Response, StatusCode};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Identifies a request to the cache, the store adds the headers named in the upstream `Vary`.
+#[derive(Clone)]
+struct RequestKey {
+    /// Host, path and query
+    base: String,
+    /// Values of the configured `vary_by` headers and cookies
+    variant: String,
+    headers: HeaderMap,
+}
+
+struct CachedResponse {
+    base: String,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    size: usize,
+    last_used: u64,
+    stale_while_revalidate: Duration,
+    stale_if_error: Duration,
+    /// Set while a background refresh is running so concurrent stale hits do not start another
+    revalidating: bool,
+}
+
+impl CachedResponse {
+    fn to_response(&self, age: Duration) -> Response<ResponseBody> {
+        let mut response = Response::new(
+            Full::new(self.body.clone())
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response
+            .headers_mut()
+            .insert(AGE, HeaderValue::from(age.as_secs()));
+        response
+    }
+}
+
+enum Lookup {
+    Fresh(Response<ResponseBody>),
+    /// Past the TTL but within `stale-while-revalidate`, only the first caller has to revalidate
+    Stale {
+        response: Response<ResponseBody>,
+        revalidate: bool,
+    },
+    /// Past `stale-while-revalidate` but within `stale-if-error`, served if the upstream fails
+    StaleIfError(Response<ResponseBody>),
+    Miss,
+}
+
+/// In-memory responses bounded by their total size, the least recently used are evicted first.
+pub struct ResponseCache {
+    entries: HashMap<String, CachedResponse>,
+    /// Last use tick to key, the first entry is the least recently used
+    lru: BTreeMap<u64, String>,
+    total_bytes: usize,
+    tick: u64,
+    max_bytes: usize,
+    max_entry_bytes: usize,
+    /// Cached keys per base key, oldest first
+    variants: HashMap<String, Vec<String>>,
+    /// Request headers named in the latest upstream `Vary` per base key
+    vary: HashMap<String, Vec<HeaderName>>,
+    max_variants: usize,
+}
+
+impl ResponseCache {
+    fn new(max_bytes: usize, max_entry_bytes: usize, max_variants: usize) -> Self {
+        ResponseCache {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            total_bytes: 0,
+            tick: 0,
+            max_bytes,
+            max_entry_bytes,
+            variants: HashMap::new(),
+            vary: HashMap::new(),
+            max_variants,
+        }
+    }
+
+    fn full_key(&self, key: &RequestKey) -> String {
+        let mut full_key = format!("{}\n{}", key.base, key.variant);
+        for name in self.vary.get(&key.base).into_iter().flatten() {
+            append_header_values(&mut full_key, &key.headers, name);
+        }
+        full_key
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Cached response for `key` by freshness, entries past every stale window are dropped.
+    fn lookup(&mut self, key: &RequestKey, ttl: Duration) -> Lookup {
+        let key = &self.full_key(key);
+        let Some(entry) = self.entries.get(key) else {
+            return Lookup::Miss;
+        };
+        let age = entry.stored_at.elapsed();
+        let staleness = age.saturating_sub(ttl);
+        if age >= ttl
+            && staleness >= entry.stale_while_revalidate
+            && staleness >= entry.stale_if_error
+        {
+            self.remove(key);
+            return Lookup::Miss;
+        }
+
+        let tick = self.next_tick();
+        let Some(entry) = self.entries.get_mut(key) else {
+            return Lookup::Miss;
+        };
+        self.lru.remove(&entry.last_used);
+        entry.last_used = tick;
+        self.lru.insert(tick, key.to_string());
+
+        let response = entry.to_response(age);
+        if age < ttl {
+            Lookup::Fresh(response)
+        } else if staleness < entry.stale_while_revalidate {
+            let revalidate = !entry.revalidating;
+            entry.revalidating = true;
+            Lookup::Stale {
+                response,
+                revalidate,
+            }
+        } else {
+            Lookup::StaleIfError(response)
+        }
+    }
+
+    /// Lets the next stale hit retry a background refresh that did not replace the entry.
+    fn finish_revalidation(&mut self, key: &RequestKey) {
+        let key = self.full_key(key);
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.revalidating = false;
+        }
+    }
+
+    /// Stores the response unless it exceeds the per entry cap, returns whether it was stored.
+    fn insert(
+        &mut self,
+        request_key: &RequestKey,
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> bool {
+        // The latest upstream `Vary` decides which request headers select the variant
+        let mut vary = headers
+            .get_all(VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+            .collect::<Vec<_>>();
+        vary.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        vary.dedup();
+        let base = request_key.base.clone();
+        if vary.is_empty() {
+            self.vary.remove(&base);
+        } else {
+            self.vary.insert(base.clone(), vary);
+        }
+        let key = self.full_key(request_key);
+
+        let size = key.len()
+            + body.len()
+            + headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>();
+        if size > self.max_entry_bytes || size > self.max_bytes {
+            return false;
+        }
+        self.remove(&key);
+        // Bound the variants of one URL so a varying header cannot crowd out everything else
+        if let Some(oldest) = self
+            .variants
+            .get(&base)
+            .filter(|variants| variants.len() >= self.max_variants)
+            .and_then(|variants| variants.first().cloned())
+        {
+            self.remove(&oldest);
+        }
+        while self.total_bytes + size > self.max_bytes {
+            let Some((_, oldest)) = self.lru.pop_first() else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+
+        let tick = self.next_tick();
+        self.lru.insert(tick, key.clone());
+        self.total_bytes += size;
+        self.variants
+            .entry(base.clone())
+            .or_default()
+            .push(key.clone());
+        self.entries.insert(
+            key,
+            CachedResponse {
+                base,
+                status,
+                stale_while_revalidate: directive_seconds(&headers, "stale-while-revalidate"),
+                stale_if_error: directive_seconds(&headers, "stale-if-error"),
+                headers,
+                body,
+                stored_at: Instant::now(),
+                size,
+                last_used: tick,
+                revalidating: false,
+            },
+        );
+        true
+    }
+
+    fn remove(&mut self, key: &str) {
+        let Some(entry) = self.entries.remove(key) else {
+            return;
+        };
+        self.lru.remove(&entry.last_used);
+        self.total_bytes -= entry.size;
+        if let Some(variants) = self.variants.get_mut(&entry.base) {
+            variants.retain(|variant| variant != key);
+            if variants.is_empty() {
+                self.variants.remove(&entry.base);
+                self.vary.remove(&entry.base);
+            }
+        }
+    }
+}
+
+fn append_header_values(key: &mut String, headers: &HeaderMap, name: &HeaderName) {
+    key.push('\n');
+    key.push_str(name.as_str());
+    key.push('=');
+    for value in headers.get_all(name) {
+        key.push_str(value.to_str().unwrap_or_default());
+        key.push(',');
+    }
+}
+
+fn directives(headers: &HeaderMap) -> impl Iterator<Item = (&str, Option<&str>)> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (directive.trim(), None),
+        })
+}
+
+fn has_directive(headers: &HeaderMap, wanted: &[&str]) -> bool {
+    directives(headers).any(|(name, _)| wanted.iter().any(|w| name.eq_ignore_ascii_case(w)))
+}
+
+/// Seconds of a directive like `stale-if-error=60`, zero when absent or invalid.
+fn directive_seconds(headers: &HeaderMap, wanted: &str) -> Duration {
+    directives(headers)
+        .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+        .and_then(|(_, value)| value?.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_default()
+}
+
+fn is_cacheable(response: &Response<ResponseBody>, max_entry_bytes: usize) -> bool {
+    response.status() == StatusCode::OK
+        && !response.headers().contains_key(SET_COOKIE)
+        // `Vary: *` means no request can be told apart safely
+        && !response
+            .headers()
+            .get_all(VARY)
+            .iter()
+            .any(|value| value.to_str().is_ok_and(|value| value.split(',').any(|name| name.trim() == "*")))
+        && !has_directive(response.headers(), &["no-store", "private", "no-cache"])
+        // Only bodies with a known small length are buffered, others stream through untouched
+        && response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse::<usize>().ok())
+            .is_some_and(|len| len <= max_entry_bytes)
+}
+
+/// Caches the response when allowed, returns it to the caller along with whether it was stored.
+async fn store_response(
+    store: &Mutex<ResponseCache>,
+    max_entry_bytes: usize,
+    key: &RequestKey,
+    response: Response<ResponseBody>,
+) -> (Response<ResponseBody>, bool) {
+    if !is_cacheable(&response, max_entry_bytes) {
+        return (response, false);
+    }
+    let (parts, body) = response.into_parts();
+    let body = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(err) => {
+            tracing::warn!("Error reading upstream response body for cache: {err}");
+            return (bad_gateway_response(), false);
+        }
+    };
+    let stored =
+        store
+            .lock()
+            .unwrap()
+            .insert(key, parts.status, parts.headers.clone(), body.clone());
+    let response = Response::from_parts(
+        parts,
+        Full::new(body).map_err(|never| match never {}).boxed(),
+    );
+    (response, stored)
+}
+
+pub struct Cache {
+    ttl: Duration,
+    max_entry_bytes: usize,
+    vary_headers: Box<[HeaderName]>,
+    vary_cookies: Box<[String]>,
+    store: Arc<Mutex<ResponseCache>>,
+}
+
+impl Cache {
+    fn key(&self, req: &Request<RequestBody>) -> RequestKey {
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| req.uri().host())
+            .unwrap_or_default();
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+
+        let mut variant = String::new();
+        for name in &self.vary_headers {
+            append_header_values(&mut variant, req.headers(), name);
+        }
+        let cookies = req
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .collect::<Vec<_>>();
+        for name in &self.vary_cookies {
+            variant.push_str("\ncookie:");
+            variant.push_str(name);
+            variant.push('=');
+            if let Some((_, value)) = cookies.iter().find(|(cookie, _)| cookie == name) {
+                variant.push_str(value);
+            }
+        }
+
+        RequestKey {
+            base: format!("{host}{path}"),
+            variant,
+            headers: req.headers().clone(),
+        }
+    }
+
+    /// Refreshes a stale entry off the request path, cached `GET` requests carry no body.
+    fn revalidate(&self, key: RequestKey, req: &Request<RequestBody>, next: &Next<'_>) {
+        let mut refresh = Request::new(
+            Empty::<Bytes>::new()
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        *refresh.method_mut() = req.method().clone();
+        *refresh.uri_mut() = req.uri().clone();
+        *refresh.version_mut() = req.version();
+        *refresh.headers_mut() = req.headers().clone();
+
+        let next = next.to_owned();
+        let store = self.store.clone();
+        let max_entry_bytes = self.max_entry_bytes;
+        tokio::spawn(async move {
+            let stored = match next.run(refresh).await {
+                Ok(response) => {
+                    store_response(&store, max_entry_bytes, &key, response)
+                        .await
+                        .1
+                }
+                Err(never) => match never {},
+            };
+            if !stored {
+                store.lock().unwrap().finish_revalidation(&key);
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Middleware for Cache {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        if req.method() != Method::GET
+            || req.headers().contains_key(AUTHORIZATION)
+            || has_directive(req.headers(), &["no-store", "no-cache"])
+        {
+            return next.run(req).await;
+        }
+
+        let key = self.key(&req);
+        let lookup = self.store.lock().unwrap().lookup(&key, self.ttl);
+        let stale_if_error = match lookup {
+            Lookup::Fresh(response) => return Ok(response),
+            Lookup::Stale {
+                response,
+                revalidate,
+            } => {
+                if revalidate {
+                    self.revalidate(key, &req, &next);
+                }
+                return Ok(response);
+            }
+            Lookup::StaleIfError(response) => Some(response),
+            Lookup::Miss => None,
+        };
+
+        let response = next.run(req).await?;
+        if let Some(stale) = stale_if_error
+            && response.status().is_server_error()
+        {
+            return Ok(stale);
+        }
+        Ok(
+            store_response(&self.store, self.max_entry_bytes, &key, response)
+                .await
+                .0,
+        )
+    }
+}
+
+/// Keeps one store per cache middleware so entries survive across requests and reloads that
+/// leave its config unchanged.
+pub struct CacheFactory {
+    stores: NamedStates<CacheConfig, Mutex<ResponseCache>>,
+}
+
+impl CacheFactory {
+    pub fn new() -> Self {
+        CacheFactory {
+            stores: NamedStates::new(),
+        }
+    }
+
+    fn store_for(&self, name: &str, config: &CacheConfig) -> Arc<Mutex<ResponseCache>> {
+        self.stores.get_or_insert_with(name, config, || {
+            Mutex::new(ResponseCache::new(
+                config.max_bytes,
+                config.max_entry_bytes,
+                config.max_variants,
+            ))
+        })
+    }
+}
+
+impl MiddlewareFactory for CacheFactory {
+    fn create(
+        &self,
+        name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::Cache(cfg)) => Ok(Arc::new(Cache {
+                ttl: cfg.ttl,
+                max_entry_bytes: cfg.max_entry_bytes,
+                // Validated on load
+                vary_headers: cfg
+                    .vary_by
+                    .headers
+                    .iter()
+                    .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+                    .collect(),
+                vary_cookies: cfg.vary_by.cookies.clone().into_boxed_slice(),
+                store: self.store_for(name, &cfg),
+            })),
+            _ => Err(String::from("Invalid config for cache middleware")),
+        }
+    }
+
+    fn retain(&self, middlewares: &HashMap<String, MiddlewareConfig>) {
+        self.stores.retain(middlewares);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CacheVaryConfig;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::Empty;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn request_key(base: &str) -> RequestKey {
+        RequestKey {
+            base: base.to_string(),
+            variant: String::new(),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    fn insert(cache: &mut ResponseCache, key: &str, body_len: usize) -> bool {
+        cache.insert(
+            &request_key(key),
+            StatusCode::OK,
+            HeaderMap::new(),
+            Bytes::from(vec![b'a'; body_len]),
+        )
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used_by_size() {
+        // Sizes include the two byte keys
+        let mut cache = ResponseCache::new(300, 200, 8);
+        assert!(insert(&mut cache, "a", 98));
+        assert!(insert(&mut cache, "b", 98));
+        assert!(insert(&mut cache, "c", 98));
+        assert_eq!(cache.total_bytes, 300);
+
+        // Touching `a` makes `b` the least recently used
+        assert!(matches!(
+            cache.lookup(&request_key("a"), Duration::from_secs(60)),
+            Lookup::Fresh(_)
+        ));
+        assert!(insert(&mut cache, "d", 148));
+        assert!(cache.total_bytes <= 300);
+        assert!(!cache.entries.contains_key("b\n"));
+        assert!(!cache.entries.contains_key("c\n"));
+        assert!(cache.entries.contains_key("a\n"));
+        assert!(cache.entries.contains_key("d\n"));
+        assert_eq!(cache.total_bytes, 250);
+    }
+
+    #[test]
+    fn test_skips_entries_over_the_entry_cap() {
+        let mut cache = ResponseCache::new(300, 200, 8);
+        assert!(insert(&mut cache, "a", 98));
+        assert!(!insert(&mut cache, "big", 250));
+        assert!(cache.entries.contains_key("a\n"));
+        assert_eq!(cache.total_bytes, 100);
+    }
+
+    #[test]
+    fn test_replacing_an_entry_updates_the_total() {
+        let mut cache = ResponseCache::new(300, 200, 8);
+        assert!(insert(&mut cache, "a", 98));
+        assert!(insert(&mut cache, "a", 48));
+        assert_eq!(cache.total_bytes, 50);
+        assert_eq!(cache.lru.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_serves_repeated_get_from_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler: HandlerFunc = {
+            let calls = calls.clone();
+            Arc::new(move |_req| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async {
+                    Ok(Response::builder()
+                        .header(CONTENT_LENGTH, 5)
+                        .body(
+                            Full::new(Bytes::from_static(b"hello"))
+                                .map_err(|never| match never {})
+                                .boxed(),
+                        )
+                        .unwrap())
+                })
+            })
+        };
+        let config = MiddlewareConfig::Cache(CacheConfig {
+            ttl: Duration::from_secs(60),
+            max_bytes: 1024,
+            max_entry_bytes: 512,
+            vary_by: CacheVaryConfig::default(),
+            max_variants: 8,
+        });
+        let middlewares = [CacheFactory::new().create("cache", Some(config)).unwrap()];
+
+        for _ in 0..2 {
+            let req = Request::builder()
+                .uri("/cached")
+                .header(HOST, "localhost")
+                .body(
+                    Empty::<Bytes>::new()
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap();
+            let response = Next::new(handler.clone(), &middlewares)
+                .run(req)
+                .await
+                .unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(&body[..], b"hello");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    /// Answers with the call count as the body, slowly so concurrent stale hits overlap.
+    fn counting_handler(calls: Arc<AtomicUsize>, cache_control: &'static str) -> HandlerFunc {
+        Arc::new(move |_req| {
+            let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                let status = if call == 1 {
+                    StatusCode::OK
+                } else if cache_control.contains("stale-if-error") {
+                    StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    StatusCode::OK
+                };
+                Ok(Response::builder()
+                    .status(status)
+                    .header(CONTENT_LENGTH, 2)
+                    .header(CACHE_CONTROL, cache_control)
+                    .body(
+                        Full::new(Bytes::from(format!("v{call}")))
+                            .map_err(|never| match never {})
+                            .boxed(),
+                    )
+                    .unwrap())
+            })
+        })
+    }
+
+    async fn get(
+        handler: &HandlerFunc,
+        middlewares: &[Arc<dyn Middleware>],
+    ) -> (StatusCode, Bytes) {
+        let req = Request::builder()
+            .uri("/stale")
+            .header(HOST, "localhost")
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        let response = Next::new(handler.clone(), middlewares)
+            .run(req)
+            .await
+            .unwrap();
+        let status = response.status();
+        (
+            status,
+            response.into_body().collect().await.unwrap().to_bytes(),
+        )
+    }
+
+    fn short_lived_cache() -> [Arc<dyn Middleware>; 1] {
+        let config = MiddlewareConfig::Cache(CacheConfig {
+            ttl: Duration::from_millis(30),
+            max_bytes: 1024,
+            max_entry_bytes: 512,
+            vary_by: CacheVaryConfig::default(),
+            max_variants: 8,
+        });
+        [CacheFactory::new().create("cache", Some(config)).unwrap()]
+    }
+
+    #[tokio::test]
+    async fn test_stores_are_kept_per_middleware_name() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = counting_handler(calls.clone(), "max-age=60");
+        let config = MiddlewareConfig::Cache(CacheConfig {
+            ttl: Duration::from_secs(60),
+            max_bytes: 1024,
+            max_entry_bytes: 512,
+            vary_by: CacheVaryConfig::default(),
+            max_variants: 8,
+        });
+        let factory = CacheFactory::new();
+        let chain = |name| [factory.create(name, Some(config.clone())).unwrap()];
+
+        assert_eq!(get(&handler, &chain("pages")).await.1, "v1");
+        // Chains of the same middleware share its entries
+        assert_eq!(get(&handler, &chain("pages")).await.1, "v1");
+        // An identically configured middleware keeps its own
+        assert_eq!(get(&handler, &chain("assets")).await.1, "v2");
+
+        // Entries of a middleware a reload removed are dropped
+        factory.retain(&HashMap::from([(String::from("assets"), config.clone())]));
+        assert_eq!(get(&handler, &chain("assets")).await.1, "v2");
+        assert_eq!(get(&handler, &chain("pages")).await.1, "v3");
+    }
+
+    #[tokio::test]
+    async fn test_serves_stale_while_revalidating_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = counting_handler(calls.clone(), "stale-while-revalidate=60");
+        let middlewares = short_lived_cache();
+
+        assert_eq!(get(&handler, &middlewares).await.1, "v1");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Both stale hits are answered from the cache, only one refresh goes upstream
+        assert_eq!(get(&handler, &middlewares).await.1, "v1");
+        assert_eq!(get(&handler, &middlewares).await.1, "v1");
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        assert_eq!(get(&handler, &middlewares).await.1, "v2");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_serves_stale_if_upstream_fails() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = counting_handler(calls.clone(), "stale-if-error=60");
+        let middlewares = short_lived_cache();
+
+        assert_eq!(get(&handler, &middlewares).await.1, "v1");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let (status, body) = get(&handler, &middlewares).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "v1");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_vary_headers_select_separate_entries() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler: HandlerFunc = {
+            let calls = calls.clone();
+            Arc::new(move |req: Request<RequestBody>| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                let language = req.headers()["accept-language"].clone();
+                Box::pin(async move {
+                    Ok(Response::builder()
+                        .header(CONTENT_LENGTH, language.len())
+                        .header(VARY, "Accept-Language")
+                        .body(
+                            Full::new(Bytes::copy_from_slice(language.as_bytes()))
+                                .map_err(|never| match never {})
+                                .boxed(),
+                        )
+                        .unwrap())
+                })
+            })
+        };
+        let config = MiddlewareConfig::Cache(CacheConfig {
+            ttl: Duration::from_secs(60),
+            max_bytes: 4096,
+            max_entry_bytes: 512,
+            vary_by: CacheVaryConfig {
+                headers: Vec::new(),
+                cookies: vec![String::from("region")],
+            },
+            max_variants: 2,
+        });
+        let middlewares = [CacheFactory::new().create("cache", Some(config)).unwrap()];
+        let get = async |language: &str, cookie: &str| {
+            let req = Request::builder()
+                .uri("/greeting")
+                .header(HOST, "localhost")
+                .header("accept-language", language)
+                .header(COOKIE, cookie)
+                .body(
+                    Empty::<Bytes>::new()
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap();
+            let response = Next::new(handler.clone(), &middlewares)
+                .run(req)
+                .await
+                .unwrap();
+            response.into_body().collect().await.unwrap().to_bytes()
+        };
+
+        assert_eq!(get("en", "region=eu").await, "en");
+        assert_eq!(get("de", "region=eu").await, "de");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(get("en", "region=eu").await, "en");
+        assert_eq!(get("de", "region=eu").await, "de");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // The configured cookie splits entries too, and the third variant evicts the oldest
+        assert_eq!(get("en", "theme=dark; region=us").await, "en");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(get("en", "region=eu").await, "en");
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+}
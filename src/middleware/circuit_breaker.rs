@@ -0,0 +1,403 @@
+use crate::config::{CircuitBreakerConfig, MiddlewareConfig};
+use crate::middleware::registry::{MiddlewareFactory, NamedStates};
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::utils::response_with_status;
+use async_trait::async_trait;
+use hyper::{Request, Response, StatusCode};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How many of the latest trips are kept per circuit.
+const TRIP_HISTORY_CAPACITY: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CircuitState {
+    Closed,
+    Open {
+        until: Instant,
+    },
+    /// A single trial request decides, another one is let through if it never completes
+    HalfOpen {
+        trial_since: Instant,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitStatus {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Trip {
+    #[serde(with = "humantime_serde")]
+    pub timestamp: SystemTime,
+    /// Failed and total responses of the window that opened the circuit, 1 of 1 for a failed trial
+    pub failures: u32,
+    pub requests: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitSnapshot {
+    pub state: CircuitStatus,
+    /// Time left until the trial request, only while open
+    #[serde(with = "humantime_serde")]
+    pub half_open_in: Option<Duration>,
+    /// Failed and total responses of the current window
+    pub failures: u32,
+    pub requests: u32,
+    /// Oldest first
+    pub trips: Vec<Trip>,
+}
+
+struct Circuit {
+    state: CircuitState,
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+    trips: VecDeque<Trip>,
+}
+
+impl Circuit {
+    fn new() -> Self {
+        Circuit {
+            state: CircuitState::Closed,
+            window_start: Instant::now(),
+            requests: 0,
+            failures: 0,
+            trips: VecDeque::new(),
+        }
+    }
+
+    /// Whether a request may go through and if so whether it is the trial request.
+    fn try_acquire(&mut self, config: &CircuitBreakerConfig, now: Instant) -> Option<bool> {
+        match self.state {
+            CircuitState::Closed => Some(false),
+            CircuitState::Open { until } if now < until => None,
+            CircuitState::HalfOpen { trial_since } if now < trial_since + config.cooldown => None,
+            CircuitState::Open { .. } | CircuitState::HalfOpen { .. } => {
+                self.state = CircuitState::HalfOpen { trial_since: now };
+                Some(true)
+            }
+        }
+    }
+
+    fn record(&mut self, config: &CircuitBreakerConfig, trial: bool, failed: bool, now: Instant) {
+        if trial {
+            if failed {
+                self.trip(config, 1, 1, now);
+            } else {
+                self.state = CircuitState::Closed;
+                self.reset_window(now);
+            }
+            return;
+        }
+        // Requests let through before the circuit opened may still complete
+        if self.state != CircuitState::Closed {
+            return;
+        }
+        if now.duration_since(self.window_start) >= config.window {
+            self.reset_window(now);
+        }
+        self.requests += 1;
+        if failed {
+            self.failures += 1;
+        }
+        if self.requests >= config.min_requests
+            && f64::from(self.failures) >= config.failure_threshold * f64::from(self.requests)
+        {
+            let (failures, requests) = (self.failures, self.requests);
+            self.trip(config, failures, requests, now);
+        }
+    }
+
+    fn trip(&mut self, config: &CircuitBreakerConfig, failures: u32, requests: u32, now: Instant) {
+        tracing::warn!(
+            "Opening circuit for {:?} after {failures} failed of {requests} responses",
+            config.cooldown
+        );
+        self.state = CircuitState::Open {
+            until: now + config.cooldown,
+        };
+        self.reset_window(now);
+        if self.trips.len() == TRIP_HISTORY_CAPACITY {
+            self.trips.pop_front();
+        }
+        self.trips.push_back(Trip {
+            timestamp: SystemTime::now(),
+            failures,
+            requests,
+        });
+    }
+
+    fn reset_window(&mut self, now: Instant) {
+        self.window_start = now;
+        self.requests = 0;
+        self.failures = 0;
+    }
+
+    fn snapshot(&self, now: Instant) -> CircuitSnapshot {
+        let (state, half_open_in) = match self.state {
+            CircuitState::Closed => (CircuitStatus::Closed, None),
+            CircuitState::Open { until } if now < until => {
+                (CircuitStatus::Open, Some(until.duration_since(now)))
+            }
+            // Due for its trial request
+            CircuitState::Open { .. } => (CircuitStatus::Open, Some(Duration::ZERO)),
+            CircuitState::HalfOpen { .. } => (CircuitStatus::HalfOpen, None),
+        };
+        CircuitSnapshot {
+            state,
+            half_open_in,
+            failures: self.failures,
+            requests: self.requests,
+            trips: self.trips.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Circuits by middleware name, so every chain built from a circuit breaker middleware shares
+/// its state.
+pub struct Circuits {
+    circuits: NamedStates<CircuitBreakerConfig, Mutex<Circuit>>,
+}
+
+impl Circuits {
+    pub fn new() -> Self {
+        Circuits {
+            circuits: NamedStates::new(),
+        }
+    }
+
+    fn circuit_for(&self, name: &str, config: &CircuitBreakerConfig) -> Arc<Mutex<Circuit>> {
+        self.circuits
+            .get_or_insert_with(name, config, || Mutex::new(Circuit::new()))
+    }
+
+    /// State of the circuit of `name`, closed when no request went through it yet.
+    pub fn snapshot(&self, name: &str, config: &CircuitBreakerConfig) -> CircuitSnapshot {
+        let now = Instant::now();
+        match self.circuits.get(name, config) {
+            Some(circuit) => circuit.lock().unwrap().snapshot(now),
+            None => Circuit::new().snapshot(now),
+        }
+    }
+}
+
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    circuit: Arc<Mutex<Circuit>>,
+}
+
+#[async_trait]
+impl Middleware for CircuitBreaker {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let acquired = self
+            .circuit
+            .lock()
+            .unwrap()
+            .try_acquire(&self.config, Instant::now());
+        let Some(trial) = acquired else {
+            return Ok(response_with_status(StatusCode::SERVICE_UNAVAILABLE));
+        };
+        let response = next.run(req).await?;
+        self.circuit.lock().unwrap().record(
+            &self.config,
+            trial,
+            response.status().is_server_error(),
+            Instant::now(),
+        );
+        Ok(response)
+    }
+}
+
+pub struct CircuitBreakerFactory {
+    circuits: Arc<Circuits>,
+}
+
+impl CircuitBreakerFactory {
+    pub fn new(circuits: Arc<Circuits>) -> Self {
+        CircuitBreakerFactory { circuits }
+    }
+}
+
+impl MiddlewareFactory for CircuitBreakerFactory {
+    fn create(
+        &self,
+        name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::CircuitBreaker(cfg)) => Ok(Arc::new(CircuitBreaker {
+                circuit: self.circuits.circuit_for(name, &cfg),
+                config: cfg,
+            })),
+            _ => Err(String::from(
+                "Invalid config for circuit breaker middleware",
+            )),
+        }
+    }
+
+    fn retain(&self, middlewares: &HashMap<String, MiddlewareConfig>) {
+        self.circuits.circuits.retain(middlewares);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::Bytes;
+    use std::sync::atomic::{AtomicU16, Ordering};
+
+    fn config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            failure_threshold: 0.5,
+            window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+            min_requests: 4,
+        }
+    }
+
+    #[test]
+    fn test_circuit_transitions() {
+        let config = config();
+        let mut circuit = Circuit::new();
+        let start = Instant::now();
+
+        // One failure short of the threshold keeps it closed
+        for failed in [false, false, true] {
+            assert_eq!(circuit.try_acquire(&config, start), Some(false));
+            circuit.record(&config, false, failed, start);
+        }
+        assert_eq!(circuit.state, CircuitState::Closed);
+        circuit.record(&config, false, true, start);
+        assert_eq!(
+            circuit.state,
+            CircuitState::Open {
+                until: start + config.cooldown
+            }
+        );
+        assert_eq!(
+            circuit.try_acquire(&config, start + Duration::from_secs(29)),
+            None
+        );
+
+        // A single trial request once the cooldown passed
+        let trial_at = start + config.cooldown;
+        assert_eq!(circuit.try_acquire(&config, trial_at), Some(true));
+        assert_eq!(
+            circuit.state,
+            CircuitState::HalfOpen {
+                trial_since: trial_at
+            }
+        );
+        assert_eq!(circuit.try_acquire(&config, trial_at), None);
+
+        // A failed trial opens it again
+        circuit.record(&config, true, true, trial_at);
+        assert_eq!(
+            circuit.state,
+            CircuitState::Open {
+                until: trial_at + config.cooldown
+            }
+        );
+
+        let trial_at = trial_at + config.cooldown;
+        assert_eq!(circuit.try_acquire(&config, trial_at), Some(true));
+        circuit.record(&config, true, false, trial_at);
+        assert_eq!(circuit.state, CircuitState::Closed);
+        assert_eq!(circuit.try_acquire(&config, trial_at), Some(false));
+        assert_eq!(circuit.trips.len(), 2);
+    }
+
+    #[test]
+    fn test_failures_of_expired_window_are_forgotten() {
+        let config = config();
+        let mut circuit = Circuit::new();
+        let start = Instant::now();
+        for _ in 0..3 {
+            circuit.record(&config, false, true, start);
+        }
+        circuit.record(&config, false, true, start + config.window);
+        assert_eq!(circuit.state, CircuitState::Closed);
+        assert_eq!((circuit.failures, circuit.requests), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_short_circuits_with_503() {
+        let status = Arc::new(AtomicU16::new(500));
+        let upstream_status = status.clone();
+        let handler: HandlerFunc = Arc::new(move |_req| {
+            let status = StatusCode::from_u16(upstream_status.load(Ordering::Relaxed)).unwrap();
+            Box::pin(async move { Ok(response_with_status(status)) })
+        });
+        // Chains built from the same middleware share one circuit, an identically configured
+        // middleware has its own
+        let factory = CircuitBreakerFactory::new(Arc::new(Circuits::new()));
+        let chains = ["orders-breaker", "orders-breaker", "billing-breaker"].map(|name| {
+            [factory
+                .create(name, Some(MiddlewareConfig::CircuitBreaker(config())))
+                .unwrap()]
+        });
+        let send = |chain: usize| {
+            let req = Request::builder()
+                .body(
+                    Empty::<Bytes>::new()
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap();
+            Next::new(handler.clone(), &chains[chain]).run(req)
+        };
+
+        for i in 0..4 {
+            let response = send(i % 2).await.unwrap();
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        status.store(200, Ordering::Relaxed);
+        for chain in [0, 1] {
+            let response = send(chain).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+        assert_eq!(send(2).await.unwrap().status(), StatusCode::OK);
+        let snapshot = factory.circuits.snapshot("orders-breaker", &config());
+        assert_eq!(snapshot.state, CircuitStatus::Open);
+        assert_eq!(snapshot.trips.len(), 1);
+        let snapshot = factory.circuits.snapshot("billing-breaker", &config());
+        assert_eq!(snapshot.state, CircuitStatus::Closed);
+        assert_eq!(snapshot.requests, 1);
+    }
+
+    #[test]
+    fn test_circuits_are_dropped_once_unconfigured() {
+        let factory = CircuitBreakerFactory::new(Arc::new(Circuits::new()));
+        let circuits = &factory.circuits.circuits;
+
+        // Reading the state of an unused breaker leaves no circuit behind
+        let snapshot = factory.circuits.snapshot("orders-breaker", &config());
+        assert_eq!(snapshot.state, CircuitStatus::Closed);
+        assert!(circuits.get("orders-breaker", &config()).is_none());
+
+        for name in ["orders-breaker", "billing-breaker"] {
+            factory
+                .create(name, Some(MiddlewareConfig::CircuitBreaker(config())))
+                .unwrap();
+        }
+        factory.retain(&HashMap::from([(
+            String::from("billing-breaker"),
+            MiddlewareConfig::CircuitBreaker(config()),
+        )]));
+        assert!(circuits.get("orders-breaker", &config()).is_none());
+        assert!(circuits.get("billing-breaker", &config()).is_some());
+    }
+}
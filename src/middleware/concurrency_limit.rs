@@ -0,0 +1,246 @@
+use crate::config::{ConcurrencyLimitConfig, MiddlewareConfig};
+use crate::middleware::registry::{MiddlewareFactory, NamedStates};
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::utils::{GuardedBody, response_with_status};
+use async_trait::async_trait;
+use http_body_util::BodyExt;
+use hyper::{Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::Semaphore;
+
+/// Slots of one concurrency limit, shared by every chain built from its middleware.
+struct Limiter {
+    slots: Arc<Semaphore>,
+    queued: AtomicUsize,
+}
+
+/// Place in the queue, given up when the wait ends or the request is abandoned.
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl<'a> QueueSlot<'a> {
+    fn take(queued: &'a AtomicUsize, depth: usize) -> Option<Self> {
+        queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+                (queued < depth).then_some(queued + 1)
+            })
+            .ok()
+            .map(|_| QueueSlot(queued))
+    }
+}
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+pub struct ConcurrencyLimit {
+    config: ConcurrencyLimitConfig,
+    limiter: Arc<Limiter>,
+}
+
+#[async_trait]
+impl Middleware for ConcurrencyLimit {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let permit = match self.limiter.slots.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let Some(_queue_slot) =
+                    QueueSlot::take(&self.limiter.queued, self.config.queue_depth)
+                else {
+                    tracing::warn!("Concurrency limit reached with a full queue, shedding request");
+                    return Ok(response_with_status(StatusCode::SERVICE_UNAVAILABLE));
+                };
+                let acquire = self.limiter.slots.clone().acquire_owned();
+                match tokio::time::timeout(self.config.max_wait, acquire).await {
+                    Ok(Ok(permit)) => permit,
+                    _ => {
+                        tracing::warn!(
+                            "No concurrency slot freed up within {:?}, shedding request",
+                            self.config.max_wait
+                        );
+                        return Ok(response_with_status(StatusCode::SERVICE_UNAVAILABLE));
+                    }
+                }
+            }
+        };
+        let (parts, body) = next.run(req).await?.into_parts();
+        // The slot is held until the response body is fully sent or dropped
+        let body = GuardedBody::new(body, permit);
+        Ok(Response::from_parts(parts, body.boxed()))
+    }
+}
+
+pub struct ConcurrencyLimitFactory {
+    limiters: NamedStates<ConcurrencyLimitConfig, Limiter>,
+}
+
+impl ConcurrencyLimitFactory {
+    pub fn new() -> Self {
+        ConcurrencyLimitFactory {
+            limiters: NamedStates::new(),
+        }
+    }
+
+    fn limiter_for(&self, name: &str, config: &ConcurrencyLimitConfig) -> Arc<Limiter> {
+        self.limiters.get_or_insert_with(name, config, || Limiter {
+            slots: Arc::new(Semaphore::new(config.max_concurrent)),
+            queued: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl MiddlewareFactory for ConcurrencyLimitFactory {
+    fn create(
+        &self,
+        name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::ConcurrencyLimit(cfg)) => Ok(Arc::new(ConcurrencyLimit {
+                limiter: self.limiter_for(name, &cfg),
+                config: cfg,
+            })),
+            _ => Err(String::from(
+                "Invalid config for concurrency limit middleware",
+            )),
+        }
+    }
+
+    fn retain(&self, middlewares: &HashMap<String, MiddlewareConfig>) {
+        self.limiters.retain(middlewares);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::Empty;
+    use hyper::body::Bytes;
+    use std::time::Duration;
+    use tokio::task::JoinSet;
+
+    /// Statuses of `requests` sent at once through a fresh limit to a handler taking `latency`.
+    async fn burst(
+        config: ConcurrencyLimitConfig,
+        requests: usize,
+        latency: Duration,
+    ) -> Vec<StatusCode> {
+        let middleware = ConcurrencyLimitFactory::new()
+            .create(
+                "concurrency-limit",
+                Some(MiddlewareConfig::ConcurrencyLimit(config)),
+            )
+            .unwrap();
+        let handler: HandlerFunc = Arc::new(move |_req| {
+            Box::pin(async move {
+                tokio::time::sleep(latency).await;
+                Ok(response_with_status(StatusCode::OK))
+            })
+        });
+
+        let mut requests_in_flight = JoinSet::new();
+        for _ in 0..requests {
+            let middlewares = [middleware.clone()];
+            let handler = handler.clone();
+            requests_in_flight.spawn(async move {
+                let req = Request::new(
+                    Empty::<Bytes>::new()
+                        .map_err(|never| match never {})
+                        .boxed(),
+                );
+                let response = Next::new(handler, &middlewares).run(req).await.unwrap();
+                response.status()
+            });
+        }
+        requests_in_flight.join_all().await
+    }
+
+    /// Response of a single request through `middleware`, its body left unread.
+    async fn send(middleware: &Arc<dyn Middleware>) -> Response<ResponseBody> {
+        let handler: HandlerFunc =
+            Arc::new(|_req| Box::pin(async { Ok(response_with_status(StatusCode::OK)) }));
+        let req = Request::new(
+            Empty::<Bytes>::new()
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        Next::new(handler, std::slice::from_ref(middleware))
+            .run(req)
+            .await
+            .unwrap()
+    }
+
+    fn count(statuses: &[StatusCode], status: StatusCode) -> usize {
+        statuses.iter().filter(|&&s| s == status).count()
+    }
+
+    #[tokio::test]
+    async fn test_burst_within_capacity_and_queue_is_served() {
+        let config = ConcurrencyLimitConfig {
+            max_concurrent: 2,
+            queue_depth: 2,
+            max_wait: Duration::from_secs(1),
+        };
+        let statuses = burst(config, 4, Duration::from_millis(50)).await;
+        assert_eq!(count(&statuses, StatusCode::OK), 4);
+    }
+
+    #[tokio::test]
+    async fn test_sustained_overload_is_shed() {
+        let config = ConcurrencyLimitConfig {
+            max_concurrent: 2,
+            queue_depth: 2,
+            max_wait: Duration::from_millis(50),
+        };
+        // Queued requests time out, the ones beyond the queue are shed right away
+        let statuses = burst(config, 6, Duration::from_millis(300)).await;
+        assert_eq!(count(&statuses, StatusCode::OK), 2);
+        assert_eq!(count(&statuses, StatusCode::SERVICE_UNAVAILABLE), 4);
+    }
+
+    #[tokio::test]
+    async fn test_slot_is_held_until_the_body_is_done() {
+        let config = MiddlewareConfig::ConcurrencyLimit(ConcurrencyLimitConfig {
+            max_concurrent: 1,
+            queue_depth: 0,
+            max_wait: Duration::from_secs(1),
+        });
+        let factory = ConcurrencyLimitFactory::new();
+        let middleware = |name| factory.create(name, Some(config.clone())).unwrap();
+
+        let streaming = send(&middleware("orders")).await;
+        assert_eq!(streaming.status(), StatusCode::OK);
+        let shed = send(&middleware("orders")).await;
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+        // An identically configured middleware has its own slots
+        assert_eq!(send(&middleware("billing")).await.status(), StatusCode::OK);
+
+        streaming.into_body().collect().await.unwrap();
+        assert_eq!(send(&middleware("orders")).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_limits_are_dropped_once_unconfigured() {
+        let config = MiddlewareConfig::ConcurrencyLimit(ConcurrencyLimitConfig {
+            max_concurrent: 1,
+            queue_depth: 0,
+            max_wait: Duration::from_secs(1),
+        });
+        let factory = ConcurrencyLimitFactory::new();
+        let held = send(&factory.create("orders", Some(config.clone())).unwrap()).await;
+
+        // A reload removing the middleware starts it over with free slots
+        factory.retain(&HashMap::from([(String::from("billing"), config.clone())]));
+        let middleware = factory.create("orders", Some(config)).unwrap();
+        assert_eq!(send(&middleware).await.status(), StatusCode::OK);
+        drop(held);
+    }
+}
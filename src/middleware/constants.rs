@@ -2,3 +2,20 @@ pub const REQUEST_ID_MIDDLEWARE: &str = "request_id";
 pub const ACCESS_LOGGER_MIDDLEWARE: &str = "access_logger";
 pub const ADD_PREFIX_MIDDLEWARE: &str = "add_prefix";
 pub const RATE_LIMIT_MIDDLEWARE: &str = "rate_limit";
+pub const WHEN_MIDDLEWARE: &str = "when";
+pub const STATUS_REMAP_MIDDLEWARE: &str = "status_remap";
+pub const CORS_MIDDLEWARE: &str = "cors";
+pub const REQUEST_DECOMPRESS_MIDDLEWARE: &str = "request_decompress";
+pub const RETRY_MIDDLEWARE: &str = "retry";
+pub const CONTENT_TYPE_MIDDLEWARE: &str = "content_type";
+pub const CACHE_MIDDLEWARE: &str = "cache";
+pub const MIRROR_MIDDLEWARE: &str = "mirror";
+pub const CIRCUIT_BREAKER_MIDDLEWARE: &str = "circuit_breaker";
+pub const CONCURRENCY_LIMIT_MIDDLEWARE: &str = "concurrency_limit";
+pub const QUERY_REWRITE_MIDDLEWARE: &str = "query_rewrite";
+pub const REWRITE_MIDDLEWARE: &str = "rewrite";
+pub const HEADERS_MIDDLEWARE: &str = "headers";
+pub const BASIC_AUTH_MIDDLEWARE: &str = "basic_auth";
+pub const JWT_MIDDLEWARE: &str = "jwt";
+pub const API_KEY_MIDDLEWARE: &str = "api_key";
+pub const IP_FILTER_MIDDLEWARE: &str = "ip_filter";
@@ -0,0 +1,205 @@
+use crate::config::MiddlewareConfig;
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::utils::response_with_status;
+use async_trait::async_trait;
+use hyper::body::Body;
+use hyper::header::{ACCEPT, CONTENT_TYPE, HeaderMap};
+use hyper::{Request, Response, StatusCode};
+use std::sync::Arc;
+
+/// Lowercased `type/subtype` without parameters, `None` when it is not a media type.
+fn essence(value: &str) -> Option<String> {
+    let media_type = value.split(';').next()?.trim().to_ascii_lowercase();
+    let (main_type, sub_type) = media_type.split_once('/')?;
+    if main_type.is_empty() || sub_type.is_empty() {
+        return None;
+    }
+    Some(media_type)
+}
+
+/// Whether `pattern` which may be `*/*` or `type/*` covers `media_type`.
+fn covers(pattern: &str, media_type: &str) -> bool {
+    if pattern == "*/*" || pattern == media_type {
+        return true;
+    }
+    match (pattern.strip_suffix("/*"), media_type.split_once('/')) {
+        (Some(main_type), Some((media_main_type, _))) => main_type == media_main_type,
+        _ => false,
+    }
+}
+
+pub struct ContentType {
+    allowed_types: Box<[String]>,
+    enforce_accept: bool,
+}
+
+impl ContentType {
+    fn is_allowed(&self, media_type: &str) -> bool {
+        self.allowed_types
+            .iter()
+            .any(|allowed| covers(allowed, media_type))
+    }
+
+    /// Whether any range in `Accept` with a non zero quality overlaps an allowed type.
+    fn is_acceptable(&self, headers: &HeaderMap) -> bool {
+        let mut ranges = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter(|range| !range.trim().is_empty())
+            .peekable();
+        // No Accept header means the client accepts anything
+        if ranges.peek().is_none() {
+            return true;
+        }
+        ranges.any(|range| {
+            let rejected = range.split(';').skip(1).any(|param| {
+                param
+                    .split_once('=')
+                    .filter(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                    .and_then(|(_, quality)| quality.trim().parse::<f32>().ok())
+                    .is_some_and(|quality| quality <= 0.0)
+            });
+            !rejected
+                && essence(range).is_some_and(|range| {
+                    self.allowed_types
+                        .iter()
+                        .any(|allowed| covers(allowed, &range) || covers(&range, allowed))
+                })
+        })
+    }
+}
+
+#[async_trait]
+impl Middleware for ContentType {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let allowed = match req.headers().get(CONTENT_TYPE) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(essence)
+                .is_some_and(|media_type| self.is_allowed(&media_type)),
+            // Requests without a body have nothing to describe
+            None => req.body().size_hint().exact() == Some(0),
+        };
+        if !allowed {
+            return Ok(response_with_status(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        }
+        if self.enforce_accept && !self.is_acceptable(req.headers()) {
+            return Ok(response_with_status(StatusCode::NOT_ACCEPTABLE));
+        }
+        next.run(req).await
+    }
+}
+
+pub struct ContentTypeFactory;
+
+impl MiddlewareFactory for ContentTypeFactory {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::ContentType(cfg)) => Ok(Arc::new(ContentType {
+                allowed_types: cfg
+                    .allowed_types
+                    .iter()
+                    .map(|allowed| allowed.trim().to_ascii_lowercase())
+                    .collect(),
+                enforce_accept: cfg.enforce_accept,
+            })),
+            _ => Err(String::from("Invalid config for content_type middleware")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ContentTypeConfig;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Bytes;
+
+    fn ok_handler() -> HandlerFunc {
+        Arc::new(|_req| {
+            Box::pin(async {
+                Ok(Response::new(
+                    Empty::<Bytes>::new()
+                        .map_err(|never| match never {})
+                        .boxed(),
+                ))
+            })
+        })
+    }
+
+    async fn run(headers: &[(&str, &str)], body: &'static str) -> StatusCode {
+        let config = MiddlewareConfig::ContentType(ContentTypeConfig {
+            allowed_types: vec![String::from("application/json"), String::from("text/*")],
+            enforce_accept: true,
+        });
+        let middlewares = [ContentTypeFactory
+            .create("content-type", Some(config))
+            .unwrap()];
+        let mut builder = Request::builder().method("POST").uri("/");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let req = builder
+            .body(
+                Full::new(Bytes::from_static(body.as_bytes()))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        Next::new(ok_handler(), &middlewares)
+            .run(req)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_allowed_content_type_passes() {
+        for content_type in [
+            "application/json",
+            "Application/JSON; charset=utf-8",
+            "text/csv",
+        ] {
+            let status = run(&[("content-type", content_type)], "{}").await;
+            assert_eq!(status, StatusCode::OK, "{content_type}");
+        }
+        assert_eq!(run(&[], "").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_content_type_is_rejected() {
+        for content_type in ["application/xml", "json", ""] {
+            let status = run(&[("content-type", content_type)], "<a/>").await;
+            assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE, "{content_type}");
+        }
+        assert_eq!(run(&[], "{}").await, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_unsatisfiable_accept_is_rejected() {
+        for accept in ["application/*", "*/*", "text/html, application/xml;q=0.9"] {
+            assert_eq!(
+                run(&[("accept", accept)], "").await,
+                StatusCode::OK,
+                "{accept}"
+            );
+        }
+        for accept in ["application/xml", "image/*", "application/json;q=0"] {
+            let status = run(&[("accept", accept)], "").await;
+            assert_eq!(status, StatusCode::NOT_ACCEPTABLE, "{accept}");
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use crate::config::MiddlewareConfig;
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::utils::response_with_status;
+use async_trait::async_trait;
+use hyper::header::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, HeaderMap, HeaderValue, ORIGIN, VARY,
+};
+use hyper::{Method, Request, Response, StatusCode};
+use std::sync::Arc;
+
+enum OriginPattern {
+    Any,
+    Exact(String),
+    /// `https://*.example.com` stored as the scheme prefix and the `.example.com` suffix
+    Subdomain {
+        scheme: String,
+        suffix: String,
+    },
+}
+
+impl OriginPattern {
+    fn parse(pattern: &str) -> Self {
+        if pattern == "*" {
+            return OriginPattern::Any;
+        }
+        match pattern.split_once("://*.") {
+            Some((scheme, domain)) => OriginPattern::Subdomain {
+                scheme: format!("{scheme}://"),
+                suffix: format!(".{}", domain.to_ascii_lowercase()),
+            },
+            None => OriginPattern::Exact(pattern.to_ascii_lowercase()),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginPattern::Any => true,
+            OriginPattern::Exact(allowed) => allowed == origin,
+            OriginPattern::Subdomain { scheme, suffix } => origin
+                .strip_prefix(scheme.as_str())
+                .and_then(|host| host.strip_suffix(suffix.as_str()))
+                .is_some_and(|subdomain| !subdomain.is_empty()),
+        }
+    }
+}
+
+pub struct Cors {
+    allowed_origins: Box<[OriginPattern]>,
+    /// `Access-Control-Allow-Methods` of preflight answers
+    allowed_methods: HeaderValue,
+    /// `Access-Control-Allow-Headers` of preflight answers, `None` reflects the requested ones
+    allowed_headers: Option<HeaderValue>,
+    allow_credentials: bool,
+    max_age: Option<HeaderValue>,
+}
+
+impl Cors {
+    /// Value for `Access-Control-Allow-Origin`, `None` when the origin is not allowed.
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        let origin_str = origin.to_str().ok()?.to_ascii_lowercase();
+        let pattern = self
+            .allowed_origins
+            .iter()
+            .find(|pattern| pattern.matches(&origin_str))?;
+        match pattern {
+            OriginPattern::Any => Some(HeaderValue::from_static("*")),
+            _ => Some(origin.clone()),
+        }
+    }
+
+    fn insert_origin_headers(&self, headers: &mut HeaderMap, allow_origin: Option<HeaderValue>) {
+        match allow_origin {
+            Some(allow_origin) if allow_origin == "*" => {
+                headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+            }
+            Some(allow_origin) => {
+                headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+                if self.allow_credentials {
+                    headers.insert(
+                        ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                        HeaderValue::from_static("true"),
+                    );
+                }
+                headers.append(VARY, HeaderValue::from_static("Origin"));
+            }
+            // Caches must not reuse a response without the header for an allowed origin
+            None => {
+                headers.append(VARY, HeaderValue::from_static("Origin"));
+            }
+        }
+    }
+
+    /// Answer to a preflight request, the methods and headers are only sent to allowed origins.
+    fn preflight(
+        &self,
+        req: &Request<RequestBody>,
+        allow_origin: Option<HeaderValue>,
+    ) -> Response<ResponseBody> {
+        let mut response = response_with_status(StatusCode::NO_CONTENT);
+        let headers = response.headers_mut();
+        if allow_origin.is_some() {
+            headers.insert(ACCESS_CONTROL_ALLOW_METHODS, self.allowed_methods.clone());
+            let allowed_headers = self
+                .allowed_headers
+                .clone()
+                .or_else(|| req.headers().get(ACCESS_CONTROL_REQUEST_HEADERS).cloned());
+            if let Some(allowed_headers) = allowed_headers.filter(|headers| !headers.is_empty()) {
+                headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+            }
+            if let Some(max_age) = &self.max_age {
+                headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.clone());
+            }
+        }
+        self.insert_origin_headers(headers, allow_origin);
+        headers.append(
+            VARY,
+            HeaderValue::from_static(
+                "Access-Control-Request-Method, Access-Control-Request-Headers",
+            ),
+        );
+        response
+    }
+}
+
+fn is_preflight(req: &Request<RequestBody>) -> bool {
+    req.method() == Method::OPTIONS
+        && req.headers().contains_key(ORIGIN)
+        && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+#[async_trait]
+impl Middleware for Cors {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let allow_origin = req
+            .headers()
+            .get(ORIGIN)
+            .and_then(|origin| self.allow_origin(origin));
+        if is_preflight(&req) {
+            return Ok(self.preflight(&req, allow_origin));
+        }
+        let mut response = next.run(req).await?;
+        self.insert_origin_headers(response.headers_mut(), allow_origin);
+        Ok(response)
+    }
+}
+
+pub struct CorsFactory;
+
+impl MiddlewareFactory for CorsFactory {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::Cors(cfg)) => {
+                let methods = cfg
+                    .allowed_methods
+                    .iter()
+                    .map(|method| method.to_ascii_uppercase())
+                    .collect::<Vec<_>>();
+                let allowed_methods = HeaderValue::from_str(&methods.join(", "))
+                    .map_err(|_| String::from("Invalid allowed methods"))?;
+                let allowed_headers = if cfg.allowed_headers.iter().any(|header| header == "*") {
+                    None
+                } else {
+                    Some(
+                        HeaderValue::from_str(&cfg.allowed_headers.join(", "))
+                            .map_err(|_| String::from("Invalid allowed headers"))?,
+                    )
+                };
+                Ok(Arc::new(Cors {
+                    allowed_origins: cfg
+                        .allowed_origins
+                        .iter()
+                        .map(|origin| OriginPattern::parse(origin))
+                        .collect(),
+                    allowed_methods,
+                    allowed_headers,
+                    allow_credentials: cfg.allow_credentials,
+                    max_age: cfg
+                        .max_age
+                        .map(|max_age| HeaderValue::from(max_age.as_secs())),
+                }))
+            }
+            _ => Err(String::from("Invalid config for cors middleware")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CorsConfig;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::Bytes;
+
+    fn ok_handler() -> HandlerFunc {
+        Arc::new(|_req| {
+            Box::pin(async {
+                Ok(Response::new(
+                    Empty::<Bytes>::new()
+                        .map_err(|never| match never {})
+                        .boxed(),
+                ))
+            })
+        })
+    }
+
+    fn cors_config(allowed_origins: &[&str], allow_credentials: bool) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: allowed_origins.iter().map(|o| o.to_string()).collect(),
+            allowed_methods: vec![String::from("get"), String::from("PUT")],
+            allowed_headers: vec![String::from("content-type"), String::from("x-api-key")],
+            allow_credentials,
+            max_age: Some(std::time::Duration::from_secs(600)),
+        }
+    }
+
+    async fn run(
+        allowed_origins: &[&str],
+        allow_credentials: bool,
+        origin: &str,
+    ) -> Response<ResponseBody> {
+        let config = MiddlewareConfig::Cors(cors_config(allowed_origins, allow_credentials));
+        let middlewares = [CorsFactory.create("cors", Some(config)).unwrap()];
+        let req = Request::builder()
+            .uri("/")
+            .header(ORIGIN, origin)
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        Next::new(ok_handler(), &middlewares)
+            .run(req)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reflects_allowed_origin() {
+        let allowed = ["https://app.example.com", "https://*.example.org"];
+        for origin in ["https://app.example.com", "https://eu.example.org"] {
+            let response = run(&allowed, true, origin).await;
+            assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+            assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+            assert_eq!(response.headers()[VARY], "Origin");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_omits_disallowed_origin() {
+        let allowed = ["https://app.example.com", "https://*.example.org"];
+        for origin in ["https://evil.example.net", "https://example.org"] {
+            let response = run(&allowed, true, origin).await;
+            assert!(
+                response
+                    .headers()
+                    .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+                    .is_none()
+            );
+            assert!(
+                response
+                    .headers()
+                    .get(ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                    .is_none()
+            );
+            assert_eq!(response.headers()[VARY], "Origin");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_is_sent_without_credentials() {
+        let response = run(&["*"], false, "https://any.example.com").await;
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(
+            response
+                .headers()
+                .get(ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                .is_none()
+        );
+    }
+
+    async fn preflight(config: CorsConfig, origin: &str) -> Response<ResponseBody> {
+        let middlewares = [CorsFactory
+            .create("cors", Some(MiddlewareConfig::Cors(config)))
+            .unwrap()];
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+            .header(ACCESS_CONTROL_REQUEST_HEADERS, "content-type, x-trace")
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        let upstream: HandlerFunc = Arc::new(|_req| panic!("preflight reached the upstream"));
+        Next::new(upstream, &middlewares).run(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_preflight_is_answered_without_the_upstream() {
+        let origin = "https://app.example.com";
+        let response = preflight(cors_config(&[origin], true), origin).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers();
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type, x-api-key"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "600");
+
+        let config = CorsConfig {
+            allowed_headers: vec![String::from("*")],
+            ..cors_config(&[origin], true)
+        };
+        let response = preflight(config, origin).await;
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type, x-trace"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_preflight_of_disallowed_origin_allows_nothing() {
+        let response = preflight(
+            cors_config(&["https://app.example.com"], true),
+            "https://evil.example.net",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        for header in [
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_HEADERS,
+        ] {
+            assert!(response.headers().get(header).is_none());
+        }
+    }
+}
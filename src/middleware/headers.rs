@@ -0,0 +1,236 @@
+use crate::config::MiddlewareConfig;
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use async_trait::async_trait;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, Response};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Placeholder replaced by the client IP in header values
+const CLIENT_IP_PLACEHOLDER: &str = "{client_ip}";
+
+enum ValueTemplate {
+    Static(HeaderValue),
+    /// Contains the client IP placeholder
+    ClientIp(String),
+}
+
+impl ValueTemplate {
+    fn parse(value: &str) -> Result<Self, String> {
+        if value.contains(CLIENT_IP_PLACEHOLDER) {
+            return Ok(ValueTemplate::ClientIp(value.to_string()));
+        }
+        HeaderValue::from_str(value)
+            .map(ValueTemplate::Static)
+            .map_err(|_| format!("Invalid header value {value}"))
+    }
+
+    fn render(&self, client_ip: Option<IpAddr>) -> Option<HeaderValue> {
+        match self {
+            ValueTemplate::Static(value) => Some(value.clone()),
+            ValueTemplate::ClientIp(template) => {
+                let client_ip = client_ip.map(|ip| ip.to_string()).unwrap_or_default();
+                HeaderValue::from_str(&template.replace(CLIENT_IP_PLACEHOLDER, &client_ip)).ok()
+            }
+        }
+    }
+}
+
+/// Header changes applied in order: removing, setting, then appending.
+struct HeaderOps {
+    remove: Vec<HeaderName>,
+    set: Vec<(HeaderName, ValueTemplate)>,
+    append: Vec<(HeaderName, ValueTemplate)>,
+}
+
+impl HeaderOps {
+    fn new(
+        remove: Vec<String>,
+        set: HashMap<String, String>,
+        append: HashMap<String, String>,
+    ) -> Result<Self, String> {
+        let parse_name = |name: &str| {
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("Invalid header {name}"))
+        };
+        let parse_all = |headers: HashMap<String, String>| {
+            headers
+                .iter()
+                .map(|(name, value)| Ok((parse_name(name)?, ValueTemplate::parse(value)?)))
+                .collect::<Result<Vec<_>, String>>()
+        };
+        Ok(HeaderOps {
+            remove: remove
+                .iter()
+                .map(|name| parse_name(name))
+                .collect::<Result<_, _>>()?,
+            set: parse_all(set)?,
+            append: parse_all(append)?,
+        })
+    }
+
+    fn apply(&self, headers: &mut HeaderMap, client_ip: Option<IpAddr>) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (name, template) in &self.set {
+            if let Some(value) = template.render(client_ip) {
+                headers.insert(name.clone(), value);
+            }
+        }
+        for (name, template) in &self.append {
+            if let Some(value) = template.render(client_ip) {
+                headers.append(name.clone(), value);
+            }
+        }
+    }
+}
+
+pub struct Headers {
+    request: HeaderOps,
+    response: HeaderOps,
+}
+
+#[async_trait]
+impl Middleware for Headers {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let mut req = req;
+        let client_ip = req.extensions().get::<IpAddr>().copied();
+        self.request.apply(req.headers_mut(), client_ip);
+        let mut response = next.run(req).await?;
+        self.response.apply(response.headers_mut(), client_ip);
+        Ok(response)
+    }
+}
+
+pub struct HeadersFactory;
+
+impl MiddlewareFactory for HeadersFactory {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::Headers(cfg)) => Ok(Arc::new(Headers {
+                request: HeaderOps::new(cfg.request_remove, cfg.request_set, cfg.request_append)?,
+                response: HeaderOps::new(
+                    cfg.response_remove,
+                    cfg.response_set,
+                    cfg.response_append,
+                )?,
+            })),
+            _ => Err(String::from("Invalid config for headers middleware")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HeadersConfig;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Bytes;
+    use std::net::Ipv4Addr;
+
+    /// Answers with the request headers it received, one `name: value` line each, and a
+    /// `server` and `x-powered-by` header.
+    fn echo_headers() -> HandlerFunc {
+        Arc::new(|req| {
+            Box::pin(async move {
+                let mut lines = req
+                    .headers()
+                    .iter()
+                    .map(|(name, value)| format!("{name}: {}", value.to_str().unwrap()))
+                    .collect::<Vec<_>>();
+                lines.sort();
+                Ok(Response::builder()
+                    .header("server", "upstream")
+                    .header("x-powered-by", "php")
+                    .body(
+                        Full::new(Bytes::from(lines.join("\n")))
+                            .map_err(|never| match never {})
+                            .boxed(),
+                    )
+                    .unwrap())
+            })
+        })
+    }
+
+    async fn run(config: HeadersConfig, headers: &[(&str, &str)]) -> (HeaderMap, String) {
+        let middlewares = [HeadersFactory
+            .create("headers", Some(MiddlewareConfig::Headers(config)))
+            .unwrap()];
+        let mut req = Request::builder().uri("/");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let mut req = req
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        req.extensions_mut()
+            .insert(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)));
+        let response = Next::new(echo_headers(), &middlewares)
+            .run(req)
+            .await
+            .unwrap();
+        let headers = response.headers().clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn headers(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_request_headers_are_set_appended_and_removed() {
+        let config = HeadersConfig {
+            request_set: headers(&[("x-client", "ip={client_ip}"), ("x-env", "prod")]),
+            request_append: headers(&[("x-tag", "gateway")]),
+            request_remove: vec![String::from("cookie"), String::from("x-absent")],
+            ..HeadersConfig::default()
+        };
+        let (_, received) = run(
+            config,
+            &[
+                ("cookie", "session=1"),
+                ("x-env", "dev"),
+                ("x-tag", "client"),
+            ],
+        )
+        .await;
+        assert_eq!(
+            received,
+            "x-client: ip=10.0.0.7\nx-env: prod\nx-tag: client\nx-tag: gateway"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_response_headers_are_set_appended_and_removed() {
+        let config = HeadersConfig {
+            response_set: headers(&[("server", "portiq")]),
+            response_append: headers(&[("vary", "origin"), ("x-seen-by", "{client_ip}")]),
+            response_remove: vec![String::from("x-powered-by"), String::from("x-absent")],
+            ..HeadersConfig::default()
+        };
+        let (headers, _) = run(config, &[]).await;
+        assert_eq!(headers["server"], "portiq");
+        assert_eq!(headers["vary"], "origin");
+        assert_eq!(headers["x-seen-by"], "10.0.0.7");
+        assert!(!headers.contains_key("x-powered-by"));
+    }
+}
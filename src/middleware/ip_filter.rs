@@ -0,0 +1,139 @@
+use crate::config::{MiddlewareConfig, parse_ip_range};
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::utils::response_with_status;
+use async_trait::async_trait;
+use hyper::{Request, Response, StatusCode};
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+pub struct IpFilter {
+    allow: Box<[IpNet]>,
+    deny: Box<[IpNet]>,
+}
+
+impl IpFilter {
+    fn is_allowed(&self, ip: IpAddr) -> bool {
+        // An IPv4 client of a dual stack listener shows up as `::ffff:a.b.c.d`
+        let ip = ip.to_canonical();
+        !self.deny.iter().any(|range| range.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|range| range.contains(&ip)))
+    }
+}
+
+#[async_trait]
+impl Middleware for IpFilter {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        // A request without a client address is blocked rather than let through
+        match req.extensions().get::<IpAddr>() {
+            Some(ip) if self.is_allowed(*ip) => next.run(req).await,
+            ip => {
+                tracing::debug!("Blocking request from {ip:?}");
+                Ok(response_with_status(StatusCode::FORBIDDEN))
+            }
+        }
+    }
+}
+
+fn parse_ranges(ranges: &[String]) -> Result<Box<[IpNet]>, String> {
+    ranges
+        .iter()
+        .map(|range| parse_ip_range(range).ok_or(format!("Invalid ip range {range}")))
+        .collect()
+}
+
+pub struct IpFilterFactory;
+
+impl MiddlewareFactory for IpFilterFactory {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::IpFilter(cfg)) => Ok(Arc::new(IpFilter {
+                allow: parse_ranges(&cfg.allow)?,
+                deny: parse_ranges(&cfg.deny)?,
+            })),
+            _ => Err(String::from("Invalid config for ip filter middleware")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IpFilterConfig;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::Bytes;
+
+    async fn status(config: &IpFilterConfig, ip: &str) -> StatusCode {
+        let middlewares = [IpFilterFactory
+            .create(
+                "ip-filter",
+                Some(MiddlewareConfig::IpFilter(config.clone())),
+            )
+            .unwrap()];
+        let handler: HandlerFunc = Arc::new(|_req| {
+            Box::pin(async {
+                Ok(Response::new(
+                    Empty::new().map_err(|never| match never {}).boxed(),
+                ))
+            })
+        });
+        let mut req = Request::builder()
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        req.extensions_mut().insert(ip.parse::<IpAddr>().unwrap());
+        let response = Next::new(handler, &middlewares).run(req).await.unwrap();
+        response.status()
+    }
+
+    fn config(allow: &[&str], deny: &[&str]) -> IpFilterConfig {
+        IpFilterConfig {
+            allow: allow.iter().map(|range| range.to_string()).collect(),
+            deny: deny.iter().map(|range| range.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ipv4_ranges_are_allowed() {
+        let config = config(&["10.0.0.0/8", "192.168.1.7"], &[]);
+        for ip in ["10.20.30.40", "192.168.1.7", "::ffff:10.0.0.1"] {
+            assert_eq!(status(&config, ip).await, StatusCode::OK, "{ip}");
+        }
+        for ip in ["11.0.0.1", "192.168.1.8", "::1"] {
+            assert_eq!(status(&config, ip).await, StatusCode::FORBIDDEN, "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ipv6_ranges_are_allowed() {
+        let config = config(&["2001:db8::/32"], &[]);
+        assert_eq!(status(&config, "2001:db8:1::5").await, StatusCode::OK);
+        assert_eq!(status(&config, "2001:db9::1").await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_deny_wins_over_allow() {
+        let config = config(&["10.0.0.0/8"], &["10.0.66.0/24"]);
+        assert_eq!(status(&config, "10.0.66.12").await, StatusCode::FORBIDDEN);
+        assert_eq!(status(&config, "10.0.67.12").await, StatusCode::OK);
+
+        // Without allow ranges everyone else gets through
+        let config = self::config(&[], &["203.0.113.0/24", "2001:db8::/32"]);
+        assert_eq!(status(&config, "203.0.113.9").await, StatusCode::FORBIDDEN);
+        assert_eq!(status(&config, "2001:db8::9").await, StatusCode::FORBIDDEN);
+        assert_eq!(status(&config, "198.51.100.1").await, StatusCode::OK);
+    }
+}
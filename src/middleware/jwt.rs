@@ -0,0 +1,561 @@
+use crate::config::{JwtConfig, MiddlewareConfig};
+use crate::middleware::registry::{MiddlewareFactory, NamedStates};
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::utils::response_with_status;
+use async_trait::async_trait;
+use hyper::header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue, WWW_AUTHENTICATE};
+use hyper::{Request, Response, StatusCode};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, AlgorithmFamily, DecodingKey, Validation, decode, decode_header};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+/// Bounds a JWKS fetch, requests wait for it while the set is refreshed.
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A token signed by an unknown key refetches the set at most this often, in case it was rotated.
+const JWKS_MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Allowed algorithms of a config, every one of them must suit its key source.
+pub fn jwt_algorithms(config: &JwtConfig) -> Result<Vec<Algorithm>, String> {
+    let hmac = match (&config.secret, &config.jwks_url) {
+        (Some(secret), None) if !secret.is_empty() => true,
+        (None, Some(_)) => false,
+        _ => {
+            return Err(String::from(
+                "exactly one of secret or jwks_url must be set",
+            ));
+        }
+    };
+    if config.algorithms.is_empty() {
+        return Ok(vec![if hmac {
+            Algorithm::HS256
+        } else {
+            Algorithm::RS256
+        }]);
+    }
+    config
+        .algorithms
+        .iter()
+        .map(|name| {
+            let algorithm = name
+                .parse::<Algorithm>()
+                .map_err(|_| format!("unsupported algorithm {name}"))?;
+            if (algorithm.family() == AlgorithmFamily::Hmac) != hmac {
+                return Err(format!(
+                    "algorithm {name} needs {}",
+                    if hmac { "a jwks_url" } else { "a secret" }
+                ));
+            }
+            Ok(algorithm)
+        })
+        .collect()
+}
+
+/// Decoding keys of a JWKS with their `kid`.
+type Keys = Arc<[(Option<String>, DecodingKey)]>;
+
+struct CachedKeys {
+    keys: Keys,
+    /// Last fetch attempt, a failed one keeps the previous keys until the next refresh
+    checked_at: Instant,
+}
+
+/// Keys of a JWKS URL, shared by every chain built from the same config.
+struct Jwks {
+    url: String,
+    refresh_interval: Duration,
+    client: Arc<reqwest::Client>,
+    cached: RwLock<Option<CachedKeys>>,
+    /// Held while fetching, so concurrent requests wait for one fetch
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+impl Jwks {
+    /// Key for a token, refreshing the set once stale or when a `kid` is missing from it.
+    async fn key_for(&self, kid: Option<&str>, family: AlgorithmFamily) -> Option<DecodingKey> {
+        let mut refetched = false;
+        loop {
+            let (keys, age) = match &*self.cached.read().unwrap() {
+                Some(cached) => (Some(cached.keys.clone()), cached.checked_at.elapsed()),
+                None => (None, Duration::MAX),
+            };
+            let key = keys.as_deref().and_then(|keys| find_key(keys, kid, family));
+            let refetch = if key.is_some() {
+                age >= self.refresh_interval
+            } else {
+                age >= JWKS_MIN_REFETCH_INTERVAL
+            };
+            if !refetch || refetched {
+                return key;
+            }
+            self.refresh(age).await;
+            refetched = true;
+        }
+    }
+
+    /// Fetches the set unless another request did so since it was found `age` old.
+    async fn refresh(&self, age: Duration) {
+        let _refreshing = self.refreshing.lock().await;
+        if let Some(cached) = &*self.cached.read().unwrap()
+            && cached.checked_at.elapsed() < age
+        {
+            return;
+        }
+        let fetched = self.fetch().await;
+        let mut cached = self.cached.write().unwrap();
+        let keys = match fetched {
+            Ok(keys) => keys,
+            Err(err) => {
+                tracing::warn!("Failed to fetch JWKS from {}: {err}", self.url);
+                cached
+                    .as_ref()
+                    .map_or_else(|| Arc::from([]), |cached| cached.keys.clone())
+            }
+        };
+        *cached = Some(CachedKeys {
+            keys,
+            checked_at: Instant::now(),
+        });
+    }
+
+    async fn fetch(&self) -> Result<Keys, String> {
+        let response = self
+            .client
+            .get(&self.url)
+            .timeout(JWKS_FETCH_TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.to_string())?;
+        let body = response.bytes().await.map_err(|err| err.to_string())?;
+        let set = serde_json::from_slice::<JwkSet>(&body).map_err(|err| err.to_string())?;
+        // Keys of unsupported types are skipped rather than failing the whole set
+        Ok(set
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let key = DecodingKey::from_jwk(jwk).ok()?;
+                Some((jwk.common.key_id.clone(), key))
+            })
+            .collect())
+    }
+}
+
+/// The key named by `kid`, or the first of the algorithm's family for a token without one.
+fn find_key(
+    keys: &[(Option<String>, DecodingKey)],
+    kid: Option<&str>,
+    family: AlgorithmFamily,
+) -> Option<DecodingKey> {
+    keys.iter()
+        .filter(|(_, key)| key.family() == family)
+        .find(|(key_id, _)| kid.is_none_or(|kid| key_id.as_deref() == Some(kid)))
+        .map(|(_, key)| key.clone())
+}
+
+enum KeySource {
+    Secret(DecodingKey),
+    Jwks(Arc<Jwks>),
+}
+
+pub struct Jwt {
+    keys: KeySource,
+    algorithms: Box<[Algorithm]>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: Duration,
+    forward_claims: Box<[(String, HeaderName)]>,
+}
+
+impl Jwt {
+    /// Claims of a valid token, `None` for a missing or invalid one.
+    async fn verify(&self, headers: &HeaderMap) -> Option<HashMap<String, Value>> {
+        let token = bearer_token(headers.get(AUTHORIZATION)?)?;
+        let header = decode_header(token).ok()?;
+        if !self.algorithms.contains(&header.alg) {
+            tracing::debug!("Rejecting token signed with {:?}", header.alg);
+            return None;
+        }
+        let key = match &self.keys {
+            KeySource::Secret(key) => key.clone(),
+            KeySource::Jwks(jwks) => {
+                jwks.key_for(header.kid.as_deref(), header.alg.family())
+                    .await?
+            }
+        };
+
+        // Checked against the token's own algorithm, the key only verifies its family
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.leeway.as_secs();
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            validation.required_spec_claims.insert(String::from("iss"));
+        }
+        match &self.audience {
+            Some(audience) => {
+                validation.set_audience(&[audience]);
+                validation.required_spec_claims.insert(String::from("aud"));
+            }
+            None => validation.validate_aud = false,
+        }
+        match decode::<HashMap<String, Value>>(token, &key, &validation) {
+            Ok(token) => Some(token.claims),
+            Err(err) => {
+                tracing::debug!("Rejecting token: {err}");
+                None
+            }
+        }
+    }
+}
+
+fn bearer_token(authorization: &HeaderValue) -> Option<&str> {
+    let (scheme, token) = authorization.to_str().ok()?.trim().split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+/// Header value of a claim, strings are sent as is and anything else as JSON.
+fn claim_header_value(claim: &Value) -> Option<HeaderValue> {
+    match claim {
+        Value::String(value) => HeaderValue::from_str(value).ok(),
+        value => HeaderValue::from_str(&value.to_string()).ok(),
+    }
+}
+
+#[async_trait]
+impl Middleware for Jwt {
+    async fn call(
+        &self,
+        mut req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let Some(claims) = self.verify(req.headers()).await else {
+            let mut response = response_with_status(StatusCode::UNAUTHORIZED);
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return Ok(response);
+        };
+        for (claim, header) in &self.forward_claims {
+            // Clients cannot pass off their own value as a verified claim
+            req.headers_mut().remove(header);
+            if let Some(value) = claims.get(claim).and_then(claim_header_value) {
+                req.headers_mut().insert(header.clone(), value);
+            }
+        }
+        next.run(req).await
+    }
+}
+
+pub struct JwtFactory {
+    /// Gateway upstream client fetching every JWKS, set once built at startup
+    client: Arc<OnceLock<Arc<reqwest::Client>>>,
+    /// Cached keys of each jwt middleware
+    jwks: NamedStates<JwtConfig, Jwks>,
+}
+
+impl JwtFactory {
+    pub fn new(client: Arc<OnceLock<Arc<reqwest::Client>>>) -> Self {
+        JwtFactory {
+            client,
+            jwks: NamedStates::new(),
+        }
+    }
+
+    fn jwks_for(&self, name: &str, config: &JwtConfig, url: &str) -> Result<Arc<Jwks>, String> {
+        let client = self
+            .client
+            .get()
+            .ok_or_else(|| String::from("No HTTP client to fetch the JWKS with"))?;
+        Ok(self.jwks.get_or_insert_with(name, config, || Jwks {
+            url: String::from(url),
+            refresh_interval: config.jwks_refresh_interval,
+            client: client.clone(),
+            cached: RwLock::new(None),
+            refreshing: tokio::sync::Mutex::new(()),
+        }))
+    }
+}
+
+impl MiddlewareFactory for JwtFactory {
+    fn create(
+        &self,
+        name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::Jwt(cfg)) => {
+                let algorithms = jwt_algorithms(&cfg)?.into_boxed_slice();
+                let keys = match (&cfg.secret, &cfg.jwks_url) {
+                    (Some(secret), _) => {
+                        KeySource::Secret(DecodingKey::from_secret(secret.as_bytes()))
+                    }
+                    (None, Some(url)) => KeySource::Jwks(self.jwks_for(name, &cfg, url)?),
+                    (None, None) => unreachable!("checked by jwt_algorithms"),
+                };
+                let forward_claims = cfg
+                    .forward_claims
+                    .iter()
+                    .map(|(claim, header)| {
+                        HeaderName::from_bytes(header.as_bytes())
+                            .map(|header| (claim.clone(), header))
+                            .map_err(|_| format!("Invalid header name {header}"))
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok(Arc::new(Jwt {
+                    keys,
+                    algorithms,
+                    issuer: cfg.issuer,
+                    audience: cfg.audience,
+                    leeway: cfg.leeway,
+                    forward_claims,
+                }))
+            }
+            _ => Err(String::from("Invalid config for jwt middleware")),
+        }
+    }
+
+    fn retain(&self, middlewares: &HashMap<String, MiddlewareConfig>) {
+        self.jwks.retain(middlewares);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::HandlerFunc;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Bytes;
+    use jsonwebtoken::{EncodingKey, Header, encode, get_current_timestamp};
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const SECRET: &str = "top-secret";
+
+    fn config() -> JwtConfig {
+        JwtConfig {
+            secret: Some(String::from(SECRET)),
+            jwks_url: None,
+            jwks_refresh_interval: Duration::from_secs(300),
+            algorithms: vec![],
+            issuer: Some(String::from("https://auth.example.com")),
+            audience: Some(String::from("orders")),
+            leeway: Duration::ZERO,
+            forward_claims: HashMap::from([(String::from("sub"), String::from("x-user-id"))]),
+        }
+    }
+
+    fn factory() -> JwtFactory {
+        JwtFactory::new(Arc::new(OnceLock::from(Arc::new(reqwest::Client::new()))))
+    }
+
+    fn hs256_token(claims: Value) -> String {
+        let key = EncodingKey::from_secret(SECRET.as_bytes());
+        encode(&Header::new(Algorithm::HS256), &claims, &key).unwrap()
+    }
+
+    fn claims(issuer: &str, expires_in: i64) -> Value {
+        json!({
+            "sub": "user-42",
+            "iss": issuer,
+            "aud": "orders",
+            "exp": get_current_timestamp() as i64 + expires_in,
+        })
+    }
+
+    /// Status of the request with `token` and the `x-user-id` header the upstream received.
+    async fn run(config: JwtConfig, token: &str) -> (StatusCode, Option<String>) {
+        let middlewares = [factory()
+            .create("jwt", Some(MiddlewareConfig::Jwt(config)))
+            .unwrap()];
+        let handler: HandlerFunc = Arc::new(|req| {
+            Box::pin(async move {
+                let user = req.headers().get("x-user-id").cloned();
+                let mut response =
+                    Response::new(Empty::new().map_err(|never| match never {}).boxed());
+                if let Some(user) = user {
+                    response.headers_mut().insert("x-user-id", user);
+                }
+                Ok(response)
+            })
+        });
+        let req = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .header("x-user-id", "spoofed")
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        let response = Next::new(handler, &middlewares).run(req).await.unwrap();
+        let user = response
+            .headers()
+            .get("x-user-id")
+            .map(|user| user.to_str().unwrap().to_string());
+        (response.status(), user)
+    }
+
+    #[tokio::test]
+    async fn test_valid_hs256_token_forwards_claims() {
+        let token = hs256_token(claims("https://auth.example.com", 60));
+        let (status, user) = run(config(), &token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(user.as_deref(), Some("user-42"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_token_is_rejected() {
+        let token = hs256_token(claims("https://auth.example.com", -60));
+        let (status, _) = run(config(), &token).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Within the allowed clock skew
+        let mut config = config();
+        config.leeway = Duration::from_secs(120);
+        let (status, _) = run(config, &token).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_issuer_is_rejected() {
+        let token = hs256_token(claims("https://evil.example.com", 60));
+        let (status, user) = run(config(), &token).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(user, None);
+
+        // Only the configured algorithms are accepted
+        let mut config = config();
+        config.algorithms = vec![String::from("HS512")];
+        let token = hs256_token(claims("https://auth.example.com", 60));
+        let (status, _) = run(config, &token).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_token_is_verified_with_fetched_jwks() {
+        let key_pair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+        // Uncompressed point, 0x04 followed by the x and y coordinates
+        let point = key_pair.public_key_raw();
+        let jwks = json!({
+            "keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "kid": "key-1",
+                "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+            }]
+        })
+        .to_string();
+
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let jwks_url = format!("http://{}/jwks.json", server.local_addr().unwrap());
+        let fetches = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let served = fetches.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = server.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let _ = stream.read(&mut buf).await.unwrap();
+                served.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{jwks}",
+                    jwks.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(String::from("key-1"));
+        let key = EncodingKey::from_ec_der(&key_pair.serialize_der());
+        let token = encode(&header, &claims("https://auth.example.com", 60), &key).unwrap();
+
+        let config = JwtConfig {
+            secret: None,
+            jwks_url: Some(jwks_url),
+            algorithms: vec![String::from("ES256")],
+            ..config()
+        };
+        let middleware = factory()
+            .create("jwt", Some(MiddlewareConfig::Jwt(config)))
+            .unwrap();
+        let middlewares = [middleware];
+        for _ in 0..3 {
+            let handler: HandlerFunc = Arc::new(|_req| {
+                Box::pin(async {
+                    Ok(Response::new(
+                        Full::new(Bytes::new())
+                            .map_err(|never| match never {})
+                            .boxed(),
+                    ))
+                })
+            });
+            let req = Request::builder()
+                .header(AUTHORIZATION, format!("Bearer {token}"))
+                .body(
+                    Empty::<Bytes>::new()
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap();
+            let response = Next::new(handler, &middlewares).run(req).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        // Cached after the first request
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_jwks_are_kept_per_middleware_name() {
+        let factory = factory();
+        let url = "https://auth.example.com/jwks.json";
+        let config = JwtConfig {
+            secret: None,
+            jwks_url: Some(String::from(url)),
+            ..config()
+        };
+        let jwks = factory.jwks_for("auth", &config, url).unwrap();
+        assert!(Arc::ptr_eq(
+            &jwks,
+            &factory.jwks_for("auth", &config, url).unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            &jwks,
+            &factory.jwks_for("partner-auth", &config, url).unwrap()
+        ));
+
+        // A reload removing the middleware drops its cached keys
+        factory.retain(&HashMap::from([(
+            String::from("partner-auth"),
+            MiddlewareConfig::Jwt(config.clone()),
+        )]));
+        assert!(!Arc::ptr_eq(
+            &jwks,
+            &factory.jwks_for("auth", &config, url).unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_jwks_need_the_gateway_client() {
+        let config = JwtConfig {
+            secret: None,
+            jwks_url: Some(String::from("https://auth.example.com/jwks.json")),
+            ..config()
+        };
+        let factory = JwtFactory::new(Arc::new(OnceLock::new()));
+        assert!(
+            factory
+                .create("auth", Some(MiddlewareConfig::Jwt(config)))
+                .is_err()
+        );
+    }
+}
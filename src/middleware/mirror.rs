@@ -0,0 +1,440 @@
+use crate::BoxedStr;
+use crate::config::{MiddlewareConfig, MirrorCompareConfig, MirrorConfig};
+use crate::error::BoxError;
+use crate::middleware::registry::{MiddlewareFactory, NamedStates};
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody, StreamedBody};
+use crate::utils::{forwarded_request_headers, response_with_status};
+use async_trait::async_trait;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hasher};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, ready};
+use tokio::sync::oneshot;
+
+/// The compared aspects of a response, `None` for the ones left out.
+#[derive(Debug, PartialEq)]
+struct Observed {
+    status: Option<StatusCode>,
+    headers: Vec<Option<HeaderValue>>,
+    body_hash: Option<u64>,
+}
+
+struct Comparison {
+    status: bool,
+    headers: Box<[HeaderName]>,
+    body: bool,
+}
+
+impl Comparison {
+    fn new(config: &MirrorCompareConfig) -> Self {
+        Comparison {
+            status: config.status,
+            // Validated on load
+            headers: config
+                .headers
+                .iter()
+                .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+                .collect(),
+            body: config.body,
+        }
+    }
+
+    /// Everything but the body hash, filled in once the body was read.
+    fn observe(&self, status: StatusCode, headers: &HeaderMap) -> Observed {
+        Observed {
+            status: self.status.then_some(status),
+            headers: self
+                .headers
+                .iter()
+                .map(|name| headers.get(name).cloned())
+                .collect(),
+            body_hash: None,
+        }
+    }
+
+    /// Names the aspects `mirror` disagrees with `primary` on.
+    fn differences(&self, primary: &Observed, mirror: &Observed) -> Vec<String> {
+        let mut differences = vec![];
+        if primary.status != mirror.status {
+            differences.push(format!(
+                "status {:?} != {:?}",
+                primary.status.map(|status| status.as_u16()),
+                mirror.status.map(|status| status.as_u16())
+            ));
+        }
+        for (name, (primary, mirror)) in self
+            .headers
+            .iter()
+            .zip(primary.headers.iter().zip(&mirror.headers))
+        {
+            if primary != mirror {
+                differences.push(format!("header {name}"));
+            }
+        }
+        if primary.body_hash != mirror.body_hash {
+            differences.push(String::from("body"));
+        }
+        differences
+    }
+
+    /// Sends the mirror request and observes its response, `None` when it failed.
+    async fn observe_mirror(&self, request: reqwest::RequestBuilder) -> Option<Observed> {
+        let mut response = request.send().await.ok()?;
+        let mut observed = self.observe(response.status(), response.headers());
+        if self.body {
+            let mut hasher = DefaultHasher::new();
+            while let Some(chunk) = response.chunk().await.ok()? {
+                hasher.write(&chunk);
+            }
+            observed.body_hash = Some(hasher.finish());
+        }
+        Some(observed)
+    }
+}
+
+/// Streams the primary response body to the client while hashing it, the observation is handed
+/// over once the body ended.
+struct HashingBody {
+    inner: ResponseBody,
+    hasher: DefaultHasher,
+    observed: Option<(Observed, oneshot::Sender<Observed>)>,
+}
+
+impl HashingBody {
+    fn finish(&mut self) {
+        if let Some((mut observed, observed_tx)) = self.observed.take() {
+            observed.body_hash = Some(self.hasher.finish());
+            let _ = observed_tx.send(observed);
+        }
+    }
+}
+
+impl Body for HashingBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = self.get_mut();
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.hasher.write(data);
+                }
+                // The end of the body may not be polled for once the inner body reports it
+                if this.inner.is_end_stream() {
+                    this.finish();
+                }
+            }
+            None => this.finish(),
+            // A truncated body is not compared
+            Some(Err(_)) => this.observed = None,
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Shared by the chains built from the same config, so mismatches add up across requests.
+struct MirrorState {
+    client: reqwest::Client,
+    compared: AtomicU64,
+    mismatches: AtomicU64,
+}
+
+pub struct Mirror {
+    target: BoxedStr,
+    comparison: Option<Arc<Comparison>>,
+    state: Arc<MirrorState>,
+}
+
+impl Mirror {
+    /// Compares the mirror response with the primary one handed over by `primary_rx`.
+    async fn compare(
+        comparison: Arc<Comparison>,
+        state: Arc<MirrorState>,
+        request_line: String,
+        mirror_request: reqwest::RequestBuilder,
+        primary_rx: oneshot::Receiver<Observed>,
+    ) {
+        let mirror = comparison.observe_mirror(mirror_request).await;
+        // The client went away before the primary body ended
+        let Ok(primary) = primary_rx.await else {
+            return;
+        };
+        let differences = match &mirror {
+            Some(mirror) => comparison.differences(&primary, mirror),
+            None => vec![String::from("mirror request failed")],
+        };
+        let compared = state.compared.fetch_add(1, Ordering::Relaxed) + 1;
+        if !differences.is_empty() {
+            let mismatches = state.mismatches.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::warn!(
+                target: "shadow_compare",
+                "Mirror response to {request_line} differs in {}, {mismatches} of {compared} compared responses differed",
+                differences.join(", ")
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for Mirror {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        // The body is sent twice, only requests small enough to have been buffered are mirrored
+        if req.extensions().get::<StreamedBody>().is_some() {
+            return next.run(req).await;
+        }
+        let (parts, body) = req.into_parts();
+        let body = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(err) => {
+                tracing::warn!("Error reading request body from client: {err}");
+                return Ok(response_with_status(StatusCode::BAD_REQUEST));
+            }
+        };
+        let path = parts.uri.path_and_query().map_or("/", |pq| pq.as_str());
+        let request_line = format!("{} {path}", parts.method);
+        let mirror_request = self
+            .state
+            .client
+            .request(parts.method.clone(), format!("{}{path}", self.target))
+            .headers(forwarded_request_headers(&parts.headers))
+            .body(body.clone());
+        let req = Request::from_parts(
+            parts,
+            Full::new(body).map_err(|never| match never {}).boxed(),
+        );
+
+        let Some(comparison) = &self.comparison else {
+            tokio::spawn(async move {
+                if let Err(err) = mirror_request.send().await {
+                    tracing::debug!("Mirror request {request_line} failed: {err}");
+                }
+            });
+            return next.run(req).await;
+        };
+
+        let (primary_tx, primary_rx) = oneshot::channel();
+        tokio::spawn(Mirror::compare(
+            comparison.clone(),
+            self.state.clone(),
+            request_line,
+            mirror_request,
+            primary_rx,
+        ));
+        let response = next.run(req).await?;
+        let observed = comparison.observe(response.status(), response.headers());
+        if !comparison.body {
+            let _ = primary_tx.send(observed);
+            return Ok(response);
+        }
+        let (parts, body) = response.into_parts();
+        let mut body = HashingBody {
+            inner: body,
+            hasher: DefaultHasher::new(),
+            observed: Some((observed, primary_tx)),
+        };
+        // An empty body may never be polled
+        if body.inner.is_end_stream() {
+            body.finish();
+        }
+        Ok(Response::from_parts(parts, body.boxed()))
+    }
+}
+
+/// Keeps the client and comparison counters of each mirror middleware.
+pub struct MirrorFactory {
+    states: NamedStates<MirrorConfig, MirrorState>,
+}
+
+impl MirrorFactory {
+    pub fn new() -> Self {
+        MirrorFactory {
+            states: NamedStates::new(),
+        }
+    }
+
+    fn state_for(&self, name: &str, config: &MirrorConfig) -> Result<Arc<MirrorState>, String> {
+        self.states.get_or_try_insert_with(name, config, || {
+            let client = reqwest::Client::builder()
+                .timeout(config.timeout)
+                .build()
+                .map_err(|err| format!("Failed to build mirror client: {err}"))?;
+            Ok(MirrorState {
+                client,
+                compared: AtomicU64::new(0),
+                mismatches: AtomicU64::new(0),
+            })
+        })
+    }
+}
+
+impl MiddlewareFactory for MirrorFactory {
+    fn create(
+        &self,
+        name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::Mirror(cfg)) => Ok(Arc::new(Mirror {
+                target: cfg.target.trim_end_matches('/').into(),
+                comparison: cfg
+                    .compare
+                    .as_ref()
+                    .map(|compare| Arc::new(Comparison::new(compare))),
+                state: self.state_for(name, &cfg)?,
+            })),
+            _ => Err(String::from("Invalid config for mirror middleware")),
+        }
+    }
+
+    fn retain(&self, middlewares: &HashMap<String, MiddlewareConfig>) {
+        self.states.retain(middlewares);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::HandlerFunc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Mirror upstream answering every request with `response`.
+    async fn mirror_upstream(response: &'static str) -> String {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = upstream.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let _ = stream.read(&mut buf).await.unwrap();
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{upstream_addr}")
+    }
+
+    fn primary() -> HandlerFunc {
+        Arc::new(|_req| {
+            Box::pin(async {
+                Ok(Response::builder()
+                    .header("x-version", "1")
+                    .body(
+                        Full::new(Bytes::from_static(b"primary"))
+                            .map_err(|never| match never {})
+                            .boxed(),
+                    )
+                    .unwrap())
+            })
+        })
+    }
+
+    /// Sends one request through the mirror and returns the client body with the counters once
+    /// the comparison ran.
+    async fn run(mirror_response: &'static str) -> (Bytes, u64) {
+        let factory = MirrorFactory::new();
+        let config = MirrorConfig {
+            target: mirror_upstream(mirror_response).await,
+            timeout: Duration::from_secs(1),
+            compare: Some(MirrorCompareConfig {
+                status: true,
+                headers: vec![String::from("x-version")],
+                body: true,
+            }),
+        };
+        let middlewares = [factory
+            .create("mirror", Some(MiddlewareConfig::Mirror(config.clone())))
+            .unwrap()];
+        let req = Request::builder()
+            .uri("/orders?page=2")
+            .body(
+                Full::new(Bytes::from_static(b"payload"))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        let response = Next::new(primary(), &middlewares).run(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        let state = factory.state_for("mirror", &config).unwrap();
+        for _ in 0..100 {
+            if state.compared.load(Ordering::Relaxed) == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(state.compared.load(Ordering::Relaxed), 1);
+        (body, state.mismatches.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn test_states_are_kept_per_middleware_name() {
+        let factory = MirrorFactory::new();
+        let config = MirrorConfig {
+            target: String::from("http://127.0.0.1:1"),
+            timeout: Duration::from_secs(1),
+            compare: None,
+        };
+        let shadow = factory.state_for("shadow", &config).unwrap();
+        assert!(Arc::ptr_eq(
+            &shadow,
+            &factory.state_for("shadow", &config).unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            &shadow,
+            &factory.state_for("other-shadow", &config).unwrap()
+        ));
+
+        // A reload removing the middleware drops its counters
+        factory.retain(&HashMap::from([(
+            String::from("other-shadow"),
+            MiddlewareConfig::Mirror(config.clone()),
+        )]));
+        assert!(!Arc::ptr_eq(
+            &shadow,
+            &factory.state_for("shadow", &config).unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_divergent_mirror_is_counted() {
+        let (body, mismatches) = run(
+            "HTTP/1.1 500 Internal Server Error\r\nx-version: 2\r\nContent-Length: 6\r\n\r\nbroken",
+        )
+        .await;
+        assert_eq!(body, Bytes::from_static(b"primary"));
+        assert_eq!(mismatches, 1);
+    }
+
+    #[tokio::test]
+    async fn test_matching_mirror_is_not_counted() {
+        let (body, mismatches) =
+            run("HTTP/1.1 200 OK\r\nx-version: 1\r\nContent-Length: 7\r\n\r\nprimary").await;
+        assert_eq!(body, Bytes::from_static(b"primary"));
+        assert_eq!(mismatches, 0);
+    }
+}
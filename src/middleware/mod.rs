@@ -1,3 +1,4 @@
+use crate::error::BoxError;
 use async_trait::async_trait;
 use http_body_util::combinators::BoxBody;
 use hyper::body::Bytes;
@@ -6,32 +7,89 @@ use hyper::{Error, Request, Response};
 use std::convert::Infallible;
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Marks a request whose body was too large to buffer, it streams upstream and cannot be
+/// replayed.
+#[derive(Clone, Copy)]
+pub struct StreamedBody;
 
 mod access_logger;
 
 pub mod registry;
 
-mod constants;
+pub mod constants;
 
 mod add_prefix;
 
+mod api_key;
+
+mod basic_auth;
+
+mod cache;
+
+mod circuit_breaker;
+
+mod concurrency_limit;
+
+mod content_type;
+
+mod cors;
+
+mod headers;
+
+mod ip_filter;
+
+mod jwt;
+
+mod mirror;
+
+mod query_rewrite;
+
 mod rate_limiter;
 
+mod request_decompress;
+
 mod request_id;
 
+mod retry;
+
+mod rewrite;
+
+mod status_remap;
+
+mod when;
+
 pub use access_logger::AccessLogger;
 pub use add_prefix::AddPrefixFactory;
+pub use api_key::ApiKeyFactory;
+pub use basic_auth::{BasicAuthFactory, parse_password_hash};
+pub use cache::CacheFactory;
+pub use circuit_breaker::{CircuitBreakerFactory, CircuitSnapshot, Circuits};
+pub use concurrency_limit::ConcurrencyLimitFactory;
+pub use content_type::ContentTypeFactory;
+pub use cors::CorsFactory;
+pub use headers::HeadersFactory;
+pub use ip_filter::IpFilterFactory;
+pub use jwt::{JwtFactory, jwt_algorithms};
+pub use mirror::MirrorFactory;
+pub use query_rewrite::QueryRewriteFactory;
 pub use rate_limiter::RateLimiterFactory;
+pub use request_decompress::RequestDecompressFactory;
 pub use request_id::RequestID;
+pub use retry::RetryFactory;
+pub use rewrite::RewriteFactory;
+pub use status_remap::StatusRemapFactory;
+pub use when::WhenFactory;
 
 type Result<T> = std::result::Result<T, Infallible>;
 
 pub type RequestBody = BoxBody<Bytes, Error>;
 
-type ResponseBody = BoxBody<Bytes, Error>;
+type ResponseBody = BoxBody<Bytes, BoxError>;
 
 type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 
@@ -52,9 +110,41 @@ pub trait Middleware: Send + Sync {
     ) -> Result<Response<ResponseBody>>;
 }
 
+/// Time spent in each segment of a middleware chain, the handler being the last segment.
+#[derive(Default)]
+pub struct ChainTimings {
+    /// Time from entering a segment until it returned, summed over repeated runs
+    inclusive: Mutex<Vec<Duration>>,
+}
+
+impl ChainTimings {
+    fn record(&self, depth: usize, elapsed: Duration) {
+        let mut inclusive = self.inclusive.lock().unwrap();
+        if inclusive.len() <= depth {
+            inclusive.resize(depth + 1, Duration::ZERO);
+        }
+        inclusive[depth] += elapsed;
+    }
+
+    /// Time spent in each entered segment itself, excluding the segments it called.
+    pub fn breakdown(&self) -> Vec<Duration> {
+        let inclusive = self.inclusive.lock().unwrap();
+        inclusive
+            .iter()
+            .enumerate()
+            .map(|(depth, total)| {
+                total.saturating_sub(inclusive.get(depth + 1).copied().unwrap_or_default())
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone)]
 pub struct Next<'a> {
     handler: HandlerFunc,
     middlewares: &'a [Arc<dyn Middleware>],
+    timings: Option<Arc<ChainTimings>>,
+    depth: usize,
 }
 
 impl<'a> Next<'a> {
@@ -62,12 +152,37 @@ impl<'a> Next<'a> {
         Next {
             handler,
             middlewares,
+            timings: None,
+            depth: 0,
         }
     }
 
+    /// Records the time spent in every segment of the chain into `timings`.
+    pub fn with_timings(mut self, timings: Arc<ChainTimings>) -> Self {
+        self.timings = Some(timings);
+        self
+    }
+
     pub fn run(
         mut self,
         req: Request<RequestBody>,
+    ) -> BoxFuture<'a, Result<Response<ResponseBody>>> {
+        let Some(timings) = self.timings.clone() else {
+            return self.run_segment(req);
+        };
+        let depth = self.depth;
+        self.depth += 1;
+        Box::pin(async move {
+            let start = Instant::now();
+            let response = self.run_segment(req).await;
+            timings.record(depth, start.elapsed());
+            response
+        })
+    }
+
+    fn run_segment(
+        mut self,
+        req: Request<RequestBody>,
     ) -> BoxFuture<'a, Result<Response<ResponseBody>>> {
         if let Some((current, rest)) = self.middlewares.split_first() {
             self.middlewares = rest;
@@ -76,4 +191,79 @@ impl<'a> Next<'a> {
             Box::pin(async move { (self.handler)(req).await })
         }
     }
+
+    /// Owned copy of the rest of the chain, for running it after the current request completes.
+    pub fn to_owned(&self) -> OwnedNext {
+        OwnedNext {
+            handler: self.handler.clone(),
+            middlewares: self.middlewares.into(),
+        }
+    }
+}
+
+pub struct OwnedNext {
+    handler: HandlerFunc,
+    middlewares: Box<[Arc<dyn Middleware>]>,
+}
+
+impl OwnedNext {
+    pub async fn run(self, req: Request<RequestBody>) -> Result<Response<ResponseBody>> {
+        Next::new(self.handler, &self.middlewares).run(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Empty};
+
+    struct Sleep(Duration);
+
+    #[async_trait]
+    impl Middleware for Sleep {
+        async fn call(
+            &self,
+            req: Request<RequestBody>,
+            next: Next<'_>,
+        ) -> Result<Response<ResponseBody>> {
+            tokio::time::sleep(self.0).await;
+            next.run(req).await
+        }
+    }
+
+    fn empty<E: 'static>() -> BoxBody<Bytes, E> {
+        Empty::<Bytes>::new()
+            .map_err(|never| match never {})
+            .boxed()
+    }
+
+    #[tokio::test]
+    async fn test_timings_cover_every_chain_segment() {
+        let handler: HandlerFunc = Arc::new(|_req| {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                Ok(Response::new(empty()))
+            })
+        });
+        let middlewares: [Arc<dyn Middleware>; 2] = [
+            Arc::new(Sleep(Duration::from_millis(10))),
+            Arc::new(Sleep(Duration::from_millis(20))),
+        ];
+        let timings = Arc::new(ChainTimings::default());
+
+        let start = Instant::now();
+        Next::new(handler, &middlewares)
+            .with_timings(timings.clone())
+            .run(Request::new(empty()))
+            .await
+            .unwrap();
+        let elapsed = start.elapsed();
+
+        let breakdown = timings.breakdown();
+        assert_eq!(breakdown.len(), 3);
+        for (segment, expected) in breakdown.iter().zip([10, 20, 30]) {
+            assert!(*segment >= Duration::from_millis(expected), "{breakdown:?}");
+        }
+        assert!(breakdown.iter().sum::<Duration>() <= elapsed);
+    }
 }
@@ -0,0 +1,181 @@
+use crate::config::MiddlewareConfig;
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use async_trait::async_trait;
+use hyper::{Request, Response, Uri};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+pub struct QueryRewrite {
+    remove: HashSet<String>,
+    rename: HashMap<String, String>,
+    /// Set after removing and renaming, replacing every occurrence of the name
+    add: Vec<(String, String)>,
+}
+
+impl QueryRewrite {
+    /// Untouched parameters keep their original encoding and order, renamed ones keep their
+    /// value and position.
+    fn rewrite(&self, query: &str) -> String {
+        let mut params = Vec::new();
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (raw_name, raw_value) = match param.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (param, None),
+            };
+            let name = decode(raw_name);
+            if self.remove.contains(&name) {
+                continue;
+            }
+            let renamed = self.rename.get(&name);
+            let forwarded_name = renamed.unwrap_or(&name);
+            if self.add.iter().any(|(added, _)| added == forwarded_name) {
+                continue;
+            }
+            params.push(match (renamed, raw_value) {
+                (Some(new_name), Some(value)) => format!("{}={value}", encode(new_name)),
+                (Some(new_name), None) => encode(new_name),
+                (None, _) => param.to_string(),
+            });
+        }
+        params.extend(
+            self.add
+                .iter()
+                .map(|(name, value)| format!("{}={}", encode(name), encode(value))),
+        );
+        params.join("&")
+    }
+}
+
+fn decode(raw: &str) -> String {
+    form_urlencoded::parse(raw.as_bytes())
+        .next()
+        .map(|(name, _)| name.into_owned())
+        .unwrap_or_default()
+}
+
+fn encode(value: &str) -> String {
+    form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+#[async_trait]
+impl Middleware for QueryRewrite {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let mut req = req;
+        let query = self.rewrite(req.uri().query().unwrap_or_default());
+        let path_and_query = if query.is_empty() {
+            req.uri().path().to_string()
+        } else {
+            format!("{}?{query}", req.uri().path())
+        };
+
+        let mut parts = req.uri().clone().into_parts();
+        parts.path_and_query = Some(
+            path_and_query
+                .parse()
+                .expect("Invalid path and query parameters"),
+        );
+        let new_uri = Uri::from_parts(parts).expect("Invalid new URI");
+        *req.uri_mut() = new_uri;
+
+        next.run(req).await
+    }
+}
+
+pub struct QueryRewriteFactory;
+
+impl MiddlewareFactory for QueryRewriteFactory {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::QueryRewrite(cfg)) => {
+                let mut add: Vec<_> = cfg.add.into_iter().collect();
+                // Added parameters are appended in a stable order
+                add.sort();
+                Ok(Arc::new(QueryRewrite {
+                    remove: cfg.remove.into_iter().collect(),
+                    rename: cfg.rename,
+                    add,
+                }))
+            }
+            _ => Err(String::from("Invalid config for query rewrite middleware")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QueryRewriteConfig;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Bytes;
+
+    /// Answers with the path and query the upstream would receive.
+    fn echo_uri() -> HandlerFunc {
+        Arc::new(|req| {
+            Box::pin(async move {
+                let uri = req.uri().to_string();
+                Ok(Response::new(
+                    Full::new(Bytes::from(uri))
+                        .map_err(|never| match never {})
+                        .boxed(),
+                ))
+            })
+        })
+    }
+
+    async fn rewrite(uri: &str) -> String {
+        let config = MiddlewareConfig::QueryRewrite(QueryRewriteConfig {
+            add: HashMap::from([(String::from("api_version"), String::from("2"))]),
+            remove: vec![String::from("utm_source"), String::from("utm medium")],
+            rename: HashMap::from([(String::from("q"), String::from("search term"))]),
+        });
+        let middlewares = [QueryRewriteFactory
+            .create("query-rewrite", Some(config))
+            .unwrap()];
+        let req = Request::builder()
+            .uri(uri)
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        let response = Next::new(echo_uri(), &middlewares).run(req).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_adds_params_and_replaces_client_values() {
+        assert_eq!(rewrite("/items").await, "/items?api_version=2");
+        assert_eq!(
+            rewrite("/items?page=2&api_version=1&api_version=3").await,
+            "/items?page=2&api_version=2"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_removes_every_occurrence_of_encoded_names() {
+        assert_eq!(
+            rewrite("/items?utm_source=a&tag=x&utm%20medium=b&tag=y&utm_source=c").await,
+            "/items?tag=x&tag=y&api_version=2"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_renames_params_keeping_values_and_others_untouched() {
+        assert_eq!(
+            rewrite("/search?q=rust%20lang&sort=desc&q=hyper&flag&note=a+b%26c").await,
+            "/search?search+term=rust%20lang&sort=desc&search+term=hyper&flag&note=a+b%26c&api_version=2"
+        );
+    }
+}
@@ -27,15 +27,19 @@ impl RateLimiterFactory {
 }
 
 impl MiddlewareFactory for RateLimiterFactory {
-    fn create(&self, config: Option<MiddlewareConfig>) -> Arc<dyn Middleware> {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
         match config {
-            Some(MiddlewareConfig::RateLimit(cfg)) => Arc::new(TokenBucketRateLimiter::new(
+            Some(MiddlewareConfig::RateLimit(cfg)) => Ok(Arc::new(TokenBucketRateLimiter::new(
                 cfg.source,
                 cfg.limit,
                 cfg.period,
                 Arc::clone(&self.store),
-            )),
-            _ => panic!("Invalid config for rate limiter"),
+            ))),
+            _ => Err(String::from("Invalid config for rate limiter")),
         }
     }
 }
@@ -1,19 +1,98 @@
 use crate::config::MiddlewareConfig;
 use crate::middleware::constants::{
-    ACCESS_LOGGER_MIDDLEWARE, ADD_PREFIX_MIDDLEWARE, RATE_LIMIT_MIDDLEWARE, REQUEST_ID_MIDDLEWARE,
+    ACCESS_LOGGER_MIDDLEWARE, ADD_PREFIX_MIDDLEWARE, API_KEY_MIDDLEWARE, BASIC_AUTH_MIDDLEWARE,
+    CACHE_MIDDLEWARE, CIRCUIT_BREAKER_MIDDLEWARE, CONCURRENCY_LIMIT_MIDDLEWARE,
+    CONTENT_TYPE_MIDDLEWARE, CORS_MIDDLEWARE, HEADERS_MIDDLEWARE, IP_FILTER_MIDDLEWARE,
+    JWT_MIDDLEWARE, MIRROR_MIDDLEWARE, QUERY_REWRITE_MIDDLEWARE, RATE_LIMIT_MIDDLEWARE,
+    REQUEST_DECOMPRESS_MIDDLEWARE, REQUEST_ID_MIDDLEWARE, RETRY_MIDDLEWARE, REWRITE_MIDDLEWARE,
+    STATUS_REMAP_MIDDLEWARE, WHEN_MIDDLEWARE,
 };
 use crate::middleware::{
-    AccessLogger, AddPrefixFactory, Middleware, RateLimiterFactory, RequestID,
+    AccessLogger, AddPrefixFactory, ApiKeyFactory, BasicAuthFactory, CacheFactory,
+    CircuitBreakerFactory, Circuits, ConcurrencyLimitFactory, ContentTypeFactory, CorsFactory,
+    HeadersFactory, IpFilterFactory, JwtFactory, Middleware, MirrorFactory, QueryRewriteFactory,
+    RateLimiterFactory, RequestDecompressFactory, RequestID, RetryFactory, RewriteFactory,
+    StatusRemapFactory, WhenFactory,
 };
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, OnceLock};
 
 pub trait MiddlewareFactory: Send + Sync {
-    fn create(&self, config: Option<MiddlewareConfig>) -> Arc<dyn Middleware>;
+    /// Creates the middleware configured under `name`.
+    fn create(
+        &self,
+        name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String>;
+
+    /// Drops the state kept for middlewares no longer configured.
+    fn retain(&self, _middlewares: &HashMap<String, MiddlewareConfig>) {}
+}
+
+/// State of a stateful middleware by middleware name, so every chain built from the middleware
+/// shares it while identically configured middlewares keep their own. A changed config of the
+/// name starts over with fresh state.
+pub struct NamedStates<C, S> {
+    states: Mutex<HashMap<String, (C, Arc<S>)>>,
+}
+
+impl<C: Clone + PartialEq, S> NamedStates<C, S> {
+    pub fn new() -> Self {
+        NamedStates {
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get_or_insert_with(&self, name: &str, config: &C, init: impl FnOnce() -> S) -> Arc<S> {
+        match self.get_or_try_insert_with(name, config, || Ok::<_, Infallible>(init())) {
+            Ok(state) => state,
+            Err(never) => match never {},
+        }
+    }
+
+    pub fn get_or_try_insert_with<E>(
+        &self,
+        name: &str,
+        config: &C,
+        init: impl FnOnce() -> Result<S, E>,
+    ) -> Result<Arc<S>, E> {
+        let mut states = self.states.lock().unwrap();
+        if let Some((cfg, state)) = states.get(name)
+            && cfg == config
+        {
+            return Ok(state.clone());
+        }
+        let state = Arc::new(init()?);
+        states.insert(name.to_string(), (config.clone(), state.clone()));
+        Ok(state)
+    }
+
+    /// State of `name`, `None` until a middleware with this config was created.
+    pub fn get(&self, name: &str, config: &C) -> Option<Arc<S>> {
+        let states = self.states.lock().unwrap();
+        states
+            .get(name)
+            .filter(|(cfg, _)| cfg == config)
+            .map(|(_, state)| state.clone())
+    }
+
+    pub fn retain(&self, middlewares: &HashMap<String, MiddlewareConfig>) {
+        self.states
+            .lock()
+            .unwrap()
+            .retain(|name, _| middlewares.contains_key(name));
+    }
 }
 
 pub struct MiddlewareRegistry {
     factories: HashMap<&'static str, Box<dyn MiddlewareFactory>>,
+    /// Circuit breaker states, shared with the factory for the admin API
+    circuits: Arc<Circuits>,
+    /// Gateway upstream client, shared with the factories making requests of their own
+    http_client: Arc<OnceLock<Arc<reqwest::Client>>>,
+    /// Configured middlewares already built, as chains are created for every request
+    built: NamedStates<MiddlewareConfig, Arc<dyn Middleware>>,
 }
 
 impl MiddlewareRegistry {
@@ -22,45 +101,157 @@ impl MiddlewareRegistry {
         factories.insert(REQUEST_ID_MIDDLEWARE, Box::new(RequestID));
         factories.insert(ACCESS_LOGGER_MIDDLEWARE, Box::new(AccessLogger));
         factories.insert(ADD_PREFIX_MIDDLEWARE, Box::new(AddPrefixFactory));
+        factories.insert(QUERY_REWRITE_MIDDLEWARE, Box::new(QueryRewriteFactory));
+        factories.insert(REWRITE_MIDDLEWARE, Box::new(RewriteFactory));
+        factories.insert(HEADERS_MIDDLEWARE, Box::new(HeadersFactory));
+        factories.insert(BASIC_AUTH_MIDDLEWARE, Box::new(BasicAuthFactory));
+        let http_client = Arc::new(OnceLock::new());
+        factories.insert(
+            JWT_MIDDLEWARE,
+            Box::new(JwtFactory::new(http_client.clone())),
+        );
+        factories.insert(API_KEY_MIDDLEWARE, Box::new(ApiKeyFactory));
+        factories.insert(IP_FILTER_MIDDLEWARE, Box::new(IpFilterFactory));
         factories.insert(RATE_LIMIT_MIDDLEWARE, Box::new(RateLimiterFactory::new()));
+        factories.insert(STATUS_REMAP_MIDDLEWARE, Box::new(StatusRemapFactory));
+        factories.insert(WHEN_MIDDLEWARE, Box::new(WhenFactory));
+        factories.insert(CORS_MIDDLEWARE, Box::new(CorsFactory));
+        factories.insert(
+            REQUEST_DECOMPRESS_MIDDLEWARE,
+            Box::new(RequestDecompressFactory),
+        );
+        factories.insert(RETRY_MIDDLEWARE, Box::new(RetryFactory::new()));
+        factories.insert(CONTENT_TYPE_MIDDLEWARE, Box::new(ContentTypeFactory));
+        factories.insert(CACHE_MIDDLEWARE, Box::new(CacheFactory::new()));
+        factories.insert(MIRROR_MIDDLEWARE, Box::new(MirrorFactory::new()));
+        factories.insert(
+            CONCURRENCY_LIMIT_MIDDLEWARE,
+            Box::new(ConcurrencyLimitFactory::new()),
+        );
+        let circuits = Arc::new(Circuits::new());
+        factories.insert(
+            CIRCUIT_BREAKER_MIDDLEWARE,
+            Box::new(CircuitBreakerFactory::new(circuits.clone())),
+        );
+
+        MiddlewareRegistry {
+            factories,
+            circuits,
+            http_client,
+            built: NamedStates::new(),
+        }
+    }
+
+    pub fn circuits(&self) -> &Circuits {
+        &self.circuits
+    }
 
-        MiddlewareRegistry { factories }
+    /// Sets the gateway upstream client once it is built, the first one set is kept.
+    pub fn set_http_client(&self, http_client: Arc<reqwest::Client>) {
+        let _ = self.http_client.set(http_client);
     }
 
-    pub fn create_chain(&self, middlewares: &[&MiddlewareConfig]) -> Box<[Arc<dyn Middleware>]> {
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// Middlewares `create_chain` runs ahead of the configured ones, in order.
+    pub fn builtin_names(&self) -> impl Iterator<Item = &'static str> {
+        [REQUEST_ID_MIDDLEWARE, ACCESS_LOGGER_MIDDLEWARE]
+            .into_iter()
+            .filter(|name| self.factories.contains_key(name))
+    }
+
+    pub fn create_chain(
+        &self,
+        middlewares: &[(&str, &MiddlewareConfig)],
+    ) -> Result<Box<[Arc<dyn Middleware>]>, String> {
         let mut route_middlewares = vec![];
 
-        if let Some(request_id_middleware) = self
-            .factories
-            .get(REQUEST_ID_MIDDLEWARE)
-            .map(|factory| factory.create(None))
-        {
-            route_middlewares.push(request_id_middleware);
+        for name in self.builtin_names() {
+            route_middlewares.push(self.factories[name].create(name, None)?);
         }
 
-        if let Some(access_logger_middleware) = self
+        for &(name, middleware_config) in middlewares {
+            route_middlewares.push(self.create(name, middleware_config)?);
+        }
+
+        Ok(route_middlewares.into_boxed_slice())
+    }
+
+    /// Middleware configured under `name`, built once per config of the name.
+    pub fn create(
+        &self,
+        name: &str,
+        middleware_config: &MiddlewareConfig,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        let middleware = self
+            .built
+            .get_or_try_insert_with(name, middleware_config, || {
+                self.build(name, middleware_config)
+            })?;
+        Ok(Arc::clone(&middleware))
+    }
+
+    /// Builds a new middleware from its config, bypassing the cache `create` uses. Middlewares
+    /// wrapping another one build it with this, as they are cached under the same name.
+    pub fn build(
+        &self,
+        name: &str,
+        middleware_config: &MiddlewareConfig,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        let factory = self
             .factories
-            .get(ACCESS_LOGGER_MIDDLEWARE)
-            .map(|factory| factory.create(None))
-        {
-            route_middlewares.push(access_logger_middleware);
+            .get(middleware_config.name())
+            .ok_or_else(|| format!("No middleware registered for {}", middleware_config.name()))?;
+        factory.create(name, Some(middleware_config.clone()))
+    }
+
+    /// Drops the state of middlewares no longer in the running config.
+    pub fn retain(&self, middlewares: &HashMap<String, MiddlewareConfig>) {
+        self.built.retain(middlewares);
+        for factory in self.factories.values() {
+            factory.retain(middlewares);
         }
+    }
+}
 
-        let chain = middlewares
-            .iter()
-            .filter_map(|middleware_config| match middleware_config {
-                MiddlewareConfig::AddPrefix(cfg) => self
-                    .factories
-                    .get(ADD_PREFIX_MIDDLEWARE)
-                    .map(|factory| factory.create(Some(MiddlewareConfig::AddPrefix(cfg.clone())))),
-                MiddlewareConfig::RateLimit(cfg) => self
-                    .factories
-                    .get(RATE_LIMIT_MIDDLEWARE)
-                    .map(|factory| factory.create(Some(MiddlewareConfig::RateLimit(cfg.clone())))),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AddPrefixConfig;
+
+    #[test]
+    fn test_factory_rejects_mismatched_config() {
+        let config = MiddlewareConfig::AddPrefix(AddPrefixConfig {
+            prefix: String::from("/api"),
+        });
+        assert!(
+            RateLimiterFactory::new()
+                .create("api-prefix", Some(config))
+                .is_err()
+        );
+        assert!(AddPrefixFactory.create("api-prefix", None).is_err());
+    }
+
+    #[test]
+    fn test_middleware_is_built_once_per_config() {
+        let registry = MiddlewareRegistry::init();
+        let prefix = |prefix: &str| {
+            MiddlewareConfig::AddPrefix(AddPrefixConfig {
+                prefix: prefix.to_string(),
             })
-            .collect::<Box<[_]>>();
+        };
+
+        let first = registry.create("api-prefix", &prefix("/api")).unwrap();
+        let second = registry.create("api-prefix", &prefix("/api")).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let changed = registry.create("api-prefix", &prefix("/v2")).unwrap();
+        assert!(!Arc::ptr_eq(&first, &changed));
 
-        route_middlewares.extend(chain);
-        route_middlewares.into_boxed_slice()
+        registry.retain(&HashMap::new());
+        let rebuilt = registry.create("api-prefix", &prefix("/v2")).unwrap();
+        assert!(!Arc::ptr_eq(&changed, &rebuilt));
     }
 }
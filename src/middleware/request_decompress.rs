@@ -0,0 +1,223 @@
+use crate::config::{ContentEncoding, MiddlewareConfig};
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use crate::utils::response_with_status;
+use async_trait::async_trait;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH, HeaderValue};
+use hyper::{Request, Response, StatusCode};
+use std::io::Read;
+use std::sync::Arc;
+
+pub struct RequestDecompress {
+    encodings: Box<[ContentEncoding]>,
+    max_decompressed_size: usize,
+}
+
+enum DecompressError {
+    TooLarge,
+    Invalid(std::io::Error),
+}
+
+impl RequestDecompress {
+    fn decompress(
+        &self,
+        encoding: ContentEncoding,
+        compressed: &[u8],
+    ) -> Result<Vec<u8>, DecompressError> {
+        let decoder: Box<dyn Read + '_> = match encoding {
+            ContentEncoding::Gzip => Box::new(flate2::read::MultiGzDecoder::new(compressed)),
+            ContentEncoding::Br => Box::new(brotli::Decompressor::new(compressed, 4096)),
+        };
+        // Reading one byte past the limit tells an oversized body apart without inflating
+        // the rest of it
+        let mut decompressed = Vec::new();
+        decoder
+            .take(self.max_decompressed_size as u64 + 1)
+            .read_to_end(&mut decompressed)
+            .map_err(DecompressError::Invalid)?;
+        if decompressed.len() > self.max_decompressed_size {
+            return Err(DecompressError::TooLarge);
+        }
+        Ok(decompressed)
+    }
+}
+
+#[async_trait]
+impl Middleware for RequestDecompress {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let encoding = match req.headers().get(CONTENT_ENCODING).map(HeaderValue::to_str) {
+            None => return next.run(req).await,
+            Some(Ok(value)) if value.trim().eq_ignore_ascii_case("identity") => {
+                return next.run(req).await;
+            }
+            Some(Ok(value)) => ContentEncoding::from_header(value.trim())
+                .filter(|encoding| self.encodings.contains(encoding)),
+            Some(Err(_)) => None,
+        };
+        let Some(encoding) = encoding else {
+            return Ok(response_with_status(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        };
+
+        let (mut parts, body) = req.into_parts();
+        let compressed = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(err) => {
+                tracing::warn!("Error reading compressed request body: {err}");
+                return Ok(response_with_status(StatusCode::BAD_REQUEST));
+            }
+        };
+        let decompressed = match self.decompress(encoding, &compressed) {
+            Ok(decompressed) => decompressed,
+            Err(DecompressError::TooLarge) => {
+                return Ok(response_with_status(StatusCode::PAYLOAD_TOO_LARGE));
+            }
+            Err(DecompressError::Invalid(err)) => {
+                tracing::warn!("Invalid {encoding:?} request body: {err}");
+                return Ok(response_with_status(StatusCode::BAD_REQUEST));
+            }
+        };
+
+        parts.headers.remove(CONTENT_ENCODING);
+        parts
+            .headers
+            .insert(CONTENT_LENGTH, HeaderValue::from(decompressed.len()));
+        let body = Full::new(Bytes::from(decompressed))
+            .map_err(|never| match never {})
+            .boxed();
+        next.run(Request::from_parts(parts, body)).await
+    }
+}
+
+pub struct RequestDecompressFactory;
+
+impl MiddlewareFactory for RequestDecompressFactory {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::RequestDecompress(cfg)) => Ok(Arc::new(RequestDecompress {
+                encodings: cfg.encodings.into_boxed_slice(),
+                max_decompressed_size: cfg.max_decompressed_size,
+            })),
+            _ => Err(String::from(
+                "Invalid config for request decompress middleware",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RequestDecompressConfig;
+    use crate::middleware::HandlerFunc;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    /// Echoes the request body and its content-length header back.
+    fn echo_handler() -> HandlerFunc {
+        Arc::new(|req: Request<RequestBody>| {
+            Box::pin(async move {
+                let content_length = req.headers().get(CONTENT_LENGTH).cloned();
+                let encoding = req.headers().get(CONTENT_ENCODING).cloned();
+                let body = req.into_body().collect().await.unwrap().to_bytes();
+                let mut response =
+                    Response::new(Full::new(body).map_err(|never| match never {}).boxed());
+                if let Some(content_length) = content_length {
+                    response
+                        .headers_mut()
+                        .insert(CONTENT_LENGTH, content_length);
+                }
+                if let Some(encoding) = encoding {
+                    response.headers_mut().insert(CONTENT_ENCODING, encoding);
+                }
+                Ok(response)
+            })
+        })
+    }
+
+    async fn run(
+        encoding: &str,
+        body: Vec<u8>,
+        max_decompressed_size: usize,
+    ) -> Response<ResponseBody> {
+        let config = MiddlewareConfig::RequestDecompress(RequestDecompressConfig {
+            encodings: vec![ContentEncoding::Gzip, ContentEncoding::Br],
+            max_decompressed_size,
+        });
+        let middlewares = [RequestDecompressFactory
+            .create("request-decompress", Some(config))
+            .unwrap()];
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(CONTENT_ENCODING, encoding)
+            .header(CONTENT_LENGTH, body.len())
+            .body(
+                Full::new(Bytes::from(body))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        Next::new(echo_handler(), &middlewares)
+            .run(req)
+            .await
+            .unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn brotli(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 5, 22);
+        encoder.write_all(data).unwrap();
+        drop(encoder);
+        compressed
+    }
+
+    #[tokio::test]
+    async fn test_decompresses_gzip_and_brotli_bodies() {
+        let payload = b"{\"message\": \"hello hello hello\"}";
+        for (encoding, compressed) in [("gzip", gzip(payload)), ("br", brotli(payload))] {
+            let response = run(encoding, compressed, 1024).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(response.headers().get(CONTENT_ENCODING).is_none());
+            assert_eq!(
+                response.headers()[CONTENT_LENGTH],
+                HeaderValue::from(payload.len())
+            );
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, Bytes::from_static(payload));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_oversized_decompressed_body() {
+        // 64 KiB of zeros compress to well under 1 KiB
+        let bomb = gzip(&[0; 64 * 1024]);
+        let response = run("gzip", bomb, 1024).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unsupported_or_invalid_encoding() {
+        let response = run("zstd", b"data".to_vec(), 1024).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let response = run("gzip", b"not gzip".to_vec(), 1024).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
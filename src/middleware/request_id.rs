@@ -30,7 +30,11 @@ impl Middleware for RequestID {
 }
 
 impl MiddlewareFactory for RequestID {
-    fn create(&self, _config: Option<MiddlewareConfig>) -> Arc<dyn Middleware> {
-        Arc::new(RequestID)
+    fn create(
+        &self,
+        _name: &str,
+        _config: Option<MiddlewareConfig>,
+    ) -> std::result::Result<Arc<dyn Middleware>, String> {
+        Ok(Arc::new(RequestID))
     }
 }
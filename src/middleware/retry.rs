@@ -0,0 +1,528 @@
+use crate::config::{MiddlewareConfig, RetryBudgetConfig, RetryConfig, RetryJitter};
+use crate::middleware::registry::{MiddlewareFactory, NamedStates};
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody, StreamedBody};
+use crate::route_errors::UpstreamConnectionError;
+use crate::router::parse_method;
+use crate::utils::response_with_status;
+use async_trait::async_trait;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::RETRY_AFTER;
+use hyper::http::request::Parts;
+use hyper::{Method, Request, Response, StatusCode};
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Exponential backoff between attempts, optionally randomized so clients failing together do
+/// not retry in lockstep.
+struct Backoff {
+    base: Duration,
+    max: Duration,
+    jitter: RetryJitter,
+    previous: Duration,
+}
+
+impl Backoff {
+    fn new(base: Duration, max: Duration, jitter: RetryJitter) -> Self {
+        Backoff {
+            base,
+            max,
+            jitter,
+            previous: base,
+        }
+    }
+
+    /// Delay before retry number `retry`, starting at 0.
+    fn next_delay(&mut self, retry: u32) -> Duration {
+        let exponential = self
+            .base
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max);
+        match self.jitter {
+            RetryJitter::None => exponential,
+            // Uniform in [0, exponential]
+            RetryJitter::Full => random_between(Duration::ZERO, exponential),
+            // Uniform in [base, previous * 3], capped, each delay builds on the last one
+            RetryJitter::Decorrelated => {
+                let upper = self.previous.saturating_mul(3).max(self.base);
+                self.previous = random_between(self.base, upper).min(self.max);
+                self.previous
+            }
+        }
+    }
+}
+
+fn random_between(low: Duration, high: Duration) -> Duration {
+    let nanos = rand::rng().random_range(low.as_nanos() as u64..=high.as_nanos() as u64);
+    Duration::from_nanos(nanos)
+}
+
+/// Token bucket bounding retries to a fraction of requests, every request deposits `ratio`
+/// tokens and every retry withdraws one.
+pub struct RetryBudget {
+    ratio: f64,
+    burst: f64,
+    tokens: Mutex<f64>,
+}
+
+impl RetryBudget {
+    fn new(config: &RetryBudgetConfig) -> Self {
+        RetryBudget {
+            ratio: config.ratio,
+            burst: config.burst as f64,
+            tokens: Mutex::new(config.burst as f64),
+        }
+    }
+
+    fn record_request(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + self.ratio).min(self.burst);
+    }
+
+    fn try_withdraw(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct Retry {
+    attempts: u32,
+    base_backoff: Duration,
+    max_backoff: Duration,
+    jitter: RetryJitter,
+    budget: Option<Arc<RetryBudget>>,
+    statuses: Vec<StatusCode>,
+    methods: Vec<Method>,
+    connection_errors: bool,
+    max_retry_after: Duration,
+}
+
+impl Retry {
+    fn is_retriable(&self, response: &Response<ResponseBody>) -> bool {
+        if response
+            .extensions()
+            .get::<UpstreamConnectionError>()
+            .is_some()
+        {
+            return self.connection_errors;
+        }
+        self.statuses.contains(&response.status())
+    }
+}
+
+/// Delay asked for by the upstream through `Retry-After` on a 429 or 503, in seconds or as a date.
+fn retry_after(response: &Response<ResponseBody>) -> Option<Duration> {
+    if !matches!(
+        response.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    ) {
+        return None;
+    }
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+fn rebuild_request(parts: &Parts, body: &Bytes) -> Request<RequestBody> {
+    let mut request = Request::new(
+        Full::new(body.clone())
+            .map_err(|never| match never {})
+            .boxed(),
+    );
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers.clone();
+    request
+}
+
+#[async_trait]
+impl Middleware for Retry {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        // A streamed body cannot be replayed
+        if !self.methods.contains(req.method()) || req.extensions().get::<StreamedBody>().is_some()
+        {
+            return next.run(req).await;
+        }
+
+        // The body is buffered once so every attempt can send it again
+        let (parts, body) = req.into_parts();
+        let body = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(err) => {
+                tracing::warn!("Error reading request body for retry: {err}");
+                return Ok(response_with_status(StatusCode::BAD_REQUEST));
+            }
+        };
+
+        if let Some(budget) = &self.budget {
+            budget.record_request();
+        }
+        let mut backoff = Backoff::new(self.base_backoff, self.max_backoff, self.jitter);
+        let mut retry = 0;
+        loop {
+            let response = next.clone().run(rebuild_request(&parts, &body)).await?;
+            if retry >= self.attempts || !self.is_retriable(&response) {
+                return Ok(response);
+            }
+            let retry_after = retry_after(&response);
+            if let Some(retry_after) = retry_after
+                && retry_after > self.max_retry_after
+            {
+                tracing::warn!(
+                    "Not retrying {} {}, the upstream asked to wait {retry_after:?}",
+                    parts.method,
+                    parts.uri
+                );
+                return Ok(response);
+            }
+            // A widespread upstream failure must not multiply the load it is already under
+            if self
+                .budget
+                .as_ref()
+                .is_some_and(|budget| !budget.try_withdraw())
+            {
+                tracing::warn!(
+                    "Retry budget exhausted, not retrying {} {} after status {}",
+                    parts.method,
+                    parts.uri,
+                    response.status()
+                );
+                return Ok(response);
+            }
+
+            let delay = backoff
+                .next_delay(retry)
+                .max(retry_after.unwrap_or_default());
+            tracing::warn!(
+                "Retrying {} {} after status {} in {delay:?}",
+                parts.method,
+                parts.uri,
+                response.status()
+            );
+            tokio::time::sleep(delay).await;
+            retry += 1;
+        }
+    }
+}
+
+/// Keeps one budget per retry middleware so it is shared by every request going through it.
+pub struct RetryFactory {
+    budgets: NamedStates<RetryConfig, RetryBudget>,
+}
+
+impl RetryFactory {
+    pub fn new() -> Self {
+        RetryFactory {
+            budgets: NamedStates::new(),
+        }
+    }
+
+    fn budget_for(&self, name: &str, config: &RetryConfig) -> Option<Arc<RetryBudget>> {
+        let budget_config = config.budget.as_ref()?;
+        Some(
+            self.budgets
+                .get_or_insert_with(name, config, || RetryBudget::new(budget_config)),
+        )
+    }
+}
+
+impl MiddlewareFactory for RetryFactory {
+    fn create(
+        &self,
+        name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::Retry(cfg)) => {
+                let statuses = cfg
+                    .statuses
+                    .iter()
+                    .map(|&code| {
+                        StatusCode::from_u16(code)
+                            .map_err(|_| format!("Invalid status code {code}"))
+                    })
+                    .collect::<Result<_, String>>()?;
+                let methods = cfg
+                    .methods
+                    .iter()
+                    .map(|method| parse_method(method).ok_or(format!("Invalid method {method}")))
+                    .collect::<Result<_, String>>()?;
+                Ok(Arc::new(Retry {
+                    attempts: cfg.attempts,
+                    base_backoff: cfg.base_backoff,
+                    max_backoff: cfg.max_backoff,
+                    jitter: cfg.jitter,
+                    budget: self.budget_for(name, &cfg),
+                    statuses,
+                    methods,
+                    connection_errors: cfg.connection_errors,
+                    max_retry_after: cfg.max_retry_after,
+                }))
+            }
+            _ => Err(String::from("Invalid config for retry middleware")),
+        }
+    }
+
+    fn retain(&self, middlewares: &HashMap<String, MiddlewareConfig>) {
+        self.budgets.retain(middlewares);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::Empty;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const BASE: Duration = Duration::from_millis(100);
+    const MAX: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn test_backoff_without_jitter_is_exponential_and_capped() {
+        let mut backoff = Backoff::new(BASE, MAX, RetryJitter::None);
+        let delays = (0..6)
+            .map(|retry| backoff.next_delay(retry))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1600, 2000].map(Duration::from_millis)
+        );
+    }
+
+    #[test]
+    fn test_full_jitter_stays_within_exponential_bound_and_varies() {
+        let mut backoff = Backoff::new(BASE, MAX, RetryJitter::Full);
+        let delays = (0..200).map(|_| backoff.next_delay(3)).collect::<Vec<_>>();
+        assert!(
+            delays
+                .iter()
+                .all(|&delay| delay <= Duration::from_millis(800))
+        );
+        assert!(delays.iter().any(|&delay| delay != delays[0]));
+    }
+
+    #[test]
+    fn test_decorrelated_jitter_stays_within_bounds_and_varies() {
+        let mut backoff = Backoff::new(BASE, MAX, RetryJitter::Decorrelated);
+        let mut previous = BASE;
+        let mut delays = Vec::new();
+        for retry in 0..200 {
+            let delay = backoff.next_delay(retry);
+            assert!(delay >= BASE && delay <= MAX, "{delay:?}");
+            assert!(delay <= previous * 3, "{delay:?} after {previous:?}");
+            previous = delay;
+            delays.push(delay);
+        }
+        assert!(delays.iter().any(|&delay| delay != delays[0]));
+    }
+
+    fn failing_handler(failures: usize, calls: Arc<AtomicUsize>) -> HandlerFunc {
+        Arc::new(move |_req| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                let status = if call < failures {
+                    StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    StatusCode::OK
+                };
+                Ok(response_with_status(status))
+            })
+        })
+    }
+
+    fn retry_config(budget: Option<RetryBudgetConfig>) -> MiddlewareConfig {
+        MiddlewareConfig::Retry(RetryConfig {
+            attempts: 2,
+            base_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            jitter: RetryJitter::Full,
+            budget,
+            statuses: vec![502, 503, 504],
+            methods: ["GET", "HEAD", "PUT", "DELETE"].map(String::from).to_vec(),
+            connection_errors: true,
+            max_retry_after: Duration::from_secs(10),
+        })
+    }
+
+    async fn run(method: &str, failures: usize) -> (StatusCode, usize) {
+        let middlewares = [RetryFactory::new()
+            .create("retry", Some(retry_config(None)))
+            .unwrap()];
+        run_with(&middlewares, method, failures).await
+    }
+
+    async fn run_with(
+        middlewares: &[Arc<dyn Middleware>],
+        method: &str,
+        failures: usize,
+    ) -> (StatusCode, usize) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let req = Request::builder()
+            .method(method)
+            .uri("/")
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        let response = Next::new(failing_handler(failures, calls.clone()), middlewares)
+            .run(req)
+            .await
+            .unwrap();
+        (response.status(), calls.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn test_retries_idempotent_requests_until_success() {
+        assert_eq!(run("GET", 2).await, (StatusCode::OK, 3));
+        assert_eq!(run("GET", 5).await, (StatusCode::SERVICE_UNAVAILABLE, 3));
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_non_idempotent_requests() {
+        assert_eq!(run("POST", 1).await, (StatusCode::SERVICE_UNAVAILABLE, 1));
+    }
+
+    #[tokio::test]
+    async fn test_retries_stop_once_budget_is_exhausted() {
+        let factory = RetryFactory::new();
+        let budget = RetryBudgetConfig {
+            ratio: 0.25,
+            burst: 1,
+        };
+        let config = retry_config(Some(budget));
+        // Created per request like the gateway does, the budget is still shared
+        let named = |name| [factory.create(name, Some(config.clone())).unwrap()];
+        let middlewares = || named("retry");
+
+        // The banked token pays for one retry, the second one is refused
+        let (status, calls) = run_with(&middlewares(), "GET", 5).await;
+        assert_eq!((status, calls), (StatusCode::SERVICE_UNAVAILABLE, 2));
+        for _ in 0..3 {
+            assert_eq!(run_with(&middlewares(), "GET", 5).await.1, 1);
+        }
+        // An identically configured middleware has its own budget
+        assert_eq!(run_with(&named("other-retry"), "GET", 5).await.1, 2);
+        // Four requests refill a full token
+        assert_eq!(run_with(&middlewares(), "GET", 5).await.1, 2);
+        assert_eq!(run_with(&middlewares(), "GET", 5).await.1, 1);
+
+        // A reload removing the middleware drops its budget, it comes back with the banked token
+        factory.retain(&HashMap::from([(
+            String::from("other-retry"),
+            config.clone(),
+        )]));
+        assert_eq!(run_with(&middlewares(), "GET", 5).await.1, 2);
+    }
+
+    /// Answers with the given responses in turn, then with 200.
+    fn answering(responses: Vec<Response<ResponseBody>>, calls: Arc<AtomicUsize>) -> HandlerFunc {
+        let responses = Arc::new(Mutex::new(responses.into_iter()));
+        Arc::new(move |_req| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let response = responses.lock().unwrap().next();
+            Box::pin(async move { Ok(response.unwrap_or(response_with_status(StatusCode::OK))) })
+        })
+    }
+
+    async fn run_custom(
+        method: &str,
+        responses: Vec<Response<ResponseBody>>,
+    ) -> (StatusCode, usize, Duration) {
+        let config = MiddlewareConfig::Retry(RetryConfig {
+            attempts: 2,
+            base_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            jitter: RetryJitter::None,
+            budget: None,
+            statuses: vec![404, 429, 503],
+            methods: vec![String::from("get"), String::from("POST")],
+            connection_errors: false,
+            max_retry_after: Duration::from_secs(1),
+        });
+        let middlewares = [RetryFactory::new().create("retry", Some(config)).unwrap()];
+        let calls = Arc::new(AtomicUsize::new(0));
+        let req = Request::builder()
+            .method(method)
+            .uri("/")
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        let start = std::time::Instant::now();
+        let response = Next::new(answering(responses, calls.clone()), &middlewares)
+            .run(req)
+            .await
+            .unwrap();
+        (
+            response.status(),
+            calls.load(Ordering::SeqCst),
+            start.elapsed(),
+        )
+    }
+
+    fn retry_after(status: StatusCode, seconds: &str) -> Response<ResponseBody> {
+        let mut response = response_with_status(status);
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, seconds.parse().unwrap());
+        response
+    }
+
+    #[tokio::test]
+    async fn test_custom_statuses_and_methods_are_retried() {
+        let not_found = || response_with_status(StatusCode::NOT_FOUND);
+        let (status, calls, _) = run_custom("POST", vec![not_found(), not_found()]).await;
+        assert_eq!((status, calls), (StatusCode::OK, 3));
+        // Not among the configured methods or statuses
+        let (status, calls, _) = run_custom("PUT", vec![not_found()]).await;
+        assert_eq!((status, calls), (StatusCode::NOT_FOUND, 1));
+        let (status, calls, _) =
+            run_custom("GET", vec![response_with_status(StatusCode::BAD_GATEWAY)]).await;
+        assert_eq!((status, calls), (StatusCode::BAD_GATEWAY, 1));
+
+        let mut connection_error = response_with_status(StatusCode::SERVICE_UNAVAILABLE);
+        connection_error
+            .extensions_mut()
+            .insert(UpstreamConnectionError(String::from("connection refused")));
+        let (status, calls, _) = run_custom("GET", vec![connection_error]).await;
+        assert_eq!((status, calls), (StatusCode::SERVICE_UNAVAILABLE, 1));
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_is_honored_up_to_its_limit() {
+        let (status, calls, elapsed) =
+            run_custom("GET", vec![retry_after(StatusCode::TOO_MANY_REQUESTS, "1")]).await;
+        assert_eq!((status, calls), (StatusCode::OK, 2));
+        assert!(elapsed >= Duration::from_secs(1), "{elapsed:?}");
+
+        let (status, calls, _) = run_custom(
+            "GET",
+            vec![retry_after(StatusCode::SERVICE_UNAVAILABLE, "120")],
+        )
+        .await;
+        assert_eq!((status, calls), (StatusCode::SERVICE_UNAVAILABLE, 1));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::config::MiddlewareConfig;
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use async_trait::async_trait;
+use hyper::{Request, Response, Uri};
+use regex::Regex;
+use std::sync::Arc;
+
+pub struct Rewrite {
+    from: Regex,
+    /// Replacement of every match, `$1` or `${name}` refer to capture groups
+    to: String,
+}
+
+#[async_trait]
+impl Middleware for Rewrite {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let mut req = req;
+        let rewritten = self.from.replace_all(req.uri().path(), self.to.as_str());
+        let query_params = req
+            .uri()
+            .query()
+            .map(|q| format!("?{q}"))
+            .unwrap_or_default();
+
+        let new_uri = format!("{rewritten}{query_params}")
+            .parse()
+            .map_err(hyper::http::Error::from)
+            .and_then(|path_and_query| {
+                let mut parts = req.uri().clone().into_parts();
+                parts.path_and_query = Some(path_and_query);
+                Uri::from_parts(parts).map_err(hyper::http::Error::from)
+            });
+        match new_uri {
+            Ok(new_uri) => *req.uri_mut() = new_uri,
+            Err(err) => tracing::warn!(
+                "Path {} rewritten to an invalid path {rewritten}, forwarding it unchanged: {err}",
+                req.uri().path()
+            ),
+        }
+
+        next.run(req).await
+    }
+}
+
+pub struct RewriteFactory;
+
+impl MiddlewareFactory for RewriteFactory {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::Rewrite(cfg)) => {
+                let from = Regex::new(&cfg.from).map_err(|err| err.to_string())?;
+                Ok(Arc::new(Rewrite { from, to: cfg.to }))
+            }
+            _ => Err(String::from("Invalid config for rewrite middleware")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RewriteConfig;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Bytes;
+
+    /// Answers with the path and query the upstream would receive.
+    fn echo_uri() -> HandlerFunc {
+        Arc::new(|req| {
+            Box::pin(async move {
+                let uri = req.uri().to_string();
+                Ok(Response::new(
+                    Full::new(Bytes::from(uri))
+                        .map_err(|never| match never {})
+                        .boxed(),
+                ))
+            })
+        })
+    }
+
+    async fn rewrite(from: &str, to: &str, uri: &str) -> String {
+        let config = MiddlewareConfig::Rewrite(RewriteConfig {
+            from: String::from(from),
+            to: String::from(to),
+        });
+        let middlewares = [RewriteFactory.create("rewrite", Some(config)).unwrap()];
+        let req = Request::builder()
+            .uri(uri)
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        let response = Next::new(echo_uri(), &middlewares).run(req).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_capture_groups_are_substituted() {
+        assert_eq!(
+            rewrite("^/api/(.*)$", "/$1", "/api/users/42?page=2").await,
+            "/users/42?page=2"
+        );
+        assert_eq!(
+            rewrite(
+                "^/v(?<version>\\d+)/(?<rest>.*)$",
+                "/${rest}/v${version}",
+                "/v2/items"
+            )
+            .await,
+            "/items/v2"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_paths_pass_through() {
+        assert_eq!(
+            rewrite("^/api/(.*)$", "/$1", "/health?verbose").await,
+            "/health?verbose"
+        );
+    }
+
+    #[test]
+    fn test_invalid_regex_fails_creation() {
+        let config = MiddlewareConfig::Rewrite(RewriteConfig {
+            from: String::from("^/api/(.*$"),
+            to: String::from("/$1"),
+        });
+        assert!(RewriteFactory.create("rewrite", Some(config)).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use crate::config::MiddlewareConfig;
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use async_trait::async_trait;
+use hyper::{Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub struct StatusRemap {
+    mappings: HashMap<StatusCode, StatusCode>,
+}
+
+#[async_trait]
+impl Middleware for StatusRemap {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        let mut response = next.run(req).await?;
+        if let Some(&status) = self.mappings.get(&response.status()) {
+            *response.status_mut() = status;
+        }
+        Ok(response)
+    }
+}
+
+pub struct StatusRemapFactory;
+
+impl MiddlewareFactory for StatusRemapFactory {
+    fn create(
+        &self,
+        _name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::StatusRemap(cfg)) => {
+                let mappings = cfg
+                    .mappings
+                    .iter()
+                    .map(|(&from, &to)| {
+                        let from = StatusCode::from_u16(from)
+                            .map_err(|_| format!("Invalid status code {from}"))?;
+                        let to = StatusCode::from_u16(to)
+                            .map_err(|_| format!("Invalid status code {to}"))?;
+                        Ok((from, to))
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok(Arc::new(StatusRemap { mappings }))
+            }
+            _ => Err(String::from("Invalid config for status remap middleware")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StatusRemapConfig;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::Bytes;
+
+    fn handler_with_status(status: StatusCode) -> HandlerFunc {
+        Arc::new(move |_req| {
+            Box::pin(async move {
+                Ok(Response::builder()
+                    .status(status)
+                    .body(
+                        Empty::<Bytes>::new()
+                            .map_err(|never| match never {})
+                            .boxed(),
+                    )
+                    .unwrap())
+            })
+        })
+    }
+
+    async fn run(upstream_status: StatusCode) -> StatusCode {
+        let config = MiddlewareConfig::StatusRemap(StatusRemapConfig {
+            mappings: HashMap::from([(418, 503), (500, 502)]),
+        });
+        let middlewares = [StatusRemapFactory
+            .create("status-remap", Some(config))
+            .unwrap()];
+        let req = Request::builder()
+            .uri("/")
+            .body(
+                Empty::<Bytes>::new()
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap();
+        Next::new(handler_with_status(upstream_status), &middlewares)
+            .run(req)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_remaps_configured_status() {
+        assert_eq!(
+            run(StatusCode::IM_A_TEAPOT).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            run(StatusCode::INTERNAL_SERVER_ERROR).await,
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keeps_unmapped_status() {
+        assert_eq!(run(StatusCode::OK).await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::MIDDLEWARE_REGISTRY;
+use crate::config::{MiddlewareConfig, WhenConfig};
+use crate::middleware::registry::MiddlewareFactory;
+use crate::middleware::{Middleware, Next, RequestBody, ResponseBody};
+use async_trait::async_trait;
+use hyper::header::HeaderName;
+use hyper::{Method, Request, Response};
+use std::sync::Arc;
+
+struct Condition {
+    methods: Option<Box<[Method]>>,
+    path_prefix: Option<String>,
+    headers: Box<[(HeaderName, String)]>,
+}
+
+impl Condition {
+    fn from_config(cfg: &WhenConfig) -> Result<Self, String> {
+        let methods = cfg
+            .methods
+            .as_ref()
+            .map(|methods| {
+                methods
+                    .iter()
+                    .map(|method| {
+                        Method::from_bytes(method.to_uppercase().as_bytes())
+                            .map_err(|_| format!("Invalid method {method}"))
+                    })
+                    .collect::<Result<Box<[_]>, _>>()
+            })
+            .transpose()?;
+        let headers = cfg
+            .headers
+            .iter()
+            .flatten()
+            .map(|(name, value)| {
+                HeaderName::from_bytes(name.as_bytes())
+                    .map(|name| (name, value.clone()))
+                    .map_err(|_| format!("Invalid header name {name}"))
+            })
+            .collect::<Result<Box<[_]>, _>>()?;
+
+        Ok(Condition {
+            methods,
+            path_prefix: cfg.path_prefix.clone(),
+            headers,
+        })
+    }
+
+    fn matches<B>(&self, req: &Request<B>) -> bool {
+        let matches_method = self
+            .methods
+            .as_ref()
+            .is_none_or(|methods| methods.contains(req.method()));
+        let matches_path = self
+            .path_prefix
+            .as_ref()
+            .is_none_or(|prefix| req.uri().path().starts_with(prefix.as_str()));
+        let matches_headers = self.headers.iter().all(|(name, expected)| {
+            req.headers()
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .any(|value| value.contains(expected.as_str()))
+        });
+
+        matches_method && matches_path && matches_headers
+    }
+}
+
+pub struct When {
+    condition: Condition,
+    inner: Arc<dyn Middleware>,
+}
+
+#[async_trait]
+impl Middleware for When {
+    async fn call(
+        &self,
+        req: Request<RequestBody>,
+        next: Next<'_>,
+    ) -> crate::middleware::Result<Response<ResponseBody>> {
+        if self.condition.matches(&req) {
+            self.inner.call(req, next).await
+        } else {
+            next.run(req).await
+        }
+    }
+}
+
+pub struct WhenFactory;
+
+impl MiddlewareFactory for WhenFactory {
+    fn create(
+        &self,
+        name: &str,
+        config: Option<MiddlewareConfig>,
+    ) -> Result<Arc<dyn Middleware>, String> {
+        match config {
+            Some(MiddlewareConfig::When(cfg)) => Ok(Arc::new(When {
+                condition: Condition::from_config(&cfg)?,
+                // Stateful inner middlewares keep their state under the name of the `when`
+                inner: MIDDLEWARE_REGISTRY.build(name, &cfg.middleware)?,
+            })),
+            _ => Err(String::from("Invalid config for when middleware")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::HandlerFunc;
+    use http_body_util::combinators::BoxBody;
+    use http_body_util::{BodyExt, Empty};
+    use hyper::StatusCode;
+    use hyper::body::Bytes;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingMiddleware {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Middleware for CountingMiddleware {
+        async fn call(
+            &self,
+            req: Request<RequestBody>,
+            next: Next<'_>,
+        ) -> crate::middleware::Result<Response<ResponseBody>> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            next.run(req).await
+        }
+    }
+
+    fn empty_body<E: 'static>() -> BoxBody<Bytes, E> {
+        Empty::<Bytes>::new()
+            .map_err(|never| match never {})
+            .boxed()
+    }
+
+    fn ok_handler() -> HandlerFunc {
+        Arc::new(|_req| {
+            Box::pin(async {
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .body(empty_body())
+                    .unwrap())
+            })
+        })
+    }
+
+    fn build_when(calls: Arc<AtomicUsize>) -> Arc<dyn Middleware> {
+        let config = WhenConfig {
+            methods: Some(vec![String::from("POST")]),
+            path_prefix: Some(String::from("/api")),
+            headers: Some(HashMap::from([(
+                String::from("accept-encoding"),
+                String::from("br"),
+            )])),
+            middleware: Box::new(MiddlewareConfig::AddPrefix(
+                crate::config::AddPrefixConfig {
+                    prefix: String::from("/unused"),
+                },
+            )),
+        };
+        Arc::new(When {
+            condition: Condition::from_config(&config).unwrap(),
+            inner: Arc::new(CountingMiddleware { calls }),
+        })
+    }
+
+    async fn run(middleware: &Arc<dyn Middleware>, method: Method, path: &str) {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("accept-encoding", "gzip, br")
+            .body(empty_body())
+            .unwrap();
+        let middlewares = [middleware.clone()];
+        let response = Next::new(ok_handler(), &middlewares)
+            .run(req)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_applies_inner_middleware_when_condition_matches() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let when = build_when(calls.clone());
+        run(&when, Method::POST, "/api/users").await;
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_skips_inner_middleware_when_condition_fails() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let when = build_when(calls.clone());
+        run(&when, Method::GET, "/api/users").await;
+        run(&when, Method::POST, "/health").await;
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+    }
+}
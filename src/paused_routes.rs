@@ -0,0 +1,30 @@
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+/// Named routes paused through the admin API, answered with `http.paused_route_status` instead of
+/// being proxied until they are resumed. Pauses survive config reloads.
+pub struct PausedRoutes {
+    routes: RwLock<HashSet<String>>,
+}
+
+impl PausedRoutes {
+    pub fn new() -> Self {
+        PausedRoutes {
+            routes: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// False when the route was already paused.
+    pub fn pause(&self, route: &str) -> bool {
+        self.routes.write().unwrap().insert(route.to_string())
+    }
+
+    /// False when the route was not paused.
+    pub fn resume(&self, route: &str) -> bool {
+        self.routes.write().unwrap().remove(route)
+    }
+
+    pub fn is_paused(&self, route: &str) -> bool {
+        self.routes.read().unwrap().contains(route)
+    }
+}
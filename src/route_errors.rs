@@ -0,0 +1,201 @@
+use hyper::{Response, StatusCode};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+/// How many of the latest errors are kept per route.
+const RECENT_ERRORS_CAPACITY: usize = 16;
+
+/// Attached to the 502 answered when the upstream could not be reached or its response could
+/// not be read, so it is counted as a connection error rather than an upstream status.
+#[derive(Clone)]
+pub struct UpstreamConnectionError(pub String);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteErrorKind {
+    ClientError,
+    ServerError,
+    Connection,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentError {
+    #[serde(with = "humantime_serde")]
+    pub timestamp: SystemTime,
+    pub kind: RouteErrorKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RouteErrorsSnapshot {
+    pub client_errors: u64,
+    pub server_errors: u64,
+    pub connection_errors: u64,
+    /// Oldest first
+    pub recent: Vec<RecentError>,
+}
+
+/// Error counters of one route with a ring buffer of its latest error messages.
+#[derive(Default)]
+pub struct RouteErrorLog {
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+    connection_errors: AtomicU64,
+    recent: Mutex<VecDeque<RecentError>>,
+}
+
+impl RouteErrorLog {
+    /// Records the response of `request` (method and path) when it is an error.
+    pub fn record<B>(&self, request: &str, response: &Response<B>) {
+        let status = response.status();
+        let (kind, message) = match response.extensions().get::<UpstreamConnectionError>() {
+            Some(UpstreamConnectionError(err)) => (RouteErrorKind::Connection, err.clone()),
+            None if status.is_client_error() => {
+                (RouteErrorKind::ClientError, format!("answered {status}"))
+            }
+            None if status.is_server_error() => {
+                (RouteErrorKind::ServerError, format!("answered {status}"))
+            }
+            None => return,
+        };
+        self.push(kind, format!("{request}: {message}"));
+    }
+
+    /// Records an error answered before a response could be proxied.
+    pub fn record_status(&self, request: &str, status: StatusCode) {
+        let kind = if status.is_client_error() {
+            RouteErrorKind::ClientError
+        } else {
+            RouteErrorKind::ServerError
+        };
+        self.push(kind, format!("{request}: answered {status}"));
+    }
+
+    fn push(&self, kind: RouteErrorKind, message: String) {
+        let counter = match kind {
+            RouteErrorKind::ClientError => &self.client_errors,
+            RouteErrorKind::ServerError => &self.server_errors,
+            RouteErrorKind::Connection => &self.connection_errors,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_ERRORS_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(RecentError {
+            timestamp: SystemTime::now(),
+            kind,
+            message,
+        });
+    }
+
+    fn snapshot(&self) -> RouteErrorsSnapshot {
+        RouteErrorsSnapshot {
+            client_errors: self.client_errors.load(Ordering::Relaxed),
+            server_errors: self.server_errors.load(Ordering::Relaxed),
+            connection_errors: self.connection_errors.load(Ordering::Relaxed),
+            recent: self.recent.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+}
+
+/// Process wide error logs of named routes, kept across config reloads by route name.
+pub struct RouteErrors {
+    routes: RwLock<HashMap<String, Arc<RouteErrorLog>>>,
+}
+
+impl RouteErrors {
+    pub fn new() -> Self {
+        RouteErrors {
+            routes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn log_for(&self, route: &str) -> Arc<RouteErrorLog> {
+        if let Some(log) = self.routes.read().unwrap().get(route) {
+            return log.clone();
+        }
+        self.routes
+            .write()
+            .unwrap()
+            .entry(route.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Errors of `route`, empty when it has not failed yet.
+    pub fn snapshot(&self, route: &str) -> RouteErrorsSnapshot {
+        self.routes
+            .read()
+            .unwrap()
+            .get(route)
+            .map(|log| log.snapshot())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: StatusCode) -> Response<()> {
+        let mut response = Response::new(());
+        *response.status_mut() = status;
+        response
+    }
+
+    #[test]
+    fn test_errors_are_counted_by_kind() {
+        let log = RouteErrorLog::default();
+        log.record("GET /ok", &response(StatusCode::OK));
+        log.record("GET /missing", &response(StatusCode::NOT_FOUND));
+        log.record("GET /broken", &response(StatusCode::SERVICE_UNAVAILABLE));
+        let mut unreachable = response(StatusCode::BAD_GATEWAY);
+        unreachable
+            .extensions_mut()
+            .insert(UpstreamConnectionError(String::from("connection refused")));
+        log.record("GET /down", &unreachable);
+
+        let snapshot = log.snapshot();
+        assert_eq!(
+            (
+                snapshot.client_errors,
+                snapshot.server_errors,
+                snapshot.connection_errors
+            ),
+            (1, 1, 1)
+        );
+        let messages = snapshot
+            .recent
+            .iter()
+            .map(|error| error.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "GET /missing: answered 404 Not Found",
+                "GET /broken: answered 503 Service Unavailable",
+                "GET /down: connection refused",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recent_errors_are_bounded() {
+        let log = RouteErrorLog::default();
+        for index in 0..RECENT_ERRORS_CAPACITY + 4 {
+            log.record_status(&format!("GET /{index}"), StatusCode::BAD_REQUEST);
+        }
+        let snapshot = log.snapshot();
+        assert_eq!(snapshot.client_errors, RECENT_ERRORS_CAPACITY as u64 + 4);
+        assert_eq!(snapshot.recent.len(), RECENT_ERRORS_CAPACITY);
+        assert_eq!(
+            snapshot.recent[0].message,
+            "GET /4: answered 400 Bad Request"
+        );
+    }
+}
@@ -1,19 +1,71 @@
-use crate::config::{GatewayConfig, TcpTlsMode, Upstream};
+use crate::config::{
+    AbTestConfig, CanaryConfig, GatewayConfig, HeadHandling, RouteConfig, RouteTimeoutConfig,
+    TcpTlsMode, TrailingSlash, Upstream,
+};
 use crate::error::RouterError;
-use crate::service::ServiceRegistry;
+use crate::lifecycle::Lifecycle;
+use crate::service::{Service, ServiceRegistry};
 use crate::{BoxedSlice, BoxedStr, SharedGatewayState};
+use hyper::Method;
+use regex::Regex;
+use std::collections::HashMap;
 use std::net::IpAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub struct HttpRoute {
+    name: Option<BoxedStr>,
     hosts: Option<BoxedSlice<BoxedStr>>,
     path: Option<BoxedStr>,
+    /// Compiled `path` when it is a `~` regex
+    path_regex: Option<Regex>,
+    /// Parsed `path` when it has `:name` segments
+    path_template: Option<PathTemplate>,
+    /// Any method when unset
+    methods: Option<BoxedSlice<Method>>,
+    /// Outranks the computed specificity, routes without one rank below all that have one
+    priority: Option<i32>,
     listeners: BoxedSlice<BoxedStr>,
     service: BoxedStr,
     middlewares: BoxedSlice<BoxedStr>,
+    timeout: Option<RouteTimeoutConfig>,
+    request_body_timeout: Option<Duration>,
+    max_request_body_size: Option<usize>,
+    request_body_buffer_threshold: Option<usize>,
+    key: BoxedStr,
+    upstream_template: Option<UpstreamTemplate>,
+    trailing_slash: TrailingSlash,
+    head: HeadHandling,
+    canary: Option<CanaryConfig>,
+    ab_test: Option<AbTestConfig>,
 }
 
 impl HttpRoute {
+    /// Identifies the route in metrics, the hosts followed by the path pattern.
+    pub fn get_key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn get_timeout(&self) -> Option<&RouteTimeoutConfig> {
+        self.timeout.as_ref()
+    }
+
+    pub fn get_request_body_timeout(&self) -> Option<Duration> {
+        self.request_body_timeout
+    }
+
+    pub fn get_max_request_body_size(&self) -> Option<usize> {
+        self.max_request_body_size
+    }
+
+    pub fn get_request_body_buffer_threshold(&self) -> Option<usize> {
+        self.request_body_buffer_threshold
+    }
+
     pub fn get_service(&self) -> &str {
         &self.service
     }
@@ -21,6 +73,224 @@ impl HttpRoute {
     pub fn get_middlewares(&self) -> &[BoxedStr] {
         self.middlewares.as_ref()
     }
+
+    pub fn get_upstream_template(&self) -> Option<&UpstreamTemplate> {
+        self.upstream_template.as_ref()
+    }
+
+    pub fn get_head_handling(&self) -> HeadHandling {
+        self.head
+    }
+
+    pub fn get_canary(&self) -> Option<&CanaryConfig> {
+        self.canary.as_ref()
+    }
+
+    pub fn get_ab_test(&self) -> Option<&AbTestConfig> {
+        self.ab_test.as_ref()
+    }
+
+    /// Whether the route serves `method`, a route allowing `GET` serves `HEAD` as well.
+    fn allows_method(&self, method: &Method) -> bool {
+        self.methods.as_ref().is_none_or(|methods| {
+            methods.contains(method) || (method == Method::HEAD && methods.contains(&Method::GET))
+        })
+    }
+
+    /// Breaks ties between routes matching on the same criteria, an exact path beats one with
+    /// `:name` segments, which beats a regex, which beats a wildcard.
+    fn path_specificity(&self) -> u8 {
+        match (&self.path_template, &self.path_regex, self.path.as_deref()) {
+            (Some(_), _, _) => 2,
+            (None, Some(_), _) => 1,
+            (None, None, Some(path)) if !path.ends_with("/*") => 3,
+            _ => 0,
+        }
+    }
+
+    /// The configured path to redirect `path` to when the route canonicalizes trailing slashes.
+    pub fn get_redirect_path(&self, path: &str) -> Option<&str> {
+        match (&self.trailing_slash, self.path.as_deref()) {
+            (TrailingSlash::Redirect, Some(route_path))
+                if self.path_regex.is_none()
+                    && self.path_template.is_none()
+                    && !route_path.ends_with("/*")
+                    && path != route_path =>
+            {
+                Some(route_path)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Values of the `:name` segments of the matched route path, as they appear in the request path.
+/// Handed to the middlewares in the request extensions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathParams(BoxedSlice<(BoxedStr, BoxedStr)>);
+
+impl<const N: usize> From<[(&str, &str); N]> for PathParams {
+    fn from(params: [(&str, &str); N]) -> Self {
+        PathParams(
+            params
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(param, _)| param.as_ref() == name)
+            .map(|(_, value)| value.as_ref())
+    }
+}
+
+enum PathSegment {
+    Literal(BoxedStr),
+    Param(BoxedStr),
+}
+
+/// Route path like `/users/:id/orders/:oid` matched segment by segment, `:name` segments match
+/// any non-empty segment and a trailing `/*` matches the rest of the path.
+struct PathTemplate {
+    segments: BoxedSlice<PathSegment>,
+    wildcard: bool,
+}
+
+impl PathTemplate {
+    /// `None` for paths without `:name` segments.
+    fn parse(path: &str) -> Option<Self> {
+        let (path, wildcard) = match path.strip_suffix("/*") {
+            Some(prefix) => (prefix, true),
+            None => (path, false),
+        };
+        let segments = path
+            .trim_start_matches('/')
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) if !name.is_empty() => PathSegment::Param(name.into()),
+                _ => PathSegment::Literal(segment.into()),
+            })
+            .collect::<BoxedSlice<_>>();
+        segments
+            .iter()
+            .any(|segment| matches!(segment, PathSegment::Param(_)))
+            .then_some(PathTemplate { segments, wildcard })
+    }
+
+    /// Captured params when `path` matches, a single trailing slash is allowed unless strict.
+    fn captures(&self, path: &str, trailing_slash: TrailingSlash) -> Option<PathParams> {
+        let mut request_segments = path.strip_prefix('/')?.split('/');
+        let mut params = Vec::new();
+        for segment in &self.segments {
+            let value = request_segments.next()?;
+            match segment {
+                PathSegment::Literal(literal) if literal.as_ref() != value => return None,
+                PathSegment::Literal(_) => {}
+                PathSegment::Param(_) if value.is_empty() => return None,
+                PathSegment::Param(name) => params.push((name.clone(), value.into())),
+            }
+        }
+        let rest = request_segments.collect::<Vec<_>>();
+        let matches = self.wildcard
+            || match trailing_slash {
+                TrailingSlash::Strict => rest.is_empty(),
+                TrailingSlash::Tolerant | TrailingSlash::Redirect => {
+                    rest.is_empty() || rest == [""]
+                }
+            };
+        matches.then(|| PathParams(params.into_boxed_slice()))
+    }
+}
+
+enum TemplatePart {
+    Literal(BoxedStr),
+    Host,
+    HostLabel(usize),
+}
+
+/// Upstream URL derived from the request host, `{host}` is the whole host and `{host_label_N}`
+/// its N-th dot separated label counted from the left.
+pub struct UpstreamTemplate {
+    parts: BoxedSlice<TemplatePart>,
+}
+
+impl UpstreamTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].into()));
+            }
+            let Some(len) = rest[start..].find('}') else {
+                return Err(format!("unclosed placeholder in {template}"));
+            };
+            let placeholder = &rest[start + 1..start + len];
+            let part = match placeholder.strip_prefix("host_label_") {
+                _ if placeholder == "host" => TemplatePart::Host,
+                Some(index) => index
+                    .parse()
+                    .map(TemplatePart::HostLabel)
+                    .map_err(|_| format!("invalid placeholder {{{placeholder}}}"))?,
+                None => return Err(format!("unknown placeholder {{{placeholder}}}")),
+            };
+            parts.push(part);
+            rest = &rest[start + len + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("unmatched '}}' in {template}"));
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.into()));
+        }
+        Ok(UpstreamTemplate {
+            parts: parts.into_boxed_slice(),
+        })
+    }
+
+    /// Highest host label the template refers to.
+    pub fn max_label(&self) -> Option<usize> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                TemplatePart::HostLabel(index) => Some(*index),
+                _ => None,
+            })
+            .max()
+    }
+
+    /// Upstream target for `host`, `None` when it lacks a referenced label or a label holds
+    /// characters that could change the meaning of the URL.
+    pub fn render(&self, host: &str) -> Option<String> {
+        let host = host
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let labels = host.split('.').collect::<Vec<_>>();
+        if labels.iter().any(|label| {
+            label.is_empty()
+                || !label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        }) {
+            return None;
+        }
+        let mut target = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(literal) => target.push_str(literal),
+                TemplatePart::Host => target.push_str(&host),
+                TemplatePart::HostLabel(index) => target.push_str(labels.get(*index)?),
+            }
+        }
+        Some(target)
+    }
 }
 
 pub struct TcpRoute {
@@ -47,11 +317,13 @@ pub struct Router {
 
 impl Router {
     pub fn new(gateway_config: Arc<GatewayConfig>, svc_registry: Arc<ServiceRegistry>) -> Self {
-        let http = gateway_config
+        let http: BoxedSlice<HttpRoute> = gateway_config
             .http
             .routes
             .iter()
-            .map(|route| HttpRoute {
+            .enumerate()
+            .map(|(index, route)| HttpRoute {
+                name: route.name.as_deref().map(BoxedStr::from),
                 hosts: route.hosts.clone().map(|hosts| {
                     hosts
                         .into_iter()
@@ -59,18 +331,47 @@ impl Router {
                         .collect()
                 }),
                 path: route.path.clone().map(|path| path.into_boxed_str()),
+                // Validated on load
+                path_regex: route
+                    .path
+                    .as_deref()
+                    .and_then(parse_path_regex)
+                    .and_then(Result::ok),
+                path_template: route
+                    .path
+                    .as_deref()
+                    .filter(|path| !path.starts_with('~'))
+                    .and_then(PathTemplate::parse),
+                // Validated on load
+                methods: route.methods.as_ref().map(|methods| {
+                    methods
+                        .iter()
+                        .filter_map(|method| parse_method(method))
+                        .collect()
+                }),
+                priority: route.priority,
                 listeners: route
                     .listeners
                     .clone()
                     .into_iter()
                     .map(|listener| listener.into_boxed_str())
                     .collect(),
-                service: route.service.clone().into_boxed_str(),
-                middlewares: route
-                    .middlewares
-                    .clone()
-                    .map(|mws| mws.into_iter().map(|m| m.into_boxed_str()).collect())
-                    .unwrap_or(Box::new([])),
+                service: route.service_name(index).into_boxed_str(),
+                middlewares: Self::effective_middlewares(&gateway_config, route),
+                timeout: route.timeout.clone(),
+                request_body_timeout: route.request_body_timeout,
+                max_request_body_size: route.max_request_body_size,
+                request_body_buffer_threshold: route.request_body_buffer_threshold,
+                key: route_key(route),
+                // Validated on load
+                upstream_template: route
+                    .upstream_template
+                    .as_deref()
+                    .and_then(|template| UpstreamTemplate::parse(template).ok()),
+                trailing_slash: route.trailing_slash,
+                head: route.head,
+                canary: route.canary.clone(),
+                ab_test: route.ab_test.clone(),
             })
             .collect();
 
@@ -90,6 +391,8 @@ impl Router {
             })
             .collect();
 
+        warn_ambiguous_routes(&http);
+
         Router {
             http,
             tcp,
@@ -97,32 +400,38 @@ impl Router {
         }
     }
 
+    /// Global middlewares not skipped by the route, followed by the route's own middlewares.
+    fn effective_middlewares(
+        gateway_config: &GatewayConfig,
+        route: &RouteConfig,
+    ) -> BoxedSlice<BoxedStr> {
+        let skipped = route.skip_middlewares.as_deref().unwrap_or_default();
+        gateway_config
+            .http
+            .global_middlewares
+            .iter()
+            .filter(|&name| !skipped.contains(name))
+            .chain(route.middlewares.iter().flatten())
+            .map(|name| name.clone().into_boxed_str())
+            .collect()
+    }
+
+    /// The most specific route serving the request with the values of its `:name` path segments.
     pub fn get_http_route(
         &self,
         host: &str,
         path: &str,
+        method: &Method,
         listener: &str,
-    ) -> Result<&HttpRoute, RouterError> {
-        let route = &self
-            .http
-            .iter()
-            .filter(|&route| {
-                let matches_listener = self.match_listener(listener, &route.listeners);
-
-                let matches_host = if let Some(router_hosts) = &route.hosts {
-                    self.match_host(host, router_hosts)
-                } else {
-                    true
-                };
-
-                let matches_path = if let Some(router_path) = &route.path {
-                    self.match_path(path, router_path)
-                } else {
-                    true
-                };
-
-                matches_listener && matches_host && matches_path
-            })
+    ) -> Result<(&HttpRoute, PathParams), RouterError> {
+        let mut path_matched = false;
+        let route = self
+            .matching_http_routes(host, path, listener)
+            .inspect(|_| path_matched = true)
+            .filter(|route| route.allows_method(method))
+            // `max_by_key` returns the last maximum, reversing makes the route declared first
+            // win among equally ranked matches
+            .rev()
             .max_by_key(|&route| {
                 let mut score = 0;
                 if route.hosts.is_some() {
@@ -131,10 +440,68 @@ impl Router {
                 if route.path.is_some() {
                     score += 1
                 }
-                score
+                if route.methods.is_some() {
+                    score += 1
+                }
+                // `None` orders below any explicit priority
+                (route.priority, score, route.path_specificity())
             });
 
-        route.ok_or(RouterError::NotFound)
+        match route {
+            Some(route) => {
+                let params = route
+                    .path_template
+                    .as_ref()
+                    .and_then(|template| template.captures(path, route.trailing_slash))
+                    .unwrap_or_default();
+                Ok((route, params))
+            }
+            None if path_matched => Err(RouterError::MethodNotAllowed),
+            None => Err(RouterError::NotFound),
+        }
+    }
+
+    /// Methods served for a request `get_http_route` answered with `MethodNotAllowed`.
+    pub fn get_allowed_methods(&self, host: &str, path: &str, listener: &str) -> Vec<Method> {
+        let mut allowed = Vec::new();
+        for route in self.matching_http_routes(host, path, listener) {
+            for method in route.methods.iter().flatten() {
+                if !allowed.contains(method) {
+                    allowed.push(method.clone());
+                }
+            }
+        }
+        allowed
+    }
+
+    /// Routes matching the request regardless of its method.
+    fn matching_http_routes(
+        &self,
+        host: &str,
+        path: &str,
+        listener: &str,
+    ) -> impl DoubleEndedIterator<Item = &HttpRoute> {
+        self.http.iter().filter(move |&route| {
+            let matches_listener = self.match_listener(listener, &route.listeners);
+
+            let matches_host = if let Some(router_hosts) = &route.hosts {
+                self.match_host(host, router_hosts)
+            } else {
+                true
+            };
+
+            let matches_path = if let Some(regex) = &route.path_regex {
+                regex.is_match(path)
+            } else if let Some(template) = &route.path_template {
+                template.captures(path, route.trailing_slash).is_some()
+            } else if let Some(router_path) = &route.path {
+                self.match_path(path, router_path, route.trailing_slash)
+            } else {
+                true
+            };
+
+            matches_listener && matches_host && matches_path
+        })
     }
 
     pub fn get_tcp_route(&self, listener: &str) -> Result<&TcpRoute, RouterError> {
@@ -145,15 +512,57 @@ impl Router {
         route.ok_or(RouterError::NotFound)
     }
 
-    pub fn get_http_upstream(&self, name: &str) -> Result<&Upstream, RouterError> {
+    pub fn get_http_upstream(
+        &self,
+        name: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<&Upstream, RouterError> {
+        let key = client_ip.map(|ip| ip.to_string());
         self.service_registry
-            .get_http_service_endpoint(name)
+            .get_http_service_endpoint(name, key.as_deref())
             .ok_or(RouterError::NoUpstream)
     }
 
-    pub fn get_tcp_upstream(&self, name: &str) -> Result<&Upstream, RouterError> {
+    /// Target of the upstream pinned to the connection for the service, the first request on the
+    /// connection selects and pins it. A pin to an upstream removed by a reload is replaced.
+    pub fn get_pinned_http_upstream(
+        &self,
+        name: &str,
+        client_ip: IpAddr,
+        affinity: &ConnectionAffinity,
+    ) -> Result<BoxedStr, RouterError> {
+        let mut pinned = affinity.lock().unwrap();
+        if let Some(target) = pinned.get(name)
+            && self
+                .get_http_service(name)
+                .is_some_and(|service| service.has_upstream(target))
+        {
+            return Ok(target.clone());
+        }
+        let target: BoxedStr = self
+            .get_http_upstream(name, Some(client_ip))?
+            .target
+            .as_str()
+            .into();
+        pinned.insert(name.into(), target.clone());
+        Ok(target)
+    }
+
+    pub fn get_http_service(&self, name: &str) -> Option<&Service> {
+        self.service_registry.get_http_service(name)
+    }
+
+    pub fn http_services(&self) -> impl Iterator<Item = &Service> {
+        self.service_registry.http_services()
+    }
+
+    pub fn get_tcp_upstream(
+        &self,
+        name: &str,
+        client_ip: IpAddr,
+    ) -> Result<&Upstream, RouterError> {
         self.service_registry
-            .get_tcp_service_endpoint(name)
+            .get_tcp_service_endpoint(name, Some(&client_ip.to_string()))
             .ok_or(RouterError::NoUpstream)
     }
 
@@ -171,7 +580,7 @@ impl Router {
         false
     }
 
-    fn match_path(&self, path: &str, router_path: &str) -> bool {
+    fn match_path(&self, path: &str, router_path: &str, trailing_slash: TrailingSlash) -> bool {
         if router_path.ends_with("/*") {
             let prefix = &router_path[..router_path.len() - 1];
             if path.ends_with('/') {
@@ -180,8 +589,18 @@ impl Router {
                 path.starts_with(&prefix[..prefix.len() - 1])
             }
         } else {
-            // check for both trailing slashes and exact match
-            path == router_path || path == format!("{router_path}/")
+            match trailing_slash {
+                TrailingSlash::Strict => path == router_path,
+                // check for both trailing slashes and exact match
+                TrailingSlash::Tolerant => path == router_path || path == format!("{router_path}/"),
+                // Either slash form, the one not configured is redirected
+                TrailingSlash::Redirect => {
+                    path == router_path
+                        || (path.len() > 1
+                            && path.trim_end_matches('/') == router_path.trim_end_matches('/')
+                            && path.len().abs_diff(router_path.len()) == 1)
+                }
+            }
         }
     }
 
@@ -190,11 +609,55 @@ impl Router {
     }
 }
 
+fn route_key(route: &RouteConfig) -> BoxedStr {
+    let hosts = route.hosts.as_deref().unwrap_or_default().join(",");
+    let path = route.path.as_deref().unwrap_or("/*");
+    format!("{hosts}{path}").into_boxed_str()
+}
+
+fn warn_ambiguous_routes(routes: &[HttpRoute]) {
+    for (index, route) in routes.iter().enumerate() {
+        for (other_index, other) in routes.iter().enumerate().skip(index + 1) {
+            let shares_listener = route
+                .listeners
+                .iter()
+                .any(|listener| other.listeners.contains(listener));
+            if shares_listener
+                && route.hosts == other.hosts
+                && route.path == other.path
+                && route.methods == other.methods
+                && route.priority == other.priority
+            {
+                tracing::warn!(
+                    "Routes #{index} ({}) and #{other_index} ({}) match the same requests, route #{index} takes precedence",
+                    route.service,
+                    other.service
+                );
+            }
+        }
+    }
+}
+
+/// Method named in a route config, `None` for an invalid token.
+pub fn parse_method(method: &str) -> Option<Method> {
+    Method::from_bytes(method.to_ascii_uppercase().as_bytes()).ok()
+}
+
+/// Regex of a route path written as `~pattern`, `None` for exact and wildcard paths.
+pub fn parse_path_regex(path: &str) -> Option<Result<Regex, regex::Error>> {
+    path.strip_prefix('~').map(Regex::new)
+}
+
+/// Upstream targets pinned to one downstream connection, keyed by service name.
+pub type ConnectionAffinity = Arc<Mutex<HashMap<BoxedStr, BoxedStr>>>;
+
 pub struct RouterContext {
     pub(crate) ip_addr: IpAddr,
     pub(crate) listener: String,
     pub(crate) http_client: Arc<reqwest::Client>,
     pub(crate) gateway_state: SharedGatewayState,
+    pub(crate) affinity: ConnectionAffinity,
+    pub(crate) lifecycle: &'static Lifecycle,
 }
 
 impl RouterContext {
@@ -203,12 +666,16 @@ impl RouterContext {
         listener: String,
         http_client: Arc<reqwest::Client>,
         gateway_state: SharedGatewayState,
+        affinity: ConnectionAffinity,
+        lifecycle: &'static Lifecycle,
     ) -> Self {
         RouterContext {
             ip_addr,
             listener,
             http_client,
             gateway_state,
+            affinity,
+            lifecycle,
         }
     }
 }
@@ -228,6 +695,18 @@ mod tests {
             addr: 127.0.0.1:8080
 
         http:
+          middlewares:
+            global-rate-limit:
+              rate_limit:
+                limit: 10
+                period: 1s
+
+            global-prefix:
+              add_prefix:
+                prefix: /internal
+
+          global_middlewares: [ global-rate-limit, global-prefix ]
+
           services:
             user-service:
               upstreams:
@@ -246,6 +725,80 @@ mod tests {
             - path: /new
               listeners: [ internal-main ]
               service: auth-service
+
+            - path: /dup
+              listeners: [ http-main ]
+              service: user-service
+
+            - path: /dup
+              listeners: [ http-main ]
+              service: auth-service
+
+            - path: /inline
+              listeners: [ http-main ]
+              skip_middlewares: [ global-rate-limit ]
+              upstreams:
+                - target: http://inline.service1:3000
+                  weight: 2
+                - target: http://inline.service2:3000
+
+            - path: /strict
+              listeners: [ http-main ]
+              service: user-service
+              trailing_slash: strict
+
+            - path: /canonical
+              listeners: [ http-main ]
+              service: user-service
+              trailing_slash: redirect
+
+            - path: /api/test/*
+              methods: [ POST, get ]
+              listeners: [ http-main ]
+              service: user-service
+
+            - path: /api/health
+              listeners: [ http-main ]
+              service: auth-service
+
+            - path: /orders
+              listeners: [ http-main ]
+              service: user-service
+
+            - path: /orders
+              methods: [ DELETE ]
+              listeners: [ http-main ]
+              service: auth-service
+
+            - path: /users/*
+              listeners: [ http-main ]
+              service: user-service
+
+            - path: '~^/users/\d+$'
+              listeners: [ http-main ]
+              service: auth-service
+
+            - path: /accounts/:id/orders/:oid
+              listeners: [ http-main ]
+              service: user-service
+
+            - path: /accounts/:id/files/*
+              listeners: [ http-main ]
+              service: auth-service
+
+            - path: /accounts/:id/orders/latest
+              listeners: [ http-main ]
+              service: auth-service
+
+            - hosts: [ priority.example.com ]
+              path: /priority
+              listeners: [ http-main ]
+              service: user-service
+
+            - path: /priority
+              priority: -1
+              listeners: [ http-main ]
+              service: auth-service
     "#;
 
     fn build_gateway_config() -> GatewayConfig {
@@ -259,7 +812,7 @@ mod tests {
 
     fn build_svc_registry() -> ServiceRegistry {
         let config = build_gateway_config();
-        ServiceRegistry::init(Arc::new(config))
+        ServiceRegistry::init(Arc::new(config)).unwrap()
     }
 
     fn build_router() -> Router {
@@ -269,100 +822,275 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::redundant_pattern_matching)]
     fn test_route_matches_with_host_and_path() {
         let router = build_router();
-        let route_result = router.get_http_route("api.example.com", "/v1/api", "http-main");
+        let route_result =
+            router.get_http_route("api.example.com", "/v1/api", &Method::GET, "http-main");
         assert!(
             matches!(route_result, Ok(_)),
             "This route should match to user-service"
         );
-        let route = route_result.unwrap();
+        let (route, _) = route_result.unwrap();
         assert_eq!(route.get_service(), "user-service");
     }
 
     #[test]
+    #[allow(clippy::redundant_pattern_matching)]
     fn test_wildcard_host_matches_user_service() {
         let router = build_router();
-        let route_result = router.get_http_route("some.api.example.com", "/v1", "http-main");
+        let route_result =
+            router.get_http_route("some.api.example.com", "/v1", &Method::GET, "http-main");
         assert!(
             matches!(route_result, Ok(_)),
             "This route should match to user-service"
         );
-        let route = route_result.unwrap();
+        let (route, _) = route_result.unwrap();
         assert_eq!(route.get_service(), "user-service");
     }
 
-    //     #[test]
-    //     fn test_route_matches_correct_path_and_method() {
-    //         let router = build_router();
-    //         let route_result = router
-    //             .get_route("/api/test", "POST")
-    //             .expect("Router should match path: /api/test and method: POST");
-    //         let upstream = route_result
-    //             .get_upstream()
-    //             .expect("Route should return upstream");
-    //         assert_eq!(upstream.target, "http://localhost:5000");
-    //     }
-    //
-    //     #[test]
-    //     fn test_route_rejects_wrong_method() {
-    //         let router = build_router();
-    //         let result = router.get_route("/api/test", "PUT");
-    //         assert!(matches!(result, Err(RouterError::MethodNotAllowed)));
-    //     }
-    //
-    //     #[test]
-    //     fn test_route_accepts_any_method_if_none_specified() {
-    //         let router = build_router();
-    //         for method in &["GET", "POST", "PUT", "DELETE"] {
-    //             let route = router
-    //                 .get_route("/api/health", method)
-    //                 .expect(&format!("Route should accept method {}", method));
-    //             let upstream = route.get_upstream().expect("Route should return upstream");
-    //             assert_eq!(upstream.target, "http://localhost:5001");
-    //         }
-    //     }
-    //
-    //     #[test]
-    //     fn test_route_not_found() {
-    //         let router = build_router();
-    //         let result = router.get_route("/nonexistent", "GET");
-    //         assert!(matches!(result, Err(RouterError::NotFound)));
-    //     }
-    //
-    //     #[test]
-    //     fn test_multiple_routes_distinct_paths() {
-    //         let router = build_router();
-    //
-    //         let test_result = router
-    //             .get_route("/api/test", "GET")
-    //             .expect("Router should match path: /api/test and method: GET");
-    //         let upstream = test_result
-    //             .get_upstream()
-    //             .expect("Route should return upstream");
-    //         assert_eq!(upstream.target, "http://localhost:5000");
-    //
-    //         let health_result = router
-    //             .get_route("/api/health", "POST")
-    //             .expect("Router should match path: /api/health and method: POST");
-    //         let upstream = health_result
-    //             .get_upstream()
-    //             .expect("Route should return upstream");
-    //         assert_eq!(upstream.target, "http://localhost:5001");
-    //     }
-    //
-    //     #[test]
-    //     fn test_prefix_path_matches() {
-    //         let router = build_router();
-    //         let exact_match = router.get_route("/api/test", "GET");
-    //         let trailing_slash_match = router.get_route("/api/test/", "GET");
-    //         let wildcard_match = router.get_route("/api/test/new", "GET");
-    //
-    //         assert!(exact_match.is_ok(), "Expected exact match to succeed");
-    //         assert!(
-    //             trailing_slash_match.is_ok(),
-    //             "Expected trailing slash match to succeed"
-    //         );
-    //         assert!(wildcard_match.is_ok(), "Expected wildcard match to succeed");
-    //     }
+    #[test]
+    fn test_inline_upstream_route_resolves_anonymous_service() {
+        let router = build_router();
+        let route = router
+            .get_http_route("localhost", "/inline", &Method::GET, "http-main")
+            .expect("This route should match the inline upstreams")
+            .0;
+        assert_eq!(route.get_service(), "@route/4");
+
+        let targets = (0..3)
+            .map(|_| router.get_http_upstream(route.get_service(), None).unwrap())
+            .map(|upstream| upstream.target.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            [
+                "http://inline.service1:3000",
+                "http://inline.service2:3000",
+                "http://inline.service1:3000"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_equally_specific_routes_resolve_to_first_declared() {
+        let router = build_router();
+        for _ in 0..10 {
+            let route = router
+                .get_http_route("localhost", "/dup", &Method::GET, "http-main")
+                .unwrap()
+                .0;
+            assert_eq!(route.get_service(), "user-service");
+        }
+    }
+
+    #[test]
+    fn test_explicit_priority_outranks_specificity() {
+        let router = build_router();
+        for host in ["localhost", "priority.example.com"] {
+            for _ in 0..10 {
+                let (route, _) = router
+                    .get_http_route(host, "/priority", &Method::GET, "http-main")
+                    .unwrap();
+                // Even a negative priority ranks above the more specific host route
+                assert_eq!(route.get_service(), "auth-service");
+            }
+        }
+    }
+
+    #[test]
+    fn test_trailing_slash_modes() {
+        let router = build_router();
+        let route = |path| {
+            router
+                .get_http_route("localhost", path, &Method::GET, "http-main")
+                .ok()
+                .map(|(route, _)| route)
+        };
+
+        assert!(route("/strict").is_some());
+        assert!(route("/strict/").is_none());
+
+        assert!(route("/dup").is_some());
+        assert!(route("/dup/").is_some());
+
+        let canonical = route("/canonical").unwrap();
+        assert_eq!(canonical.get_redirect_path("/canonical"), None);
+        let slashed = route("/canonical/").unwrap();
+        assert_eq!(slashed.get_redirect_path("/canonical/"), Some("/canonical"));
+        assert!(route("/canonical//").is_none());
+    }
+
+    #[test]
+    fn test_route_skips_global_middleware() {
+        let router = build_router();
+        let route = router
+            .get_http_route("api.example.com", "/v1/users", &Method::GET, "http-main")
+            .unwrap()
+            .0;
+        assert_eq!(
+            route.get_middlewares(),
+            ["global-rate-limit".into(), "global-prefix".into()]
+        );
+
+        let route = router
+            .get_http_route("localhost", "/inline", &Method::GET, "http-main")
+            .unwrap()
+            .0;
+        assert_eq!(route.get_middlewares(), ["global-prefix".into()]);
+    }
+
+    #[test]
+    fn test_route_matches_correct_path_and_method() {
+        let router = build_router();
+        let route = router
+            .get_http_route("localhost", "/api/test", &Method::POST, "http-main")
+            .expect("Router should match path: /api/test and method: POST")
+            .0;
+        assert_eq!(route.get_service(), "user-service");
+    }
+
+    #[test]
+    fn test_route_rejects_wrong_method() {
+        let router = build_router();
+        let result = router.get_http_route("localhost", "/api/test", &Method::PUT, "http-main");
+        assert!(matches!(result, Err(RouterError::MethodNotAllowed)));
+        assert_eq!(
+            router.get_allowed_methods("localhost", "/api/test", "http-main"),
+            [Method::POST, Method::GET]
+        );
+    }
+
+    #[test]
+    fn test_route_allowing_get_serves_head() {
+        let router = build_router();
+        let route = router
+            .get_http_route("localhost", "/api/test", &Method::HEAD, "http-main")
+            .expect("Route allowing GET should accept HEAD")
+            .0;
+        assert_eq!(route.get_service(), "user-service");
+    }
+
+    #[test]
+    fn test_route_accepts_any_method_if_none_specified() {
+        let router = build_router();
+        for method in [Method::GET, Method::POST, Method::PUT, Method::DELETE] {
+            let route = router
+                .get_http_route("localhost", "/api/health", &method, "http-main")
+                .unwrap_or_else(|_| panic!("Route should accept method {method}"))
+                .0;
+            assert_eq!(route.get_service(), "auth-service");
+        }
+    }
+
+    #[test]
+    fn test_method_qualified_route_outranks_catch_all() {
+        let router = build_router();
+        let route = |method| {
+            router
+                .get_http_route("localhost", "/orders", &method, "http-main")
+                .unwrap()
+                .0
+                .get_service()
+        };
+        assert_eq!(route(Method::DELETE), "auth-service");
+        assert_eq!(route(Method::GET), "user-service");
+    }
+
+    #[test]
+    fn test_route_not_found() {
+        let router = build_router();
+        let result = router.get_http_route("localhost", "/nonexistent", &Method::GET, "http-main");
+        assert!(matches!(result, Err(RouterError::NotFound)));
+    }
+
+    #[test]
+    fn test_multiple_routes_distinct_paths() {
+        let router = build_router();
+
+        let test_route = router
+            .get_http_route("localhost", "/api/test", &Method::GET, "http-main")
+            .expect("Router should match path: /api/test and method: GET")
+            .0;
+        assert_eq!(test_route.get_service(), "user-service");
+
+        let health_route = router
+            .get_http_route("localhost", "/api/health", &Method::POST, "http-main")
+            .expect("Router should match path: /api/health and method: POST")
+            .0;
+        assert_eq!(health_route.get_service(), "auth-service");
+    }
+
+    #[test]
+    fn test_prefix_path_matches() {
+        let router = build_router();
+        let route = |path| router.get_http_route("localhost", path, &Method::GET, "http-main");
+
+        assert!(
+            route("/api/test").is_ok(),
+            "Expected exact match to succeed"
+        );
+        assert!(
+            route("/api/test/").is_ok(),
+            "Expected trailing slash match to succeed"
+        );
+        assert!(
+            route("/api/test/new").is_ok(),
+            "Expected wildcard match to succeed"
+        );
+    }
+
+    #[test]
+    fn test_regex_path_matches_numeric_ids() {
+        let router = build_router();
+        let service = |path| {
+            router
+                .get_http_route("localhost", path, &Method::GET, "http-main")
+                .map(|(route, _)| route.get_service().to_string())
+        };
+
+        // The regex outranks the wildcard declared before it
+        assert_eq!(service("/users/42").unwrap(), "auth-service");
+        assert_eq!(service("/users/me").unwrap(), "user-service");
+        assert_eq!(service("/users/42/orders").unwrap(), "user-service");
+    }
+
+    #[test]
+    fn test_path_params_are_captured() {
+        let router = build_router();
+        let route = |path| {
+            router
+                .get_http_route("localhost", path, &Method::GET, "http-main")
+                .map(|(route, params)| (route.get_service(), params))
+        };
+
+        let (service, params) = route("/accounts/7/orders/99").unwrap();
+        assert_eq!(service, "user-service");
+        assert_eq!(params, PathParams::from([("id", "7"), ("oid", "99")]));
+        assert_eq!(
+            route("/accounts/7/orders/99/").unwrap().1.get("oid"),
+            Some("99")
+        );
+
+        // The trailing wildcard matches any rest of the path
+        for path in [
+            "/accounts/7/files",
+            "/accounts/7/files/",
+            "/accounts/7/files/a/b.txt",
+        ] {
+            let (service, params) = route(path).unwrap();
+            assert_eq!(service, "auth-service");
+            assert_eq!(params, PathParams::from([("id", "7")]));
+        }
+
+        // Params match exactly one non-empty segment
+        assert!(route("/accounts//orders/99").is_err());
+        assert!(route("/accounts/7/orders/99/items").is_err());
+
+        // Templates are equally specific so the first declared wins, other routes capture nothing
+        let (service, params) = route("/accounts/7/orders/latest").unwrap();
+        assert_eq!(service, "user-service");
+        assert_eq!(params.get("oid"), Some("latest"));
+        assert_eq!(route("/dup").unwrap().1, PathParams::default());
+    }
 }
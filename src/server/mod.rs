@@ -1,14 +1,19 @@
-use crate::SharedGatewayState;
-use crate::config::{Listener, Protocol};
+use crate::config::{Listener, Protocol, TcpOptions};
 use crate::server::http::{handle_https, serve_http_connection};
 use crate::server::tcp::handle_tcp_client;
+use crate::{HEALTH_CHECKS, LIFECYCLE, SharedGatewayState};
+use socket2::{SockRef, TcpKeepalive};
 use std::io;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tokio_rustls::TlsAcceptor;
 use tokio_util::sync::CancellationToken;
 
-pub use tls::init_rustls_server_config;
+pub(crate) use tls::handshake_failure_reason;
+pub use tls::{init_rustls_server_config, load_certified_key};
 
 mod tls;
 
@@ -23,7 +28,21 @@ pub async fn run_tcp_listener(
     gateway_state: SharedGatewayState,
     cancel_token: CancellationToken,
 ) -> io::Result<()> {
-    let listener = TcpListener::bind(listener_cfg.addr).await?;
+    // Unbound until ready, so clients are refused rather than routed to cold upstreams
+    if let Some(max_wait) = listener_cfg.wait_for_healthy_upstreams {
+        tracing::info!(
+            "Listener `{}` waits for healthy upstreams",
+            listener_cfg.name
+        );
+        let ready = HEALTH_CHECKS
+            .wait_for_listener(&gateway_state, &listener_cfg.name, max_wait, &cancel_token)
+            .await;
+        if !ready {
+            return Ok(());
+        }
+    }
+    let listeners = bind_listeners(&listener_cfg)?;
+    let handshake_limiter = Arc::new(Semaphore::new(listener_cfg.max_concurrent_handshakes));
     match listener_cfg.protocol {
         Protocol::Http => tracing::info!(
             "Listener `{}` is running on http://{}",
@@ -42,14 +61,78 @@ pub async fn run_tcp_listener(
         ),
     }
 
+    if listeners.len() > 1 {
+        tracing::info!(
+            "Listener `{}` accepts on {} SO_REUSEPORT sockets",
+            listener_cfg.name,
+            listeners.len()
+        );
+    }
+
+    let listener_cfg = Arc::new(listener_cfg);
+    let mut acceptors = JoinSet::new();
+    for listener in listeners {
+        acceptors.spawn(accept_connections(
+            listener,
+            listener_cfg.clone(),
+            tls_acceptor.clone(),
+            handshake_limiter.clone(),
+            http_client.clone(),
+            gateway_state.clone(),
+            cancel_token.clone(),
+        ));
+    }
+    while acceptors.join_next().await.is_some() {}
+    tracing::info!("Shutdown received on listener `{}`", listener_cfg.name);
+
+    Ok(())
+}
+
+/// Binds the listener sockets, one per acceptor sharing the address through `SO_REUSEPORT`.
+fn bind_listeners(listener_cfg: &Listener) -> io::Result<Vec<TcpListener>> {
+    let count = listener_cfg.acceptors.effective_count();
+    if count == 1 {
+        return Ok(vec![bind_listener(
+            &listener_cfg.name,
+            listener_cfg.addr,
+            &listener_cfg.tcp,
+        )?]);
+    }
+
+    let options = TcpOptions {
+        reuse_port: Some(true),
+        ..listener_cfg.tcp.clone()
+    };
+    let first = bind_listener(&listener_cfg.name, listener_cfg.addr, &options)?;
+    // Port 0 is resolved by the first bind, the others must join the same port
+    let addr = first.local_addr()?;
+    let mut listeners = Vec::with_capacity(count);
+    listeners.push(first);
+    for _ in 1..count {
+        listeners.push(bind_listener(&listener_cfg.name, addr, &options)?);
+    }
+    Ok(listeners)
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    listener_cfg: Arc<Listener>,
+    tls_acceptor: Option<TlsAcceptor>,
+    handshake_limiter: Arc<Semaphore>,
+    http_client: Arc<reqwest::Client>,
+    gateway_state: SharedGatewayState,
+    cancel_token: CancellationToken,
+) {
     loop {
         tokio::select! {
             maybe_conn = listener.accept() => {
                 match maybe_conn {
                     Ok((stream, client_addr)) => {
+                        apply_stream_options(&stream, &listener_cfg.tcp);
                         let protocol = listener_cfg.protocol.clone();
                         let listener_name = listener_cfg.name.clone();
                         let tls_acceptor = tls_acceptor.clone();
+                        let handshake_limiter = handshake_limiter.clone();
                         let http_client = http_client.clone();
                         let gateway_state = gateway_state.clone();
                         tokio::spawn(async move {
@@ -60,7 +143,8 @@ pub async fn run_tcp_listener(
                                         client_addr,
                                         listener_name,
                                         http_client,
-                                        gateway_state
+                                        gateway_state,
+                                        &LIFECYCLE
                                     ).await;
                                 },
                                 Protocol::Https => {
@@ -70,6 +154,7 @@ pub async fn run_tcp_listener(
                                                 stream,
                                                 client_addr,
                                                 tls_acceptor,
+                                                handshake_limiter,
                                                 listener_name,
                                                 http_client,
                                                 gateway_state
@@ -97,11 +182,155 @@ pub async fn run_tcp_listener(
             }
 
             _ = cancel_token.cancelled() => {
-                tracing::info!("Shutdown received on listener `{}`", listener_cfg.name);
+                tracing::debug!("Shutdown received on an acceptor of listener `{}`", listener_cfg.name);
                 break;
             }
         }
     }
+}
 
-    Ok(())
+/// Same backlog as `TcpListener::bind`
+const LISTEN_BACKLOG: u32 = 1024;
+
+fn bind_listener(name: &str, addr: SocketAddr, options: &TcpOptions) -> io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    let warn = |option: &str, result: io::Result<()>| {
+        if let Err(err) = result {
+            tracing::warn!("Could not set tcp.{option} of listener `{name}`, ignoring it: {err}");
+        }
+    };
+    // Matches `TcpListener::bind`, which enables it on Unix
+    warn(
+        "reuse_address",
+        socket.set_reuseaddr(options.reuse_address.unwrap_or(cfg!(unix))),
+    );
+    if let Some(reuse_port) = options.reuse_port {
+        warn("reuse_port", set_reuse_port(&socket, reuse_port));
+    }
+    if let Some(size) = options.recv_buffer_size {
+        warn(
+            "recv_buffer_size",
+            socket.set_recv_buffer_size(buffer_size(size)),
+        );
+    }
+    if let Some(size) = options.send_buffer_size {
+        warn(
+            "send_buffer_size",
+            socket.set_send_buffer_size(buffer_size(size)),
+        );
+    }
+    socket.bind(addr)?;
+    socket.listen(LISTEN_BACKLOG)
+}
+
+fn buffer_size(size: usize) -> u32 {
+    u32::try_from(size).unwrap_or(u32::MAX)
+}
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+))]
+fn set_reuse_port(socket: &TcpSocket, reuse_port: bool) -> io::Result<()> {
+    socket.set_reuseport(reuse_port)
+}
+
+#[cfg(not(all(
+    unix,
+    not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+)))]
+fn set_reuse_port(_socket: &TcpSocket, _reuse_port: bool) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Applies the per connection options to an accepted or upstream stream.
+pub(crate) fn apply_stream_options(stream: &TcpStream, options: &TcpOptions) {
+    if let Some(nodelay) = options.nodelay
+        && let Err(err) = stream.set_nodelay(nodelay)
+    {
+        tracing::debug!("Could not set tcp.nodelay, ignoring it: {err}");
+    }
+    if let Some(time) = options.keepalive {
+        let keepalive = TcpKeepalive::new().with_time(time);
+        if let Err(err) = SockRef::from(stream).set_tcp_keepalive(&keepalive) {
+            tracing::debug!("Could not set tcp.keepalive, ignoring it: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_configured_options_are_applied_to_accepted_streams() {
+        let options = TcpOptions {
+            nodelay: Some(true),
+            keepalive: Some(Duration::from_secs(30)),
+            recv_buffer_size: Some(64 * 1024),
+            ..TcpOptions::default()
+        };
+        let listener = bind_listener("test", "127.0.0.1:0".parse().unwrap(), &options).unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(!stream.nodelay().unwrap());
+
+        apply_stream_options(&stream, &options);
+        assert!(stream.nodelay().unwrap());
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_reuse_port_acceptors_share_the_address_and_all_accept() {
+        let listener_cfg: Listener = serde_json::from_str(
+            r#"{
+                "name": "http-main",
+                "addr": "127.0.0.1:0",
+                "acceptors": { "enabled": true, "count": 4 }
+            }"#,
+        )
+        .unwrap();
+        let listeners = bind_listeners(&listener_cfg).unwrap();
+        assert_eq!(listeners.len(), 4);
+        let addr = listeners[0].local_addr().unwrap();
+        assert!(
+            listeners
+                .iter()
+                .all(|listener| listener.local_addr().unwrap() == addr)
+        );
+
+        let accepted: Vec<_> = listeners
+            .into_iter()
+            .map(|listener| {
+                tokio::spawn(async move {
+                    let mut count = 0;
+                    while let Ok(Ok(_)) =
+                        tokio::time::timeout(Duration::from_millis(500), listener.accept()).await
+                    {
+                        count += 1;
+                    }
+                    count
+                })
+            })
+            .collect();
+        // The kernel spreads connections over the sockets by hashing their address
+        let mut clients = Vec::new();
+        for _ in 0..64 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+        let mut total = 0;
+        for acceptor in accepted {
+            let count = acceptor.await.unwrap();
+            assert!(count > 0, "an acceptor served no connection");
+            total += count;
+        }
+        assert_eq!(total, 64);
+    }
 }
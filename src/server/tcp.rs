@@ -1,5 +1,6 @@
 use crate::SharedGatewayState;
-use crate::config::TcpTlsMode;
+use crate::config::{TcpOptions, TcpTlsMode};
+use crate::server::apply_stream_options;
 use std::io;
 use std::net::SocketAddr;
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -15,21 +16,30 @@ pub(crate) async fn handle_tcp_client(
 ) -> io::Result<()> {
     tracing::info!("Connected with client {client_addr}");
 
-    let router = gateway_state.load().get_router();
+    let (router, upstream_tcp) = {
+        let gateway_runtime = gateway_state.load();
+        let upstream_tcp = gateway_runtime
+            .get_last_applied_config()
+            .upstream
+            .tcp
+            .clone();
+        (gateway_runtime.get_router(), upstream_tcp)
+    };
     match router.get_tcp_route(&listener) {
         Ok(route) => {
             let service = route.get_service();
-            if let Ok(upstream) = router.get_tcp_upstream(service) {
+            if let Ok(upstream) = router.get_tcp_upstream(service, client_addr.ip()) {
                 match route.get_tls_mode() {
                     Some(TcpTlsMode::Terminate) => {
                         if let Some(tls_acceptor) = tls_acceptor {
                             let tls_stream = tls_acceptor.accept(stream).await?;
-                            return send_upstream(&upstream.target, tls_stream).await;
+                            return send_upstream(&upstream.target, &upstream_tcp, tls_stream)
+                                .await;
                         } else {
                             tracing::warn!("TLS not configured for termination");
                         }
                     }
-                    _ => return send_upstream(&upstream.target, stream).await,
+                    _ => return send_upstream(&upstream.target, &upstream_tcp, stream).await,
                 }
             } else {
                 tracing::warn!("Router: No upstream found for {client_addr}");
@@ -43,11 +53,12 @@ pub(crate) async fn handle_tcp_client(
     Ok(())
 }
 
-async fn send_upstream<T>(target: &str, mut stream: T) -> io::Result<()>
+async fn send_upstream<T>(target: &str, options: &TcpOptions, mut stream: T) -> io::Result<()>
 where
     T: AsyncRead + AsyncWrite + Unpin,
 {
     let mut upstream = TcpStream::connect(target).await?;
+    apply_stream_options(&upstream, options);
     let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
     Ok(())
 }
@@ -3,6 +3,10 @@ use crate::utils::{load_certs, load_private_key};
 use rustls::crypto::aws_lc_rs::sign::any_supported_type;
 use rustls::server::{ClientHello, ResolvesServerCert, ResolvesServerCertUsingSni};
 use rustls::sign::CertifiedKey;
+use rustls::{AlertDescription, PeerIncompatible};
+use std::borrow::Cow;
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
 #[derive(Debug)]
@@ -12,31 +16,42 @@ struct SNICertificateResolver {
 }
 
 impl SNICertificateResolver {
-    fn new(cert_file: &str, key_file: &str) -> Self {
-        let certs = load_certs(cert_file).unwrap();
-        let private_key = load_private_key(key_file).unwrap();
-        let signing_key = any_supported_type(&private_key).unwrap();
-        SNICertificateResolver {
-            default: Arc::new(CertifiedKey::new(certs, signing_key)),
+    fn new(default: &TLSConfig) -> Result<Self, String> {
+        Ok(SNICertificateResolver {
+            default: Arc::new(load_certified_key(&default.cert_file, &default.key_file)?),
             sni: ResolvesServerCertUsingSni::new(),
-        }
+        })
     }
 
-    fn add_sni_cert(
-        &mut self,
-        hostname: &str,
-        cert_file: &str,
-        key_file: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let certs = load_certs(cert_file)?;
-        let private_key = load_private_key(key_file)?;
-        let signing_key = any_supported_type(&private_key)?;
-        self.sni
-            .add(hostname, CertifiedKey::new(certs, signing_key))?;
-        Ok(())
+    fn add_sni_cert(&mut self, hostname: &str, tls_config: &TLSConfig) -> Result<(), String> {
+        let certified_key = load_certified_key(&tls_config.cert_file, &tls_config.key_file)?;
+        self.sni.add(hostname, certified_key).map_err(|err| {
+            format!(
+                "Certificate {} is not valid for hostname `{hostname}`: {err}",
+                tls_config.cert_file.display()
+            )
+        })
     }
 }
 
+/// Loads a certificate chain and its private key, failing when either can't be read or parsed or
+/// when the key does not belong to the certificate.
+pub fn load_certified_key(cert_file: &Path, key_file: &Path) -> Result<CertifiedKey, String> {
+    let (cert_path, key_path) = (cert_file.display(), key_file.display());
+    let certs = load_certs(&cert_path.to_string()).map_err(|err| err.to_string())?;
+    if certs.is_empty() {
+        return Err(format!("No PEM certificate found in {cert_path}"));
+    }
+    let private_key = load_private_key(&key_path.to_string()).map_err(|err| err.to_string())?;
+    let signing_key = any_supported_type(&private_key)
+        .map_err(|err| format!("Unsupported private key in {key_path}: {err}"))?;
+    let certified_key = CertifiedKey::new(certs, signing_key);
+    certified_key.keys_match().map_err(|err| {
+        format!("Private key {key_path} does not match certificate {cert_path}: {err}")
+    })?;
+    Ok(certified_key)
+}
+
 impl ResolvesServerCert for SNICertificateResolver {
     fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
         self.sni
@@ -45,28 +60,19 @@ impl ResolvesServerCert for SNICertificateResolver {
     }
 }
 
-pub fn init_rustls_server_config(tls_configs: &[TLSConfig]) -> Arc<rustls::ServerConfig> {
+pub fn init_rustls_server_config(
+    tls_configs: &[TLSConfig],
+) -> Result<Arc<rustls::ServerConfig>, String> {
     let default_cfg = tls_configs
         .iter()
         .find(|&cfg| cfg.default)
-        .expect("A default config is required for TLS");
+        .ok_or("A default config is required for TLS")?;
 
-    let mut resolver = SNICertificateResolver::new(
-        default_cfg.cert_file.to_str().unwrap(),
-        default_cfg.key_file.to_str().unwrap(),
-    );
+    let mut resolver = SNICertificateResolver::new(default_cfg)?;
 
     for tls_config in tls_configs {
-        if let Some(hosts) = &tls_config.hostnames {
-            let cert_file = tls_config.cert_file.to_str().unwrap();
-            let key_file = tls_config.key_file.to_str().unwrap();
-            for host in hosts {
-                resolver
-                    .add_sni_cert(host, cert_file, key_file)
-                    .unwrap_or_else(|_| {
-                        panic!("The certificate should be valid for hostname `{host}`")
-                    });
-            }
+        for host in tls_config.hostnames.iter().flatten() {
+            resolver.add_sni_cert(host, tls_config)?;
         }
     }
 
@@ -75,5 +81,122 @@ pub fn init_rustls_server_config(tls_configs: &[TLSConfig]) -> Arc<rustls::Serve
         .with_cert_resolver(Arc::new(resolver));
 
     server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-    Arc::new(server_config)
+    Ok(Arc::new(server_config))
+}
+
+/// Readable category for a failed TLS handshake, from the rustls error wrapped in the I/O error.
+pub(crate) fn handshake_failure_reason(err: &io::Error) -> Cow<'static, str> {
+    let Some(tls_err) = err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+    else {
+        return match err.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                Cow::Borrowed("client closed the connection during the handshake")
+            }
+            _ => Cow::Owned(format!("I/O error: {err}")),
+        };
+    };
+
+    match tls_err {
+        rustls::Error::NoCertificatesPresented => {
+            Cow::Borrowed("client certificate required but not presented")
+        }
+        rustls::Error::InvalidCertificate(cert_err) => {
+            Cow::Owned(format!("invalid client certificate: {cert_err:?}"))
+        }
+        rustls::Error::PeerIncompatible(incompatible) => match incompatible {
+            PeerIncompatible::NoCipherSuitesInCommon => Cow::Borrowed("no common cipher suite"),
+            PeerIncompatible::ServerDoesNotSupportTls12Or13
+            | PeerIncompatible::SupportedVersionsExtensionRequired
+            | PeerIncompatible::Tls12NotOffered
+            | PeerIncompatible::Tls12NotOfferedOrEnabled => {
+                Cow::Borrowed("protocol version mismatch")
+            }
+            PeerIncompatible::NoKxGroupsInCommon => Cow::Borrowed("no common key exchange group"),
+            PeerIncompatible::NoSignatureSchemesInCommon => {
+                Cow::Borrowed("no common signature scheme")
+            }
+            other => Cow::Owned(format!("incompatible client: {other:?}")),
+        },
+        rustls::Error::AlertReceived(AlertDescription::ProtocolVersion) => {
+            Cow::Borrowed("protocol version mismatch")
+        }
+        // The default certificate is served for unknown SNI hostnames, which clients reject
+        rustls::Error::AlertReceived(
+            alert @ (AlertDescription::BadCertificate
+            | AlertDescription::UnknownCA
+            | AlertDescription::CertificateUnknown
+            | AlertDescription::CertificateExpired),
+        ) => Cow::Owned(format!(
+            "client rejected the server certificate ({alert:?}), possibly an unknown SNI hostname"
+        )),
+        rustls::Error::AlertReceived(alert) => Cow::Owned(format!("client sent alert {alert:?}")),
+        rustls::Error::General(msg) if msg == "no server certificate chain resolved" => {
+            Cow::Borrowed("unknown SNI hostname, no certificate to serve")
+        }
+        rustls::Error::InvalidMessage(_)
+        | rustls::Error::InappropriateMessage { .. }
+        | rustls::Error::InappropriateHandshakeMessage { .. } => {
+            Cow::Borrowed("malformed handshake, the client may not be speaking TLS")
+        }
+        other => Cow::Owned(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio_rustls::LazyConfigAcceptor;
+
+    fn tls_error(err: rustls::Error) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+
+    #[test]
+    fn test_handshake_failures_are_categorized() {
+        assert_eq!(
+            handshake_failure_reason(&tls_error(PeerIncompatible::NoCipherSuitesInCommon.into())),
+            "no common cipher suite"
+        );
+        assert_eq!(
+            handshake_failure_reason(&tls_error(
+                PeerIncompatible::SupportedVersionsExtensionRequired.into()
+            )),
+            "protocol version mismatch"
+        );
+        assert_eq!(
+            handshake_failure_reason(&tls_error(rustls::Error::NoCertificatesPresented)),
+            "client certificate required but not presented"
+        );
+        assert!(
+            handshake_failure_reason(&tls_error(rustls::Error::AlertReceived(
+                AlertDescription::UnknownCA
+            )))
+            .contains("unknown SNI hostname")
+        );
+        assert_eq!(
+            handshake_failure_reason(&io::Error::from(io::ErrorKind::UnexpectedEof)),
+            "client closed the connection during the handshake"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plain_http_on_tls_listener_is_categorized() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        let Err(err) = LazyConfigAcceptor::new(rustls::server::Acceptor::default(), server).await
+        else {
+            panic!("Plain HTTP is not a client hello");
+        };
+        assert_eq!(
+            handshake_failure_reason(&err),
+            "malformed handshake, the client may not be speaking TLS"
+        );
+    }
 }
@@ -1,19 +1,321 @@
-use crate::config::{GatewayConfig, Upstream};
-use crate::load_balancer::{LoadBalancer, WeightedRoundRobin};
+use crate::config::upstream_ip;
+use crate::config::{
+    GatewayConfig, HealthCheckConfig, HttpServiceConfig, LoadBalancingStrategy, Upstream,
+    UpstreamConfig,
+};
+use crate::health::{PassiveHealth, Probe, ProbeSchedule, UpstreamHealth, UpstreamStatus};
+use crate::load_balancer::{
+    ConsistentHashing, LeastConnections, LoadBalancer, LoadBalancerStrategy, Random, RoundRobin,
+    WeightedRoundRobin,
+};
+use crate::utils::{build_http_client, error_chain, http_client_builder};
+use crate::{BoxedSlice, BoxedStr, HEALTH_CHECKS};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// A dedicated client resolving the SNI host to the upstream IP, so the handshake presents it
+/// and the certificate is verified against it.
+struct SniUpstream {
+    url: BoxedStr,
+    client: Arc<reqwest::Client>,
+}
+
+impl SniUpstream {
+    fn new(
+        upstream: &Upstream,
+        sni: &str,
+        upstream_config: &UpstreamConfig,
+        service_config: Option<&HttpServiceConfig>,
+    ) -> Result<Self, String> {
+        let invalid = || format!("Upstream {} cannot use tls_sni {sni}", upstream.target);
+        let mut url = reqwest::Url::parse(&upstream.target).map_err(|_| invalid())?;
+        let ip = upstream_ip(&url).ok_or_else(invalid)?;
+        let port = url.port_or_known_default().ok_or_else(invalid)?;
+        url.set_host(Some(sni)).map_err(|_| invalid())?;
+        let client = http_client_builder(upstream_config, service_config)?
+            .resolve(sni, SocketAddr::new(ip, port))
+            .build()
+            .map_err(|err| error_chain(&err))?;
+        Ok(SniUpstream {
+            url: url.as_str().trim_end_matches('/').into(),
+            client: Arc::new(client),
+        })
+    }
+}
 
 pub struct Service {
+    name: BoxedStr,
     lb: LoadBalancer,
+    targets: BoxedSlice<BoxedStr>,
+    /// Label of each upstream target, its configured name or else its position in the service
+    labels: HashMap<BoxedStr, BoxedStr>,
+    http_client: Option<Arc<reqwest::Client>>,
+    /// Upstreams with a `tls_sni` override, reached through a url naming the SNI host
+    sni_upstreams: HashMap<BoxedStr, SniUpstream>,
+    /// Overrides the gateway wide upstream request timeout
+    request_timeout: Option<Duration>,
+    /// Passed through redirects to the upstream are pointed at the public host
+    rewrite_redirect_location: bool,
+    retry_stale_connections: bool,
+    force_connection_close: bool,
+    /// Requests an upstream connection serves before it is recycled
+    max_requests_per_connection: Option<u64>,
+    /// Requests sent to each upstream, every `max_requests_per_connection`th closes its connection
+    request_counts: HashMap<BoxedStr, AtomicU64>,
+    connection_affinity: bool,
+    health_check: Option<HealthCheckConfig>,
+    passive_health: Option<Arc<PassiveHealth>>,
 }
 
 impl Service {
-    fn new(upstreams: &[Upstream]) -> Self {
-        let strategy = Box::new(WeightedRoundRobin::new(upstreams));
+    fn new(name: &str, upstreams: &[Upstream], strategy: LoadBalancingStrategy) -> Self {
+        let strategy: Box<dyn LoadBalancerStrategy> = match strategy {
+            LoadBalancingStrategy::WeightedRoundRobin => {
+                Box::new(WeightedRoundRobin::new(upstreams))
+            }
+            LoadBalancingStrategy::RoundRobin => Box::new(RoundRobin::new(upstreams)),
+            LoadBalancingStrategy::Random => Box::new(Random::new(upstreams)),
+            LoadBalancingStrategy::LeastConnections => Box::new(LeastConnections::new(upstreams)),
+            LoadBalancingStrategy::IpHash => Box::new(ConsistentHashing::new(upstreams)),
+        };
         Service {
+            name: name.into(),
             lb: LoadBalancer::new(strategy),
+            targets: upstreams
+                .iter()
+                .map(|upstream| upstream.target.clone().into_boxed_str())
+                .collect(),
+            labels: upstreams
+                .iter()
+                .enumerate()
+                .map(|(index, upstream)| {
+                    let label = upstream.name.clone().unwrap_or_else(|| index.to_string());
+                    (upstream.target.as_str().into(), label.into_boxed_str())
+                })
+                .collect(),
+            http_client: None,
+            sni_upstreams: HashMap::new(),
+            request_timeout: None,
+            rewrite_redirect_location: false,
+            retry_stale_connections: false,
+            force_connection_close: false,
+            max_requests_per_connection: None,
+            request_counts: upstreams
+                .iter()
+                .map(|upstream| (upstream.target.as_str().into(), AtomicU64::new(0)))
+                .collect(),
+            connection_affinity: false,
+            health_check: None,
+            passive_health: None,
+        }
+    }
+
+    fn with_sni_upstreams(
+        mut self,
+        upstreams: &[Upstream],
+        upstream_config: &UpstreamConfig,
+        service_config: Option<&HttpServiceConfig>,
+    ) -> Result<Self, String> {
+        for upstream in upstreams {
+            if let Some(sni) = &upstream.tls_sni {
+                let sni_upstream =
+                    SniUpstream::new(upstream, sni, upstream_config, service_config)?;
+                self.sni_upstreams
+                    .insert(upstream.target.as_str().into(), sni_upstream);
+            }
+        }
+        Ok(self)
+    }
+
+    fn from_http_config(
+        name: &str,
+        service_config: &HttpServiceConfig,
+        upstream_config: &UpstreamConfig,
+    ) -> Result<Self, String> {
+        let mut service = Service::new(name, &service_config.upstreams, service_config.strategy)
+            .with_sni_upstreams(
+                &service_config.upstreams,
+                upstream_config,
+                Some(service_config),
+            )?;
+        // A dedicated client (and so connection pool) is only needed when its settings differ
+        // from the gateway wide client
+        if service_config.needs_dedicated_client() {
+            let http_client = build_http_client(upstream_config, Some(service_config))?;
+            service.http_client = Some(Arc::new(http_client));
+        }
+        service.request_timeout = service_config.request_timeout;
+        service.rewrite_redirect_location = service_config
+            .redirects
+            .as_ref()
+            .is_some_and(|redirects| redirects.rewrite_location);
+        service.retry_stale_connections = service_config.retry_stale_connections;
+        service.force_connection_close = service_config.force_connection_close;
+        service.max_requests_per_connection = service_config.max_requests_per_connection;
+        service.connection_affinity = service_config.connection_affinity;
+        service.health_check = service_config.health_check.clone();
+        service.passive_health = service_config.passive_health.as_ref().map(|config| {
+            let upstream_count = service.targets.len();
+            Arc::new(PassiveHealth::new(config, upstream_count))
+        });
+        Ok(service)
+    }
+
+    pub fn get_http_client(&self) -> Option<Arc<reqwest::Client>> {
+        self.http_client.clone()
+    }
+
+    /// Url and client to reach `target` through when it has a `tls_sni` override.
+    pub fn get_sni_upstream(&self, target: &str) -> Option<(&str, Arc<reqwest::Client>)> {
+        self.sni_upstreams
+            .get(target)
+            .map(|upstream| (upstream.url.as_ref(), upstream.client.clone()))
+    }
+
+    pub fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
+
+    pub fn rewrite_redirect_location(&self) -> bool {
+        self.rewrite_redirect_location
+    }
+
+    pub fn retry_stale_connections(&self) -> bool {
+        self.retry_stale_connections
+    }
+
+    pub fn force_connection_close(&self) -> bool {
+        self.force_connection_close
+    }
+
+    /// Counts a request to `target`, true when its connection should be closed afterwards.
+    ///
+    /// Sequential requests share one pooled connection, so each serves exactly
+    /// `max_requests_per_connection` requests. Concurrent ones spread over several connections
+    /// and the limit holds on average.
+    pub fn is_last_request_on_connection(&self, target: &str) -> bool {
+        let (Some(max_requests), Some(count)) = (
+            self.max_requests_per_connection,
+            self.request_counts.get(target),
+        ) else {
+            return false;
+        };
+        (count.fetch_add(1, Ordering::Relaxed) + 1).is_multiple_of(max_requests)
+    }
+
+    pub fn connection_affinity(&self) -> bool {
+        self.connection_affinity
+    }
+
+    /// In flight request counter of `target` when the service balances on it.
+    pub fn in_flight(&self, target: &str) -> Option<Arc<AtomicUsize>> {
+        self.lb.in_flight(target)
+    }
+
+    /// Health check probe of `target`, `None` without health checks. Upstreams reached through
+    /// a client of the service's own are probed through it and so per service.
+    fn probe(&self, target: &str) -> Option<Probe> {
+        let health_check = self.health_check.as_ref()?;
+        let own_client = self.http_client.is_some() || self.sni_upstreams.contains_key(target);
+        Some(Probe::new(
+            target,
+            health_check,
+            own_client.then_some(self.name.as_ref()),
+        ))
+    }
+
+    /// Probes of every upstream with how they are sent, the gateway wide client is used where
+    /// the schedule has none.
+    pub fn health_probes(&self) -> Vec<(Probe, ProbeSchedule)> {
+        let Some(health_check) = &self.health_check else {
+            return Vec::new();
+        };
+        self.targets
+            .iter()
+            .filter_map(|target| {
+                let (url, client) = match self.get_sni_upstream(target) {
+                    Some((url, client)) => (url, Some(client)),
+                    None => (target.as_ref(), self.get_http_client()),
+                };
+                let schedule = ProbeSchedule {
+                    interval: health_check.interval,
+                    timeout: health_check.timeout,
+                    url: format!("{}{}", url.trim_end_matches('/'), health_check.path),
+                    client,
+                };
+                Some((self.probe(target)?, schedule))
+            })
+            .collect()
+    }
+
+    /// Whether the last health check of `target` passed and live requests did not eject it,
+    /// always true without health checks.
+    pub fn is_healthy(&self, target: &str) -> bool {
+        self.probe(target)
+            .is_none_or(|probe| HEALTH_CHECKS.is_healthy(&probe))
+            && self
+                .passive_health
+                .as_ref()
+                .is_none_or(|passive_health| !passive_health.is_ejected(target))
+    }
+
+    /// Health of `upstream` as the load balancer sees it.
+    pub fn upstream_status(&self, upstream: &Upstream) -> UpstreamStatus {
+        let probe = self.probe(&upstream.target);
+        let status = if probe
+            .as_ref()
+            .is_some_and(|probe| !HEALTH_CHECKS.is_healthy(probe))
+        {
+            UpstreamHealth::Unhealthy
+        } else if self
+            .passive_health
+            .as_ref()
+            .is_some_and(|passive_health| passive_health.is_ejected(&upstream.target))
+        {
+            UpstreamHealth::Ejected
+        } else {
+            UpstreamHealth::Healthy
+        };
+        UpstreamStatus {
+            target: upstream.target.clone(),
+            weight: upstream.weight,
+            status,
+            last_checked: probe.and_then(|probe| HEALTH_CHECKS.last_checked(&probe)),
         }
     }
+
+    /// Failure tracking of the upstreams when the service ejects failing ones.
+    pub fn passive_health(&self) -> Option<Arc<PassiveHealth>> {
+        self.passive_health.clone()
+    }
+
+    /// Label identifying `target` without revealing it, `None` for targets outside the service.
+    pub fn upstream_label(&self, target: &str) -> Option<&str> {
+        self.labels.get(target).map(AsRef::as_ref)
+    }
+
+    pub fn has_upstream(&self, target: &str) -> bool {
+        self.targets.iter().any(|t| t.as_ref() == target)
+    }
+
+    /// Target of the upstream named by its label, i.e. its configured name or else its index, by
+    /// its full target or by its `host:port`, e.g. `backend-2:8080`.
+    pub fn find_upstream(&self, name: &str) -> Option<&BoxedStr> {
+        self.labels
+            .iter()
+            .find_map(|(target, label)| (label.as_ref() == name).then_some(target))
+            .or_else(|| {
+                self.targets.iter().find(|target| {
+                    target.as_ref() == name
+                        || target
+                            .split_once("://")
+                            .is_some_and(|(_, rest)| rest.split('/').next() == Some(name))
+                })
+            })
+    }
 }
 
 pub struct ServiceRegistry {
@@ -22,29 +324,240 @@ pub struct ServiceRegistry {
 }
 
 impl ServiceRegistry {
-    pub fn init(gateway_config: Arc<GatewayConfig>) -> Self {
-        let http = gateway_config
+    pub fn init(gateway_config: Arc<GatewayConfig>) -> Result<Self, String> {
+        let mut http = gateway_config
             .http
             .services
             .iter()
-            .map(|(name, service_config)| (name.clone(), Service::new(&service_config.upstreams)))
-            .collect();
+            .map(|(name, service_config)| {
+                Service::from_http_config(name, service_config, &gateway_config.upstream)
+                    .map(|service| (name.clone(), service))
+                    .map_err(|err| format!("Failed to build HTTP client for service {name}: {err}"))
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        // Routes with inline upstreams get an anonymous service of their own
+        for (index, route) in gateway_config.http.routes.iter().enumerate() {
+            if let Some(upstreams) = &route.upstreams {
+                let name = route.service_name(index);
+                let mut service = Service::new(&name, upstreams, LoadBalancingStrategy::default())
+                    .with_sni_upstreams(upstreams, &gateway_config.upstream, None)
+                    .map_err(|err| {
+                        format!("Failed to build HTTP client for route {index}: {err}")
+                    })?;
+                service.health_check = route.health_check.clone();
+                http.insert(name, service);
+            }
+        }
 
         let tcp = gateway_config
             .tcp
             .services
             .iter()
-            .map(|(name, service_config)| (name.clone(), Service::new(&service_config.upstreams)))
+            .map(|(name, service_config)| {
+                let service =
+                    Service::new(name, &service_config.upstreams, service_config.strategy);
+                (name.clone(), service)
+            })
             .collect();
 
-        ServiceRegistry { http, tcp }
+        Ok(ServiceRegistry { http, tcp })
+    }
+
+    pub fn get_http_service(&self, name: &str) -> Option<&Service> {
+        self.http.get(name)
+    }
+
+    /// Every HTTP service, including the anonymous ones of routes with inline upstreams.
+    pub fn http_services(&self) -> impl Iterator<Item = &Service> {
+        self.http.values()
+    }
+
+    /// Next upstream of the service, `key` identifies the client for key based strategies.
+    pub fn get_http_service_endpoint(&self, name: &str, key: Option<&str>) -> Option<&Upstream> {
+        let service = self.http.get(name)?;
+        let selected = service.lb.get_next(key)?;
+        if service.is_healthy(&selected.target) {
+            return Some(selected);
+        }
+        // Unhealthy upstreams are skipped, when none is healthy the first pick is used anyway
+        (1..service.targets.len())
+            .filter_map(|_| service.lb.get_next(key))
+            .find(|upstream| service.is_healthy(&upstream.target))
+            .or(Some(selected))
+    }
+
+    pub fn get_tcp_service_endpoint(&self, name: &str, key: Option<&str>) -> Option<&Upstream> {
+        self.tcp.get(name).and_then(|svc| svc.lb.get_next(key))
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Config, File, FileFormat};
+    use rustls_pki_types::PrivateKeyDer;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    #[test]
+    fn test_invalid_upstream_tls_config_is_reported_for_service() {
+        let gateway_config: GatewayConfig = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
 
-    pub fn get_http_service_endpoint(&self, name: &str) -> Option<&Upstream> {
-        self.http.get(name).and_then(|svc| svc.lb.get_next())
+                http:
+                  services:
+                    secure-service:
+                      upstreams:
+                        - target: https://secure.service:3443
+                      tls:
+                        ca_file: /nonexistent/ca.pem
+
+                  routes:
+                    - path: /secure
+                      listeners: [ http-main ]
+                      service: secure-service
+                "#,
+                FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let err = ServiceRegistry::init(Arc::new(gateway_config))
+            .err()
+            .expect("Service with unreadable CA file should fail");
+        assert!(err.contains("secure-service"), "{err}");
+        assert!(err.contains("/nonexistent/ca.pem"), "{err}");
     }
 
-    pub fn get_tcp_service_endpoint(&self, name: &str) -> Option<&Upstream> {
-        self.tcp.get(name).and_then(|svc| svc.lb.get_next())
+    #[test]
+    fn test_http2_keep_alive_uses_dedicated_client() {
+        let gateway_config: GatewayConfig = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                listeners:
+                  - name: http-main
+                    addr: 0.0.0.0:3000
+
+                http:
+                  services:
+                    grpc-service:
+                      upstreams:
+                        - target: https://grpc.service:50051
+                      http2_keep_alive:
+                        interval: 30s
+
+                    plain-service:
+                      upstreams:
+                        - target: http://plain.service:8080
+
+                  routes:
+                    - path: /grpc
+                      listeners: [ http-main ]
+                      service: grpc-service
+                "#,
+                FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let keep_alive = gateway_config.http.services["grpc-service"]
+            .http2_keep_alive
+            .as_ref()
+            .unwrap();
+        assert_eq!(keep_alive.timeout, std::time::Duration::from_secs(20));
+        assert!(keep_alive.while_idle);
+
+        let registry = ServiceRegistry::init(Arc::new(gateway_config)).unwrap();
+        let client = |name| registry.get_http_service(name).unwrap().get_http_client();
+        assert!(client("grpc-service").is_some());
+        assert!(client("plain-service").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tls_sni_override_is_presented_to_ip_upstream() {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        let certified =
+            rcgen::generate_simple_self_signed(vec![String::from("backend.internal")]).unwrap();
+        let ca_file = std::env::temp_dir().join(format!("portiq-sni-{}.pem", uuid::Uuid::new_v4()));
+        std::fs::write(&ca_file, certified.cert.pem()).unwrap();
+
+        // The certificate only covers the SNI name, not the IP the upstream is addressed by
+        let server_config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![certified.cert.der().clone()],
+                PrivateKeyDer::Pkcs8(certified.signing_key.serialize_der().into()),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let backend = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let sni = stream.get_ref().1.server_name().map(String::from);
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0; 1];
+                stream.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            sni
+        });
+
+        let gateway_config: GatewayConfig = Config::builder()
+            .add_source(File::from_str(
+                &format!(
+                    r#"
+                    listeners:
+                      - name: http-main
+                        addr: 0.0.0.0:3000
+
+                    http:
+                      services:
+                        sni-service:
+                          upstreams:
+                            - target: https://{addr}
+                              tls_sni: backend.internal
+                          tls:
+                            ca_file: {}
+                      routes:
+                        - path: /
+                          listeners: [ http-main ]
+                          service: sni-service
+                    "#,
+                    ca_file.display()
+                ),
+                FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let registry = ServiceRegistry::init(Arc::new(gateway_config)).unwrap();
+        let service = registry.get_http_service("sni-service").unwrap();
+        let (url, client) = service
+            .get_sni_upstream(&format!("https://{addr}"))
+            .unwrap();
+        assert_eq!(url, format!("https://backend.internal:{}", addr.port()));
+
+        let response = client.get(format!("{url}/health")).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(backend.await.unwrap().as_deref(), Some("backend.internal"));
     }
 }
@@ -1,12 +1,24 @@
+use crate::LIFECYCLE;
+use crate::config::{
+    ErrorResponseConfig, HttpServiceConfig, IpFamily, PathValidation, RedirectMode, UpstreamConfig,
+    UpstreamProxyConfig,
+};
+use crate::dns::FamilyResolver;
+use crate::error::BoxError;
+use crate::lifecycle::LifecycleEvent;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, Empty, Full};
-use hyper::body::Bytes;
-use hyper::http::HeaderMap;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::{CONNECTION, UPGRADE};
+use hyper::http::{HeaderMap, HeaderName};
 use hyper::{Response, StatusCode};
 use reqwest::RequestBuilder;
 use rustls_pki_types::pem::PemObject;
 use rustls_pki_types::{CertificateDer, PrivateKeyDer};
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use std::time::Duration;
 use std::{fs, io};
 use tokio::signal::unix::{SignalKind, signal};
@@ -26,10 +38,164 @@ pub fn load_private_key(filename: &str) -> io::Result<PrivateKeyDer<'static>> {
         .map_err(|e| io::Error::other(format!("Failed to open {filename}: {e}")))?;
     let reader = io::BufReader::new(keyfile);
     PrivateKeyDer::from_pem_reader(reader)
-        .map_err(|e| io::Error::other(format!("Failed to read private key: {e}")))
+        .map_err(|e| io::Error::other(format!("Failed to read private key from {filename}: {e}")))
 }
 
-pub fn response_with_status(status_code: StatusCode) -> Response<BoxBody<Bytes, hyper::Error>> {
+// Render an error with all of its sources, `reqwest` only says "builder error" at the top level.
+pub fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message.push_str(&format!(": {err}"));
+        source = err.source();
+    }
+    message
+}
+
+// Build the client used to proxy requests to upstreams.
+pub fn build_http_client(
+    upstream_config: &UpstreamConfig,
+    service_config: Option<&HttpServiceConfig>,
+) -> Result<reqwest::Client, String> {
+    http_client_builder(upstream_config, service_config)?
+        .build()
+        .map_err(|err| error_chain(&err))
+}
+
+/// Client builder with the DNS and optional service settings applied.
+pub fn http_client_builder(
+    upstream_config: &UpstreamConfig,
+    service_config: Option<&HttpServiceConfig>,
+) -> Result<reqwest::ClientBuilder, String> {
+    let mut builder = reqwest::Client::builder()
+        .use_rustls_tls()
+        .connect_timeout(upstream_config.connect_timeout)
+        .timeout(upstream_config.request_timeout);
+    if upstream_config.dns.family != IpFamily::Any {
+        builder = builder.dns_resolver(FamilyResolver::new(upstream_config.dns.family));
+    }
+    for (host, ips) in &upstream_config.dns.hosts {
+        // The port is ignored, the one from the upstream url is used
+        let addrs = ips
+            .iter()
+            .map(|&ip| SocketAddr::new(ip, 0))
+            .collect::<Vec<_>>();
+        builder = builder.resolve_to_addrs(host, &addrs);
+    }
+    if let Some(nodelay) = upstream_config.tcp.nodelay {
+        builder = builder.tcp_nodelay(nodelay);
+    }
+    if let Some(keepalive) = upstream_config.tcp.keepalive {
+        builder = builder.tcp_keepalive(keepalive);
+    }
+    let Some(service_config) = service_config else {
+        return Ok(builder);
+    };
+    if let Some(connect_timeout) = service_config.connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    if let Some(idle_timeout) = service_config.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(idle_timeout);
+    }
+    if let Some(proxy_config) = &service_config.proxy {
+        builder = builder.proxy(build_proxy(proxy_config)?);
+    }
+    if let Some(redirects) = &service_config.redirects {
+        builder = builder.redirect(match redirects.mode {
+            RedirectMode::Follow => reqwest::redirect::Policy::limited(redirects.max_redirects),
+            RedirectMode::PassThrough => reqwest::redirect::Policy::none(),
+        });
+    }
+    if let Some(keep_alive) = &service_config.http2_keep_alive {
+        builder = builder
+            .http2_keep_alive_interval(keep_alive.interval)
+            .http2_keep_alive_timeout(keep_alive.timeout)
+            .http2_keep_alive_while_idle(keep_alive.while_idle);
+    }
+    if let Some(ca_file) = service_config
+        .tls
+        .as_ref()
+        .and_then(|cfg| cfg.ca_file.as_ref())
+    {
+        for cert in load_ca_certificates(ca_file)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    Ok(builder)
+}
+
+/// Root certificates of a PEM bundle, at least one.
+pub fn load_ca_certificates(ca_file: &Path) -> Result<Vec<reqwest::Certificate>, String> {
+    let pem = fs::read(ca_file)
+        .map_err(|err| format!("Failed to read CA file {}: {err}", ca_file.display()))?;
+    let certs = reqwest::Certificate::from_pem_bundle(&pem).map_err(|err| {
+        format!(
+            "Invalid CA file {}: {}",
+            ca_file.display(),
+            error_chain(&err)
+        )
+    })?;
+    if certs.is_empty() {
+        return Err(format!(
+            "CA file {} contains no certificates",
+            ca_file.display()
+        ));
+    }
+    Ok(certs)
+}
+
+fn build_proxy(proxy_config: &UpstreamProxyConfig) -> Result<reqwest::Proxy, String> {
+    let mut url = reqwest::Url::parse(&proxy_config.url)
+        .map_err(|err| format!("Invalid proxy url {}: {err}", proxy_config.url))?;
+    // Credentials in the url work for both HTTP and SOCKS5 proxies
+    if let Some(username) = &proxy_config.username {
+        url.set_username(username)
+            .map_err(|_| format!("Proxy url {} cannot have credentials", proxy_config.url))?;
+        url.set_password(proxy_config.password.as_deref())
+            .map_err(|_| format!("Proxy url {} cannot have credentials", proxy_config.url))?;
+    }
+    let proxy = reqwest::Proxy::all(url).map_err(|err| error_chain(&err))?;
+    Ok(proxy.no_proxy(reqwest::NoProxy::from_string(
+        &proxy_config.no_proxy.join(","),
+    )))
+}
+
+/// Response body holding a guard until the body is complete, failed or dropped unsent.
+pub struct GuardedBody<G> {
+    inner: BoxBody<Bytes, BoxError>,
+    _guard: G,
+}
+
+impl<G> GuardedBody<G> {
+    pub fn new(inner: BoxBody<Bytes, BoxError>, guard: G) -> Self {
+        GuardedBody {
+            inner,
+            _guard: guard,
+        }
+    }
+}
+
+impl<G: Unpin> Body for GuardedBody<G> {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+pub fn response_with_status(status_code: StatusCode) -> Response<BoxBody<Bytes, BoxError>> {
     Response::builder()
         .status(status_code)
         .header("Server", "portiq")
@@ -41,7 +207,31 @@ pub fn response_with_status(status_code: StatusCode) -> Response<BoxBody<Bytes,
         .unwrap()
 }
 
-pub fn bad_gateway_response() -> Response<BoxBody<Bytes, hyper::Error>> {
+pub fn error_response(
+    status_code: StatusCode,
+    response_config: Option<&ErrorResponseConfig>,
+    request_id: &str,
+) -> Response<BoxBody<Bytes, BoxError>> {
+    let Some(response_config) = response_config else {
+        return response_with_status(status_code);
+    };
+
+    let mut builder = Response::builder()
+        .status(status_code)
+        .header("Server", "portiq")
+        .header("x-request-id", request_id);
+    for (name, value) in &response_config.headers {
+        builder = builder.header(name, value);
+    }
+    let body = Full::new(Bytes::from(
+        response_config.body.replace("{request_id}", request_id),
+    ));
+    builder
+        .body(BoxBody::new(body).map_err(|never| match never {}).boxed())
+        .expect("Error response headers are validated with the config")
+}
+
+pub fn bad_gateway_response() -> Response<BoxBody<Bytes, BoxError>> {
     let html_res = r#"<!DOCTYPE html>
         <html>
         <head>
@@ -67,6 +257,7 @@ pub async fn graceful_shutdown(cancel_token: CancellationToken) {
     cancel_token.cancel();
     tracing::info!("Initiating shutdown, application will exit after 5 seconds");
     tokio::time::sleep(Duration::from_secs(5)).await;
+    LIFECYCLE.publish(LifecycleEvent::Stopped);
 }
 
 pub async fn shutdown_signal() {
@@ -82,6 +273,61 @@ pub async fn shutdown_signal() {
     }
 }
 
+// Connection specific headers which must not be forwarded by proxies (RFC 9110 section 7.6.1)
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+pub fn is_hop_by_hop_header(name: &HeaderName) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name.as_str())
+}
+
+// A WebSocket handshake names `upgrade` among the `Connection` options and asks for `websocket`.
+pub fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    let connection_upgrade = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|option| option.trim().eq_ignore_ascii_case("upgrade"));
+    connection_upgrade
+        && headers
+            .get(UPGRADE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("websocket"))
+}
+
+// End-to-end request headers to forward upstream. Hop-by-hop headers, including the ones named in
+// `Connection`, are dropped along with those the client or `set_proxy_headers` recompute.
+pub fn forwarded_request_headers(original_headers: &HeaderMap) -> HeaderMap {
+    let connection_headers = original_headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    let mut headers = HeaderMap::with_capacity(original_headers.len());
+    for (name, value) in original_headers {
+        if is_hop_by_hop_header(name)
+            || connection_headers.iter().any(|h| h == name.as_str())
+            || matches!(name.as_str(), "host" | "content-length" | "x-forwarded-for")
+        {
+            continue;
+        }
+        headers.append(name, value.clone());
+    }
+    headers
+}
+
 pub fn set_proxy_headers(
     client_ip: IpAddr,
     host: &str,
@@ -108,3 +354,271 @@ pub fn set_proxy_headers(
 
     builder
 }
+
+// RFC 3986 normalization of a request path, `None` for malformed percent-encoding or encoded
+// slashes. Escaped unreserved characters are decoded so `%2e%2e` is resolved like `..`, other
+// escapes are kept with uppercase hex. Encoded `/` and `\` are refused since backends disagree on
+// whether they separate segments, which would let `/admin/..%2f` route differently than it is served.
+pub fn normalize_path(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            decoded.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let hex = path.get(i + 1..i + 3)?;
+        let byte = u8::from_str_radix(hex, 16).ok()?;
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) || matches!(byte, b'/' | b'\\') {
+            return None;
+        }
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            decoded.push(byte);
+        } else {
+            decoded.extend_from_slice(format!("%{byte:02X}").as_bytes());
+        }
+        i += 3;
+    }
+    let decoded = String::from_utf8(decoded).ok()?;
+
+    let mut segments = Vec::new();
+    let mut trailing_slash = decoded.ends_with('/');
+    for segment in decoded.split('/') {
+        match segment {
+            // Empty segments come from duplicate slashes
+            "" => {}
+            "." => trailing_slash = true,
+            ".." => {
+                segments.pop();
+                trailing_slash = true;
+            }
+            segment => {
+                segments.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+    trailing_slash |= decoded.ends_with('/');
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+// Why a request path is refused under `validation`, `None` for an acceptable one. Lenient
+// validation leaves a `%` that starts no valid escape as a literal character.
+pub fn invalid_path_reason(path: &str, validation: PathValidation) -> Option<&'static str> {
+    let strict = validation == PathValidation::Strict;
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) if byte.is_ascii_control() => return Some("encoded control character"),
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None if bytes[i].is_ascii_control() => return Some("control character"),
+            None if bytes[i] == b'%' && strict => return Some("malformed percent-encoding"),
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    if strict && std::str::from_utf8(&decoded).is_err() {
+        return Some("percent-encoded invalid UTF-8");
+    }
+    None
+}
+
+// Percent-decodes `segment` once, `None` when it holds a malformed escape.
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+// Splits a path into segments the way lenient backends do, on `/` as well as `\` whether
+// literal or percent-encoded, up to double encoding.
+fn lenient_segments(path: &str) -> Option<Vec<String>> {
+    let decoded = percent_decode(path)?;
+    // A literal `%` left after the first pass is data rather than a second encoding layer
+    let decoded = percent_decode(&decoded).unwrap_or(decoded);
+    Some(decoded.split(['/', '\\']).map(String::from).collect())
+}
+
+// Whether a segment is `.` or `..` once servlet style `;param` suffixes are dropped.
+fn is_dot_segment(segment: &str) -> bool {
+    let segment = segment.split(';').next().unwrap_or_default();
+    segment == "." || segment == ".."
+}
+
+// Detects traversal that a backend could resolve differently than the router, dot segments
+// hidden behind percent-encoding or `;` parameters, and backslash separators.
+pub fn has_path_traversal(path: &str) -> bool {
+    let lowered = path.to_ascii_lowercase();
+    if lowered.contains('\\') || lowered.contains("%5c") || lowered.contains("%255c") {
+        return true;
+    }
+    match lenient_segments(path) {
+        Some(segments) => segments.iter().any(|segment| is_dot_segment(segment)),
+        // Garbage escapes are as suspicious as a decoded traversal
+        None => true,
+    }
+}
+
+// Rewrites encoded or parameterized dot segments into plain ones and backslashes into slashes,
+// then normalizes the result. `None` when the path still hides an encoded separator.
+pub fn neutralize_path_traversal(path: &str) -> Option<String> {
+    let mut rewritten = Vec::new();
+    for segment in path.split(['/', '\\']) {
+        let decoded = lenient_segments(segment)?;
+        let [decoded] = decoded.as_slice() else {
+            return None;
+        };
+        if is_dot_segment(decoded) {
+            rewritten.push(if decoded.starts_with("..") { ".." } else { "." });
+        } else {
+            rewritten.push(segment);
+        }
+    }
+    normalize_path(&rewritten.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_path() {
+        for (path, expected) in [
+            ("/a/../b", "/b"),
+            ("/a/./b/", "/a/b/"),
+            ("//a///b", "/a/b"),
+            ("/a/b/..", "/a/"),
+            ("/../../a", "/a"),
+            ("/%2e%2E/admin", "/admin"),
+            ("/a/%7euser/%41", "/a/~user/A"),
+            ("/files/a%20b%3f", "/files/a%20b%3F"),
+            ("/", "/"),
+        ] {
+            assert_eq!(normalize_path(path).as_deref(), Some(expected), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_path_reason() {
+        for (path, lenient, strict) in [
+            ("/a/b%20c", None, None),
+            ("/caf%C3%A9", None, None),
+            (
+                "/a\u{1}b",
+                Some("control character"),
+                Some("control character"),
+            ),
+            (
+                "/a%00b",
+                Some("encoded control character"),
+                Some("encoded control character"),
+            ),
+            (
+                "/a%1Fb",
+                Some("encoded control character"),
+                Some("encoded control character"),
+            ),
+            ("/100%", None, Some("malformed percent-encoding")),
+            ("/a%G1", None, Some("malformed percent-encoding")),
+            ("/a%FFb", None, Some("percent-encoded invalid UTF-8")),
+        ] {
+            assert_eq!(
+                invalid_path_reason(path, PathValidation::Lenient),
+                lenient,
+                "{path}"
+            );
+            assert_eq!(
+                invalid_path_reason(path, PathValidation::Strict),
+                strict,
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_detects_path_traversal_payloads() {
+        for path in [
+            "/static/%2e%2e/admin",
+            "/static/%2E%2E/admin",
+            "/static/.%2e/admin",
+            "/static/%252e%252e/admin",
+            "/static/..;/admin",
+            "/static/..;jsessionid=1/admin",
+            "/static/..\\admin",
+            "/static/..%5cadmin",
+            "/static/..%255cadmin",
+            "/static/..%2fadmin",
+            "/static/../admin",
+            "/static/%zz",
+        ] {
+            assert!(has_path_traversal(path), "{path}");
+        }
+        for path in [
+            "/static/app.js",
+            "/static/..data/a",
+            "/a;b/c",
+            "/files/a%20b",
+            "/files/100%2525",
+        ] {
+            assert!(!has_path_traversal(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_neutralizes_path_traversal() {
+        for (path, expected) in [
+            ("/static/%2e%2e/admin", Some("/admin")),
+            ("/static/%252e%252e/admin", Some("/admin")),
+            ("/static/..;/admin", Some("/admin")),
+            ("/static/..\\admin", Some("/admin")),
+            ("/static/..%2fadmin", None),
+            ("/static/..%5cadmin", None),
+        ] {
+            assert_eq!(
+                neutralize_path_traversal(path).as_deref(),
+                expected,
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_path_rejects_malformed_and_encoded_slashes() {
+        for path in ["/admin/..%2f", "/a%5c..", "/a%zz", "/a%2", "/a%"] {
+            assert_eq!(normalize_path(path), None, "{path}");
+        }
+    }
+}